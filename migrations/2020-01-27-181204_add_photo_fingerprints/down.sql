@@ -0,0 +1,5 @@
+drop index if exists idx_photos_fingerprint;
+
+alter table photos
+    drop column if exists file_modified,
+    drop column if exists file_size;
@@ -0,0 +1,11 @@
+-- Store a cheap fingerprint of each file next to its hash
+-- A file whose size and modification time have not changed since it was imported does not need to be hashed again,
+-- and a file that shows up at a new path with the same name and fingerprint is treated as moved rather than new.
+-- Existing rows are left null and are filled in by the next scan.
+alter table photos
+    add column file_size     bigint    default null
+        constraint valid_file_size
+            check ( file_size >= 0 ),
+    add column file_modified timestamp default null;
+
+create index idx_photos_fingerprint on photos (file_name, file_size, file_modified);
@@ -0,0 +1,4 @@
+drop index if exists idx_photos_file_hash;
+
+-- drop `duplicate_groups` table
+drop table if exists duplicate_groups;
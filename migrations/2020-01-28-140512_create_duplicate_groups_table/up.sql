@@ -0,0 +1,27 @@
+-- Add `duplicate_groups` table
+-- A scan no longer aborts when it finds files with the same hash. Instead every hash that appears more than once is
+-- recorded here so the copies can be reviewed and resolved later. The photos in a group are simply every photo with
+-- the group's hash.
+create table duplicate_groups
+(
+    id            serial                                      not null
+        constraint duplicate_groups_pk
+            primary key,
+    file_hash     varchar(255)                                not null
+        constraint unique_duplicate_group_hash
+            unique,
+    status        varchar(20)  default 'pending'              not null
+        constraint duplicate_group_status_values
+            check ( status = 'pending'
+                or status = 'resolved'
+                or status = 'intentional' ),
+    kept_photo_id int          default null,
+    date_created  timestamp    default CURRENT_TIMESTAMP      not null,
+    date_resolved timestamp    default null,
+    constraint duplicate_groups_photos_fk foreign key (kept_photo_id) references photos (id) on delete set null
+);
+
+create index idx_duplicate_groups_status on duplicate_groups (status);
+
+-- photos are looked up by hash when listing the members of a group
+create index if not exists idx_photos_file_hash on photos (file_hash);
@@ -0,0 +1,4 @@
+drop function if exists hamming_distance(bigint, bigint);
+
+alter table photos
+    drop column if exists perceptual_hash;
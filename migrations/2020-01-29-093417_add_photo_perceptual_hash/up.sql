@@ -0,0 +1,12 @@
+-- Store a perceptual hash of each image next to its exact hash
+-- `file_hash` only matches byte-for-byte copies. The perceptual hash (a 64 bit dHash) stays close for copies that have
+-- been re-encoded, resized or stripped of metadata, and the number of differing bits tells how similar two images are.
+-- Existing rows are left null and are filled in by the next scan. Formats that cannot be decoded stay null.
+alter table photos
+    add column perceptual_hash bigint default null;
+
+-- counts the bits that differ between two perceptual hashes
+create or replace function hamming_distance(a bigint, b bigint) returns int as
+$$
+select length(replace((a # b)::bit(64)::text, '0', ''));
+$$ language sql immutable strict;
@@ -0,0 +1,83 @@
+-- Add EXIF metadata to `photos`
+-- Scans read the EXIF block of every new or modified photo. The orientation is not stored on its own, it is mapped
+-- onto the existing `rotation` column instead. `exif_extracted` marks rows that have been read, so that photos
+-- imported before this migration are filled in by the next scan without re-reading photos that simply have no EXIF.
+alter table photos
+    add column date_taken     timestamp    default null,
+    add column camera_make    varchar(255) default null,
+    add column camera_model   varchar(255) default null,
+    add column lens_model     varchar(255) default null,
+    add column exposure_time  varchar(20)  default null,
+    add column f_number       real         default null,
+    add column focal_length   real         default null,
+    add column iso            integer      default null,
+    add column exif_extracted bool         default false not null;
+
+create index idx_photos_date_taken on photos (date_taken);
+create index idx_photos_camera on photos (lower(camera_make), lower(camera_model));
+
+-- expose the metadata through `photos_all`
+-- new columns can only be appended, otherwise `create or replace` refuses to replace the view
+create or replace view photos_all as
+select id,
+       file_path,
+       replace(file_path, file_name, '')                                folder,
+       file_name,
+       file_hash,
+       rating,
+       date_created,
+       date_updated,
+       last_viewed,
+       original_width,
+       original_height,
+       calculate_aspect_ratio(original_width, original_height)          aspect_ratio,
+       case
+           when original_width::decimal / nullif(original_height::decimal, 0) < 1.0 then 'Portrait'
+           when original_width::decimal / nullif(original_height::decimal, 0) > 1.0 then 'Landscape'
+           when original_width::decimal / nullif(original_height::decimal, 0) = 1.0 then 'Square'
+           else 'N/A'
+           end                                                          orientation,
+       rotation,
+       ineligible_for_wallpaper,
+       anonymous_entities,
+       case
+           when file_path like '%/Entities/%'
+               or file_path like '%/Suicide Girls/%'
+               or file_path like '%/Usernames/%'
+               or file_path like '%/XXX/%'
+               then
+               case
+                   when file_path like '%/_Favs/%'
+                       then strip_alt_names((regexp_split_to_array(file_path, '/'))[6])
+                   else strip_alt_names((regexp_split_to_array(file_path, '/'))[5]) end
+           else 'Anonymous' end                                         suggested_entity_name,
+       (file_hash || '.' || (regexp_matches(file_name, '\.(\w+)$'))[1]) wallpaper_file_name,
+       e.entities,
+       t.tags,
+       w.wallpapers,
+       date_taken,
+       camera_make,
+       camera_model,
+       lens_model,
+       exposure_time,
+       f_number,
+       focal_length,
+       iso
+from photos p
+         LEFT JOIN (
+    select pe.photo_id as id, array_agg(e.entity_name) as entities
+    from photo_entity pe
+             JOIN entity e on pe.entity_id = e.id
+    group by pe.photo_id) e using (id)
+         LEFT JOIN (
+    SELECT pt.photo_id as id, array_agg(t.tag_name) as tags
+    FROM photo_tag pt
+             JOIN tags t on pt.tag_id = t.id
+    GROUP BY pt.photo_id
+) t using (id)
+         LEFT JOIN (
+    SELECT pw.photo_id as id, array_agg(ws.name) as wallpapers
+    FROM photo_wallpaper pw
+             JOIN wallpaper_sizes ws on pw.wallpaper_size_id = ws.id
+    GROUP BY pw.photo_id
+) w using (id);
@@ -0,0 +1,129 @@
+-- Add `libraries` table
+-- Photos are no longer assumed to live under a single `/photos` directory. Each library is a named root on disk with
+-- its own scan settings and the URL prefix its files are served under. `/photos` becomes the default library so that
+-- existing media urls keep working.
+create table libraries
+(
+    id            serial                                    not null
+        constraint libraries_pk
+            primary key,
+    name          varchar(50)                               not null,
+    root_path     varchar(1000)                             not null,
+    media_prefix  varchar(100)                              not null,
+    scan_enabled  bool         default true                 not null,
+    watch_enabled bool         default true                 not null,
+    date_created  timestamp    default CURRENT_TIMESTAMP    not null
+);
+
+create unique index idx_unique_library_names on libraries (lower(name));
+create unique index idx_unique_library_roots on libraries (root_path);
+create unique index idx_unique_library_media_prefixes on libraries (media_prefix);
+
+insert into libraries (name, root_path, media_prefix)
+values ('Photos', '/photos', 'media');
+
+-- returns the library whose root contains `path`. Roots may be nested, in which case the deepest one wins.
+create or replace function find_library(path text) returns int as
+$$
+select id
+from libraries
+where left(path, length(root_path) + 1) = root_path || '/'
+order by length(root_path) desc
+limit 1;
+$$ language sql stable strict;
+
+alter table photos
+    add column library_id int default null,
+    add constraint photos_library_fk foreign key (library_id) references libraries (id) on delete set null;
+
+create index idx_photos_library_id on photos (library_id);
+
+update photos
+set library_id = find_library(file_path);
+
+-- keep `library_id` in sync with `file_path`, whichever query inserts or moves the photo
+create or replace function set_photo_library() returns trigger as
+$$
+begin
+    new.library_id := find_library(new.file_path);
+    return new;
+end;
+$$ language plpgsql;
+
+create trigger photos_set_library
+    before insert or update of file_path
+    on photos
+    for each row
+execute procedure set_photo_library();
+
+-- expose the library through `photos_all`, media urls are built relative to its root
+create or replace view photos_all as
+select id,
+       file_path,
+       replace(file_path, file_name, '')                                folder,
+       file_name,
+       file_hash,
+       rating,
+       date_created,
+       date_updated,
+       last_viewed,
+       original_width,
+       original_height,
+       calculate_aspect_ratio(original_width, original_height)          aspect_ratio,
+       case
+           when original_width::decimal / nullif(original_height::decimal, 0) < 1.0 then 'Portrait'
+           when original_width::decimal / nullif(original_height::decimal, 0) > 1.0 then 'Landscape'
+           when original_width::decimal / nullif(original_height::decimal, 0) = 1.0 then 'Square'
+           else 'N/A'
+           end                                                          orientation,
+       rotation,
+       ineligible_for_wallpaper,
+       anonymous_entities,
+       case
+           when file_path like '%/Entities/%'
+               or file_path like '%/Suicide Girls/%'
+               or file_path like '%/Usernames/%'
+               or file_path like '%/XXX/%'
+               then
+               case
+                   when file_path like '%/_Favs/%'
+                       then strip_alt_names((regexp_split_to_array(file_path, '/'))[6])
+                   else strip_alt_names((regexp_split_to_array(file_path, '/'))[5]) end
+           else 'Anonymous' end                                         suggested_entity_name,
+       (file_hash || '.' || (regexp_matches(file_name, '\.(\w+)$'))[1]) wallpaper_file_name,
+       e.entities,
+       t.tags,
+       w.wallpapers,
+       date_taken,
+       camera_make,
+       camera_model,
+       lens_model,
+       exposure_time,
+       f_number,
+       focal_length,
+       iso,
+       library_id,
+       library_root,
+       media_prefix
+from photos p
+         LEFT JOIN (
+    select pe.photo_id as id, array_agg(e.entity_name) as entities
+    from photo_entity pe
+             JOIN entity e on pe.entity_id = e.id
+    group by pe.photo_id) e using (id)
+         LEFT JOIN (
+    SELECT pt.photo_id as id, array_agg(t.tag_name) as tags
+    FROM photo_tag pt
+             JOIN tags t on pt.tag_id = t.id
+    GROUP BY pt.photo_id
+) t using (id)
+         LEFT JOIN (
+    SELECT pw.photo_id as id, array_agg(ws.name) as wallpapers
+    FROM photo_wallpaper pw
+             JOIN wallpaper_sizes ws on pw.wallpaper_size_id = ws.id
+    GROUP BY pw.photo_id
+) w using (id)
+         LEFT JOIN (
+    SELECT l.id as library_id, l.root_path as library_root, l.media_prefix
+    FROM libraries l
+) l using (library_id);
@@ -0,0 +1,18 @@
+-- Add `scan_settings` table
+-- Holds the rules that decide which files a scan picks up. There is only ever one row. Extensions are compared without
+-- the leading dot and case-insensitively. Patterns are globs matched against the full path and the file name, an empty
+-- list of include patterns includes everything. Per directory rules live in `.scarlettignore` files on disk instead.
+create table scan_settings
+(
+    id               int    default 1  not null
+        constraint scan_settings_pk
+            primary key
+        constraint scan_settings_single_row
+            check ( id = 1 ),
+    extensions       text[]            not null,
+    include_patterns text[] default '{}' not null,
+    exclude_patterns text[] default '{}' not null
+);
+
+insert into scan_settings (extensions)
+values ('{jpg,jpeg,png,gif,bmp,ico,tiff,webp,pnm,heic}');
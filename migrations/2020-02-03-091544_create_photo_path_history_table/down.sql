@@ -0,0 +1,5 @@
+drop trigger if exists photos_record_path on photos;
+drop function if exists record_photo_path();
+
+-- drop `photo_path_history` table
+drop table if exists photo_path_history;
@@ -0,0 +1,40 @@
+-- Add `photo_path_history` table
+-- Every path a photo has been stored at, oldest first. Rows are written by a trigger, so that scans, the file watcher
+-- and the api all keep the history no matter which query moves the photo. The first row of a photo has no old path.
+create table photo_path_history
+(
+    id           serial                                  not null
+        constraint photo_path_history_pk
+            primary key,
+    photo_id     int                                     not null,
+    old_path     varchar(1000) default null,
+    new_path     varchar(1000)                           not null,
+    date_changed timestamp     default CURRENT_TIMESTAMP not null,
+    constraint photo_path_history_photos_fk foreign key (photo_id) references photos (id) on delete cascade
+);
+
+create index idx_photo_path_history_photo_id on photo_path_history (photo_id, date_changed);
+
+-- existing photos start their history where they are now
+insert into photo_path_history (photo_id, old_path, new_path, date_changed)
+select id, null, file_path, date_created
+from photos;
+
+create or replace function record_photo_path() returns trigger as
+$$
+begin
+    if tg_op = 'INSERT' then
+        insert into photo_path_history (photo_id, old_path, new_path) values (new.id, null, new.file_path);
+    elsif new.file_path is distinct from old.file_path then
+        insert into photo_path_history (photo_id, old_path, new_path) values (new.id, old.file_path, new.file_path);
+    end if;
+
+    return null;
+end;
+$$ language plpgsql;
+
+create trigger photos_record_path
+    after insert or update of file_path
+    on photos
+    for each row
+execute procedure record_photo_path();
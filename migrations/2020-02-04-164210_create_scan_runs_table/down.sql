@@ -0,0 +1,2 @@
+-- drop `scan_runs` table
+drop table if exists scan_runs;
@@ -0,0 +1,34 @@
+-- Add `scan_runs` table
+-- Every scan, dry runs included, is recorded along with what it changed so that changes to the library can be audited
+-- later. A null folder means every library was scanned. For dry runs the file lists hold the planned changes.
+create table scan_runs
+(
+    id               serial                                   not null
+        constraint scan_runs_pk
+            primary key,
+    folder           varchar(1000) default null,
+    dry_run          bool          default false              not null,
+    status           varchar(20)   default 'running'          not null
+        constraint scan_run_status_values
+            check ( status = 'running'
+                or status = 'completed'
+                or status = 'failed'
+                or status = 'cancelled' ),
+    date_started     timestamp     default CURRENT_TIMESTAMP  not null,
+    date_finished    timestamp     default null,
+    new_photos       int           default 0                  not null,
+    existing_photos  int           default 0                  not null,
+    updated_photos   int           default 0                  not null,
+    deleted_photos   int           default 0                  not null,
+    hashed_photos    int           default 0                  not null,
+    duplicate_groups int           default 0                  not null,
+    error            text          default null,
+    inserted_files   text[]        default '{}'               not null,
+    moved_files      jsonb         default '[]'               not null,
+    updated_files    text[]        default '{}'               not null,
+    deleted_files    text[]        default '{}'               not null,
+    excluded_files   jsonb         default '{}'               not null
+);
+
+create index idx_scan_runs_date_started on scan_runs (date_started desc);
+create index idx_scan_runs_status on scan_runs (status);
@@ -0,0 +1,153 @@
+-- Add trash columns to `photos`
+-- Deleting a photo moves its file into the trash directory and only marks the row, so that a restore brings back its
+-- tags, entities and wallpapers. While trashed, `file_path` points into the trash and `trashed_from` holds the path
+-- the file is restored to.
+alter table photos
+    add column date_trashed timestamp     default null,
+    add column trashed_from varchar(1000) default null;
+
+create index idx_photos_date_trashed on photos (date_trashed) where date_trashed is not null;
+
+-- trashed photos are only visible through the trash
+create or replace view photos_all as
+select id,
+       file_path,
+       replace(file_path, file_name, '')                                folder,
+       file_name,
+       file_hash,
+       rating,
+       date_created,
+       date_updated,
+       last_viewed,
+       original_width,
+       original_height,
+       calculate_aspect_ratio(original_width, original_height)          aspect_ratio,
+       case
+           when original_width::decimal / nullif(original_height::decimal, 0) < 1.0 then 'Portrait'
+           when original_width::decimal / nullif(original_height::decimal, 0) > 1.0 then 'Landscape'
+           when original_width::decimal / nullif(original_height::decimal, 0) = 1.0 then 'Square'
+           else 'N/A'
+           end                                                          orientation,
+       rotation,
+       ineligible_for_wallpaper,
+       anonymous_entities,
+       case
+           when file_path like '%/Entities/%'
+               or file_path like '%/Suicide Girls/%'
+               or file_path like '%/Usernames/%'
+               or file_path like '%/XXX/%'
+               then
+               case
+                   when file_path like '%/_Favs/%'
+                       then strip_alt_names((regexp_split_to_array(file_path, '/'))[6])
+                   else strip_alt_names((regexp_split_to_array(file_path, '/'))[5]) end
+           else 'Anonymous' end                                         suggested_entity_name,
+       (file_hash || '.' || (regexp_matches(file_name, '\.(\w+)$'))[1]) wallpaper_file_name,
+       e.entities,
+       t.tags,
+       w.wallpapers,
+       date_taken,
+       camera_make,
+       camera_model,
+       lens_model,
+       exposure_time,
+       f_number,
+       focal_length,
+       iso,
+       library_id,
+       library_root,
+       media_prefix
+from photos p
+         LEFT JOIN (
+    select pe.photo_id as id, array_agg(e.entity_name) as entities
+    from photo_entity pe
+             JOIN entity e on pe.entity_id = e.id
+    group by pe.photo_id) e using (id)
+         LEFT JOIN (
+    SELECT pt.photo_id as id, array_agg(t.tag_name) as tags
+    FROM photo_tag pt
+             JOIN tags t on pt.tag_id = t.id
+    GROUP BY pt.photo_id
+) t using (id)
+         LEFT JOIN (
+    SELECT pw.photo_id as id, array_agg(ws.name) as wallpapers
+    FROM photo_wallpaper pw
+             JOIN wallpaper_sizes ws on pw.wallpaper_size_id = ws.id
+    GROUP BY pw.photo_id
+) w using (id)
+         LEFT JOIN (
+    SELECT l.id as library_id, l.root_path as library_root, l.media_prefix
+    FROM libraries l
+) l using (library_id)
+where p.date_trashed is null;
+
+-- and are left out of the stats
+CREATE OR REPLACE VIEW photos_stats AS
+SELECT UNRATED,
+       (UNRATED / TOTAL_KEPT::decimal) * 100              UNRATED_PERCENT,
+       HIDDEN,
+       (HIDDEN / TOTAL_KEPT::decimal) * 100               HIDDEN_PERCENT,
+       NEUTRAL,
+       (NEUTRAL / TOTAL_KEPT::decimal) * 100              NEUTRAL_PERCENT,
+       WALLPAPER_CANDIDATES,
+       (WALLPAPER_CANDIDATES / TOTAL_KEPT::decimal) * 100 WC_PERCENT,
+       FAVORITES,
+       (FAVORITES / TOTAL_KEPT::decimal) * 100            FAVORITES_PERCENT,
+       WITH_ENTITIES,
+       (WITH_ENTITIES / TOTAL_KEPT::decimal) * 100        WITH_ENTITIES_PERCENT,
+       WITH_TAGS,
+       (WITH_TAGS / TOTAL_KEPT::decimal) * 100            WITH_TAGS_PERCENT,
+       WITH_WALLPAPER,
+       (WITH_WALLPAPER / TOTAL_KEPT::decimal) * 100       WITH_WALLPAPER_PERCENT,
+       TOTAL_KEPT,
+       (TOTAL_KEPT / TOTAL::decimal) * 100                KEPT_PERCENT,
+       PENDING_DELETE,
+       (PENDING_DELETE / TOTAL::decimal) * 100            PENDING_DELETE_PERCENT,
+       TOTAL
+FROM (
+         SELECT (SELECT COUNT(*)
+                 from photos
+                 where date_trashed is null
+                   and rating = 0)  UNRATED,
+                (SELECT NULLIF(COUNT(*), 0)
+                 from photos
+                 where date_trashed is null
+                   and rating = 1)  PENDING_DELETE,
+                (SELECT NULLIF(COUNT(*), 0)
+                 from photos
+                 where date_trashed is null
+                   and rating = 2)  HIDDEN,
+                (SELECT NULLIF(COUNT(*), 0)
+                 from photos
+                 where date_trashed is null
+                   and rating = 3)  NEUTRAL,
+                (SELECT NULLIF(COUNT(*), 0)
+                 from photos
+                 where date_trashed is null
+                   and rating = 4)  WALLPAPER_CANDIDATES,
+                (SELECT NULLIF(COUNT(*), 0)
+                 from photos
+                 where date_trashed is null
+                   and rating = 5)  FAVORITES,
+                (SELECT NULLIF(COUNT(DISTINCT pe.photo_id), 0)
+                 FROM photos p
+                          INNER JOIN photo_entity pe on p.id = pe.photo_id
+                 WHERE date_trashed is null
+                   AND rating <> 1) WITH_ENTITIES,
+                (SELECT NULLIF(COUNT(DISTINCT pt.photo_id), 0)
+                 FROM photos p
+                          inner join photo_tag pt on p.id = pt.photo_id
+                 WHERE date_trashed is null
+                   AND rating <> 1) WITH_TAGS,
+                (SELECT NULLIF(COUNT(DISTINCT pw.photo_id), 0)
+                 FROM photos p
+                          INNER JOIN photo_wallpaper pw on p.id = pw.photo_id
+                 WHERE date_trashed is null
+                   AND rating <> 1) WITH_WALLPAPER,
+                (SELECT NULLIF(COUNT(*), 0)
+                 from photos
+                 where date_trashed is null
+                   and rating <> 1) TOTAL_KEPT,
+                (SELECT NULLIF(COUNT(*), 0)
+                 from photos
+                 where date_trashed is null) TOTAL) s;
@@ -0,0 +1,5 @@
+-- drop `scan_issues` table
+alter table scan_runs
+    drop column if exists quarantined_files;
+
+drop table if exists scan_issues;
@@ -0,0 +1,25 @@
+-- Add `scan_issues` table
+-- Files that fail validation during a scan are quarantined here instead of being imported. Every file has at most one
+-- issue, which is updated whenever a scan runs into it again. Scans skip files whose issue was ignored.
+create table scan_issues
+(
+    id            serial                                   not null
+        constraint scan_issues_pk
+            primary key,
+    file_path     varchar(1000)                            not null
+        constraint unique_scan_issue_file_path
+            unique,
+    kind          varchar(50)                              not null,
+    error         text                                     not null,
+    status        varchar(20)   default 'pending'          not null
+        constraint scan_issue_status_values
+            check ( status = 'pending'
+                or status = 'ignored' ),
+    date_detected timestamp     default CURRENT_TIMESTAMP  not null,
+    date_updated  timestamp     default CURRENT_TIMESTAMP  not null
+);
+
+create index idx_scan_issues_status on scan_issues (status);
+
+alter table scan_runs
+    add column quarantined_files int default 0 not null;
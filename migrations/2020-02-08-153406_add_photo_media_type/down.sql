@@ -0,0 +1,136 @@
+-- remove media type columns from `photos`
+-- columns cannot be dropped from a view with `create or replace`, so `photos_all` and the views that depend on it
+-- are dropped and recreated as they were before this migration
+drop view if exists directory_tree;
+drop view if exists tag_stats;
+drop view if exists entity_stats;
+drop view if exists photos_all;
+
+update scan_settings
+set extensions = array(select extension
+                       from unnest(extensions) extension
+                       where extension <> all ('{mp4,m4v,mov,webm}'::text[]));
+
+drop index if exists idx_photos_media_type;
+
+alter table photos
+    drop column if exists codec,
+    drop column if exists duration,
+    drop column if exists media_type;
+
+create or replace view photos_all as
+select id,
+       file_path,
+       replace(file_path, file_name, '')                                folder,
+       file_name,
+       file_hash,
+       rating,
+       date_created,
+       date_updated,
+       last_viewed,
+       original_width,
+       original_height,
+       calculate_aspect_ratio(original_width, original_height)          aspect_ratio,
+       case
+           when original_width::decimal / nullif(original_height::decimal, 0) < 1.0 then 'Portrait'
+           when original_width::decimal / nullif(original_height::decimal, 0) > 1.0 then 'Landscape'
+           when original_width::decimal / nullif(original_height::decimal, 0) = 1.0 then 'Square'
+           else 'N/A'
+           end                                                          orientation,
+       rotation,
+       ineligible_for_wallpaper,
+       anonymous_entities,
+       case
+           when file_path like '%/Entities/%'
+               or file_path like '%/Suicide Girls/%'
+               or file_path like '%/Usernames/%'
+               or file_path like '%/XXX/%'
+               then
+               case
+                   when file_path like '%/_Favs/%'
+                       then strip_alt_names((regexp_split_to_array(file_path, '/'))[6])
+                   else strip_alt_names((regexp_split_to_array(file_path, '/'))[5]) end
+           else 'Anonymous' end                                         suggested_entity_name,
+       (file_hash || '.' || (regexp_matches(file_name, '\.(\w+)$'))[1]) wallpaper_file_name,
+       e.entities,
+       t.tags,
+       w.wallpapers,
+       date_taken,
+       camera_make,
+       camera_model,
+       lens_model,
+       exposure_time,
+       f_number,
+       focal_length,
+       iso,
+       library_id,
+       library_root,
+       media_prefix
+from photos p
+         LEFT JOIN (
+    select pe.photo_id as id, array_agg(e.entity_name) as entities
+    from photo_entity pe
+             JOIN entity e on pe.entity_id = e.id
+    group by pe.photo_id) e using (id)
+         LEFT JOIN (
+    SELECT pt.photo_id as id, array_agg(t.tag_name) as tags
+    FROM photo_tag pt
+             JOIN tags t on pt.tag_id = t.id
+    GROUP BY pt.photo_id
+) t using (id)
+         LEFT JOIN (
+    SELECT pw.photo_id as id, array_agg(ws.name) as wallpapers
+    FROM photo_wallpaper pw
+             JOIN wallpaper_sizes ws on pw.wallpaper_size_id = ws.id
+    GROUP BY pw.photo_id
+) w using (id)
+         LEFT JOIN (
+    SELECT l.id as library_id, l.root_path as library_root, l.media_prefix
+    FROM libraries l
+) l using (library_id)
+where p.date_trashed is null;
+
+create or replace view directory_tree as
+with data as (
+    select array_to_json(array_agg(folder)) as data
+    from (select folder
+          from photos_all
+          group by folder
+          order by lower(folder)) s
+)
+select get_tree(data) directory_tree
+from data;
+
+create or replace view tag_stats as
+select tag_name,
+       photos_with_tag,
+       (photos_with_tag::decimal / photos_with_tags::decimal) * 100                  percentage_with_tag,
+       (photos_with_tag::decimal / (select count(*)::decimal from photos_all)) * 100 percentage_total
+from (select t.tag_name,
+             (select nullif(count(pt.photo_id), 0)
+              from tags t2
+                       left join photo_tag pt on t2.id = pt.tag_id
+              where t2.id = t.id)                                      photos_with_tag,
+             (select count(distinct photo_id)
+              from tags t3
+                       inner join photo_tag pt2 on t3.id = pt2.tag_id) photos_with_tags
+      from tags t) s
+order by photos_with_tag desc, tag_name;
+
+create or replace view entity_stats as
+select entity_name,
+       photos_with_entity,
+       (photos_with_entity::decimal / photos_with_entities::decimal) * 100              percentage_with_entity,
+       (photos_with_entity::decimal / (select count(*)::decimal from photos_all)) * 100 percentage_total
+from (select se.entity_name,
+             se.sort_name,
+             (select nullif(count(pe.photo_id), 0)
+              from sorted_entity se2
+                       left join photo_entity pe on se2.id = pe.entity_id
+              where se2.id = se.id)              photos_with_entity,
+             (select count(distinct photo_id)
+              from sorted_entity se3
+                       inner join photo_entity pe on se3.id = pe.entity_id
+                       inner join photos_all pa on pa.id = pe.photo_id
+              where anonymous_entities is false) photos_with_entities
+      from sorted_entity se) s;
@@ -0,0 +1,95 @@
+-- Add media type columns to `photos`
+-- Video clips are stored alongside photos so that ratings, tags and entities work the same for both. Width, height,
+-- duration and codec are read from the container when the clip is scanned. `duration` is in seconds, `duration` and
+-- `codec` stay null for images. Clips are never wallpaper candidates.
+alter table photos
+    add column media_type varchar(10)      default 'image' not null
+        constraint photos_media_type_check
+            check ( media_type in ('image', 'video') ),
+    add column duration   double precision default null,
+    add column codec      varchar(50)      default null;
+
+create index idx_photos_media_type on photos (media_type);
+
+-- expose the columns through `photos_all`
+create or replace view photos_all as
+select id,
+       file_path,
+       replace(file_path, file_name, '')                                folder,
+       file_name,
+       file_hash,
+       rating,
+       date_created,
+       date_updated,
+       last_viewed,
+       original_width,
+       original_height,
+       calculate_aspect_ratio(original_width, original_height)          aspect_ratio,
+       case
+           when original_width::decimal / nullif(original_height::decimal, 0) < 1.0 then 'Portrait'
+           when original_width::decimal / nullif(original_height::decimal, 0) > 1.0 then 'Landscape'
+           when original_width::decimal / nullif(original_height::decimal, 0) = 1.0 then 'Square'
+           else 'N/A'
+           end                                                          orientation,
+       rotation,
+       ineligible_for_wallpaper,
+       anonymous_entities,
+       case
+           when file_path like '%/Entities/%'
+               or file_path like '%/Suicide Girls/%'
+               or file_path like '%/Usernames/%'
+               or file_path like '%/XXX/%'
+               then
+               case
+                   when file_path like '%/_Favs/%'
+                       then strip_alt_names((regexp_split_to_array(file_path, '/'))[6])
+                   else strip_alt_names((regexp_split_to_array(file_path, '/'))[5]) end
+           else 'Anonymous' end                                         suggested_entity_name,
+       -- wallpapers are always rendered as JPEG, see `files::wallpapers`
+       (file_hash || '.jpg')                                            wallpaper_file_name,
+       e.entities,
+       t.tags,
+       w.wallpapers,
+       date_taken,
+       camera_make,
+       camera_model,
+       lens_model,
+       exposure_time,
+       f_number,
+       focal_length,
+       iso,
+       library_id,
+       library_root,
+       media_prefix,
+       media_type,
+       duration,
+       codec
+from photos p
+         LEFT JOIN (
+    select pe.photo_id as id, array_agg(e.entity_name) as entities
+    from photo_entity pe
+             JOIN entity e on pe.entity_id = e.id
+    group by pe.photo_id) e using (id)
+         LEFT JOIN (
+    SELECT pt.photo_id as id, array_agg(t.tag_name) as tags
+    FROM photo_tag pt
+             JOIN tags t on pt.tag_id = t.id
+    GROUP BY pt.photo_id
+) t using (id)
+         LEFT JOIN (
+    SELECT pw.photo_id as id, array_agg(ws.name) as wallpapers
+    FROM photo_wallpaper pw
+             JOIN wallpaper_sizes ws on pw.wallpaper_size_id = ws.id
+    GROUP BY pw.photo_id
+) w using (id)
+         LEFT JOIN (
+    SELECT l.id as library_id, l.root_path as library_root, l.media_prefix
+    FROM libraries l
+) l using (library_id)
+where p.date_trashed is null;
+
+-- pick up clips on the next scan
+update scan_settings
+set extensions = extensions || array(select extension
+                                     from unnest('{mp4,m4v,mov,webm}'::text[]) extension
+                                     where extension <> all (extensions));
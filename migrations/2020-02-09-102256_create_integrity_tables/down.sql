@@ -0,0 +1,3 @@
+-- drop `integrity_issues` and `integrity_runs` tables
+drop table if exists integrity_issues;
+drop table if exists integrity_runs;
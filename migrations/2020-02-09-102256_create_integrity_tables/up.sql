@@ -0,0 +1,49 @@
+-- Add `integrity_runs` table
+-- Every pass of the integrity verification job re-hashes the whole library and compares the result with the stored
+-- `file_hash`. Photos are verified in id order and `last_photo_id` is updated along the way, so that a run interrupted by
+-- a restart picks up where it left off.
+create table integrity_runs
+(
+    id               serial                                   not null
+        constraint integrity_runs_pk
+            primary key,
+    status           varchar(20)   default 'running'          not null
+        constraint integrity_run_status_values
+            check ( status = 'running'
+                or status = 'completed' ),
+    date_started     timestamp     default CURRENT_TIMESTAMP  not null,
+    date_finished    timestamp     default null,
+    last_photo_id    int           default 0                  not null,
+    checked_photos   int           default 0                  not null,
+    mismatched_files int           default 0                  not null,
+    missing_files    int           default 0                  not null,
+    unreadable_files int           default 0                  not null
+);
+
+create index idx_integrity_runs_date_started on integrity_runs (date_started desc);
+
+-- Add `integrity_issues` table
+-- Files a run found to be corrupt, missing or unreadable. The path and hash are copied from the photo at the time, so
+-- the report still makes sense after the photo has been fixed or deleted.
+create table integrity_issues
+(
+    id            serial                                   not null
+        constraint integrity_issues_pk
+            primary key,
+    run_id        int                                      not null,
+    photo_id      int,
+    file_path     varchar(1000)                            not null,
+    kind          varchar(20)                              not null
+        constraint integrity_issue_kind_values
+            check ( kind = 'mismatch'
+                or kind = 'missing'
+                or kind = 'unreadable' ),
+    expected_hash varchar(255)                             not null,
+    actual_hash   varchar(255)  default null,
+    error         text          default null,
+    date_detected timestamp     default CURRENT_TIMESTAMP  not null,
+    constraint integrity_issues_runs_fk foreign key (run_id) references integrity_runs (id) on delete cascade,
+    constraint integrity_issues_photos_fk foreign key (photo_id) references photos (id) on delete set null
+);
+
+create index idx_integrity_issues_run_id on integrity_issues (run_id);
@@ -0,0 +1,7 @@
+-- drop `photo_crops` table and focal point columns from `photos`
+drop table if exists photo_crops;
+
+alter table photos
+    drop constraint if exists photos_focal_point_check,
+    drop column if exists focal_y,
+    drop column if exists focal_x;
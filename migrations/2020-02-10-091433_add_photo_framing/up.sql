@@ -0,0 +1,41 @@
+-- Add focal point columns to `photos`
+-- The focal point is the spot that has to stay in view when a photo is cropped, given as fractions of the width and
+-- height of the upright photo. Wallpapers and cover-fit renditions are centered on it, or on the center of the photo
+-- while it is not set.
+alter table photos
+    add column focal_x real default null
+        constraint photos_focal_x_check
+            check ( focal_x between 0 and 1 ),
+    add column focal_y real default null
+        constraint photos_focal_y_check
+            check ( focal_y between 0 and 1 ),
+    add constraint photos_focal_point_check
+        check ( (focal_x is null) = (focal_y is null) );
+
+-- Add `photo_crops` table
+-- A crop rectangle chosen by hand for the wallpaper of a photo in one wallpaper size, it takes precedence over the focal
+-- point. Like the focal point, the rectangle is given as fractions of the upright photo.
+create table photo_crops
+(
+    id                serial                                 not null
+        constraint photo_crops_pk
+            primary key,
+    photo_id          int                                    not null,
+    wallpaper_size_id int                                    not null,
+    x                 real                                   not null,
+    y                 real                                   not null,
+    width             real                                   not null,
+    height            real                                   not null,
+    date_updated      timestamp default CURRENT_TIMESTAMP    not null,
+    -- the rectangle has to lie within the photo, with a little slack for fractions that do not add up to exactly 1
+    constraint photo_crops_bounds_check
+        check ( x >= 0 and y >= 0 and x < 1 and y < 1 and width > 0 and height > 0 and
+                x + width <= 1.000001 and y + height <= 1.000001 ),
+    constraint photo_crops_photos_fk foreign key (photo_id) references photos (id) on delete cascade,
+    constraint photo_crops_wallpaper_sizes_fk foreign key (wallpaper_size_id) references wallpaper_sizes (id)
+        on delete cascade
+);
+
+-- ensure there is at most one crop per photo and wallpaper size
+create unique index idx_unique_photo_crop
+    on photo_crops (photo_id, wallpaper_size_id);
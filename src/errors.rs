@@ -1,9 +1,10 @@
+use actix_web::error::BlockingError;
 use actix_web::{error::ResponseError, HttpResponse};
 use deadpool_postgres::PoolError;
+use serde::Serialize;
 use thiserror::Error;
 use tokio_postgres::error::Error as TpgError;
 
-use crate::files::photos::DuplicatePhoto;
 use crate::responses::api_response::ApiResponse;
 
 #[derive(Debug, Error)]
@@ -12,12 +13,18 @@ pub enum ServiceError {
     #[error("Internal Server Error")]
     InternalServerError,
 
-    #[error("Duplicate files detected")]
-    DuplicateFileError(Vec<DuplicatePhoto>),
-
     #[error("Bad request: {0}")]
     BadRequest(String),
 
+    #[error("Validation failed")]
+    ValidationFailed(Vec<FieldError>),
+
+    #[error("Not found: {0}")]
+    NotFound(String),
+
+    #[error("Scan was cancelled")]
+    ScanCancelled,
+
     #[error("IO Error: {0}")]
     IOError(std::io::Error),
 
@@ -28,12 +35,37 @@ pub enum ServiceError {
     TpgError(TpgError),
 }
 
+/// Why the value of a request field was rejected
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: &str) -> Self {
+        FieldError {
+            field: field.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
 impl From<std::io::Error> for ServiceError {
     fn from(error: std::io::Error) -> Self {
         Self::IOError(error)
     }
 }
 
+impl From<BlockingError<ServiceError>> for ServiceError {
+    fn from(error: BlockingError<ServiceError>) -> Self {
+        match error {
+            BlockingError::Error(error) => error,
+            BlockingError::Canceled => Self::InternalServerError,
+        }
+    }
+}
+
 impl From<PoolError> for ServiceError {
     fn from(error: PoolError) -> Self {
         Self::PoolError(error)
@@ -49,13 +81,13 @@ impl From<TpgError> for ServiceError {
 impl ResponseError for ServiceError {
     fn error_response(&self) -> HttpResponse {
         match self {
-            ServiceError::InternalServerError => {
+            // cancelled scans end the job, they are never returned from a handler
+            ServiceError::InternalServerError | ServiceError::ScanCancelled => {
                 ApiResponse::error("Internal server error. Please try again later")
             }
-            ServiceError::DuplicateFileError(ref duplicate_photos) => {
-                ApiResponse::error(duplicate_photos)
-            }
             ServiceError::BadRequest(ref message) => ApiResponse::bad_request(message),
+            ServiceError::ValidationFailed(ref errors) => ApiResponse::bad_request(errors),
+            ServiceError::NotFound(ref message) => ApiResponse::not_found(message),
             ServiceError::IOError(ref error) => ApiResponse::error(format!("{}", error)),
             ServiceError::PoolError(ref error) => {
                 ApiResponse::error(format!("Unable to connect to the database: {}", error))
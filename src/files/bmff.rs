@@ -0,0 +1,272 @@
+use std::convert::TryInto;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Largest box read into memory. `meta` boxes are a few kilobytes and `moov` boxes a few megabytes
+/// even for long clips, anything larger is more likely a corrupt size.
+const MAX_BOX_SIZE: u64 = 64 * 1024 * 1024;
+
+// ISO BASE MEDIA FILE FORMAT **********************************************************************
+
+/// A box (a.k.a. atom) of the ISO base media file format, the container shared by HEIF images and
+/// MP4/MOV videos. `data` is the body of the box, without its size and type header.
+pub struct BmffBox<'a> {
+    pub box_type: [u8; 4],
+    pub data: &'a [u8],
+}
+
+/// Iterates over boxes laid out back to back, such as the children of a container box. Iteration
+/// stops at the first box whose size does not fit in the remaining data.
+pub struct Boxes<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for Boxes<'a> {
+    type Item = BmffBox<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (header_size, box_size) = match parse_header(self.data) {
+            Some(header) => header,
+            None => {
+                self.data = &[];
+                return None;
+            }
+        };
+
+        let box_type = self.data[4..8].try_into().ok()?;
+        let data = &self.data[header_size..box_size];
+        self.data = &self.data[box_size..];
+
+        Some(BmffBox { box_type, data })
+    }
+}
+
+pub fn boxes(data: &[u8]) -> Boxes<'_> {
+    Boxes { data }
+}
+
+/// Returns the body of the first child box of the given type
+pub fn find_box<'a>(data: &'a [u8], box_type: &[u8; 4]) -> Option<&'a [u8]> {
+    boxes(data)
+        .find(|child| &child.box_type == box_type)
+        .map(|child| child.data)
+}
+
+/// Follows `path` down through nested container boxes and returns the body of the last one, e.g.
+/// `[b"mdia", b"minf", b"stbl"]` inside a `trak`. The first matching child is taken at every level.
+pub fn find_nested_box<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    path.iter()
+        .try_fold(data, |parent, box_type| find_box(parent, box_type))
+}
+
+/// Scans the top level boxes of a file and reads the body of the first one of the given type into
+/// memory. Other boxes, such as the (potentially huge) media data, are skipped over without being
+/// read. Scanning stops at a box that claims to be larger than the rest of the file, and a box
+/// larger than `MAX_BOX_SIZE` is not read.
+pub fn read_top_level_box<R: Read + Seek>(
+    reader: &mut R,
+    box_type: &[u8; 4],
+) -> io::Result<Option<Vec<u8>>> {
+    let end = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    loop {
+        let mut header = [0; 16];
+        if read_up_to(reader, &mut header[..8])? < 8 {
+            return Ok(None);
+        }
+
+        let (header_size, box_size) = match read_u32(&header, 0) {
+            Some(1) => {
+                reader.read_exact(&mut header[8..16])?;
+                (16, read_u64(&header, 8).unwrap_or(0))
+            }
+            // a size of 0 means the box runs until the end of the file
+            Some(0) => (8, end - reader.stream_position()? + 8),
+            Some(size) => (8, u64::from(size)),
+            None => return Ok(None),
+        };
+
+        let position = reader.stream_position()?;
+        let body_size = match box_size.checked_sub(header_size) {
+            Some(body_size) if body_size <= end - position => body_size,
+            _ => return Ok(None),
+        };
+
+        if &header[4..8] == box_type {
+            if body_size > MAX_BOX_SIZE {
+                return Ok(None);
+            }
+
+            let mut body = Vec::new();
+            reader.take(body_size).read_to_end(&mut body)?;
+
+            return Ok(Some(body));
+        }
+
+        reader.seek(SeekFrom::Start(position + body_size))?;
+    }
+}
+
+/// Returns the header size and the total size of the box at the start of `data`
+fn parse_header(data: &[u8]) -> Option<(usize, usize)> {
+    let (header_size, box_size) = match read_u32(data, 0)? {
+        1 => (16, read_u64(data, 8)? as usize),
+        0 => (8, data.len()),
+        size => (8, size as usize),
+    };
+
+    if data.len() < 8 || box_size < header_size || box_size > data.len() {
+        return None;
+    }
+
+    Some((header_size, box_size))
+}
+
+fn read_up_to<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+
+    while read < buf.len() {
+        match reader.read(&mut buf[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+
+    Ok(read)
+}
+
+// BIG ENDIAN INTEGERS *****************************************************************************
+
+pub fn read_u8(data: &[u8], offset: usize) -> Option<u8> {
+    data.get(offset).copied()
+}
+
+pub fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes(bytes.try_into().ok()?))
+}
+
+pub fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+pub fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    let bytes = data.get(offset..offset + 8)?;
+    Some(u64::from_be_bytes(bytes.try_into().ok()?))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// Builds a box with a 32-bit size around `body`
+    pub fn make_box(box_type: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut data = (8 + body.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(box_type);
+        data.extend_from_slice(body);
+        data
+    }
+
+    fn read(file: Vec<u8>, box_type: &[u8; 4]) -> Option<Vec<u8>> {
+        read_top_level_box(&mut Cursor::new(file), box_type).unwrap()
+    }
+
+    #[test]
+    fn reads_top_level_box_after_skipping_others() {
+        let mut file = make_box(b"ftyp", b"heic");
+        file.extend(make_box(b"mdat", &[0; 32]));
+        file.extend(make_box(b"meta", b"body"));
+
+        assert_eq!(read(file, b"meta"), Some(b"body".to_vec()));
+    }
+
+    #[test]
+    fn reads_top_level_box_running_to_end_of_file() {
+        let mut file = make_box(b"ftyp", b"heic");
+        let mut meta = make_box(b"meta", b"body");
+        meta[..4].copy_from_slice(&0u32.to_be_bytes());
+        file.extend(meta);
+
+        assert_eq!(read(file, b"meta"), Some(b"body".to_vec()));
+    }
+
+    #[test]
+    fn stops_at_top_level_box_larger_than_file() {
+        let mut file = make_box(b"ftyp", b"heic");
+        let mut mdat = make_box(b"mdat", &[0; 4]);
+        mdat[..4].copy_from_slice(&1000u32.to_be_bytes());
+        file.extend(mdat);
+        file.extend(make_box(b"meta", b"body"));
+
+        assert_eq!(read(file.clone(), b"meta"), None);
+        assert_eq!(read(file, b"mdat"), None);
+    }
+
+    #[test]
+    fn stops_at_top_level_box_with_overflowing_size() {
+        let mut file = 1u32.to_be_bytes().to_vec();
+        file.extend_from_slice(b"mdat");
+        file.extend_from_slice(&u64::MAX.to_be_bytes());
+        file.extend(make_box(b"meta", b"body"));
+
+        assert_eq!(read(file, b"meta"), None);
+    }
+
+    #[test]
+    fn stops_at_top_level_box_smaller_than_header() {
+        let mut file = make_box(b"mdat", &[]);
+        file[..4].copy_from_slice(&4u32.to_be_bytes());
+        file.extend(make_box(b"meta", b"body"));
+
+        assert_eq!(read(file, b"meta"), None);
+    }
+
+    #[test]
+    fn does_not_read_top_level_box_larger_than_limit() {
+        let mut file = make_box(b"meta", &[]);
+        file[..4].copy_from_slice(&(MAX_BOX_SIZE as u32 + 9).to_be_bytes());
+        file.resize(MAX_BOX_SIZE as usize + 9, 0);
+
+        assert_eq!(read(file, b"meta"), None);
+    }
+
+    #[test]
+    fn iterates_children_until_truncated_box() {
+        let mut data = make_box(b"ispe", &[0; 12]);
+        data.extend(make_box(b"pixi", &[0; 4]));
+        let mut irot = make_box(b"irot", &[1]);
+        irot[..4].copy_from_slice(&100u32.to_be_bytes());
+        data.extend(irot);
+
+        let types: Vec<[u8; 4]> = boxes(&data).map(|child| child.box_type).collect();
+
+        assert_eq!(types, vec![*b"ispe", *b"pixi"]);
+    }
+
+    #[test]
+    fn iterates_no_children_with_overflowing_size() {
+        let mut data = 1u32.to_be_bytes().to_vec();
+        data.extend_from_slice(b"ispe");
+        data.extend_from_slice(&u64::MAX.to_be_bytes());
+
+        assert_eq!(boxes(&data).count(), 0);
+        assert_eq!(boxes(&data[..6]).count(), 0);
+    }
+
+    #[test]
+    fn finds_nested_box() {
+        let stbl = make_box(b"stbl", b"body");
+        let minf = make_box(b"minf", &stbl);
+        let mut mdia = make_box(b"hdlr", &[0; 12]);
+        mdia.extend(make_box(b"minf", &stbl));
+
+        assert_eq!(
+            find_nested_box(&make_box(b"mdia", &mdia), &[b"mdia", b"minf", b"stbl"]),
+            Some(&b"body"[..])
+        );
+        assert_eq!(find_nested_box(&minf, &[b"mdia", b"minf"]), None);
+    }
+}
@@ -0,0 +1,337 @@
+use std::convert::TryInto;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Id of the header element every EBML document starts with
+const EBML_HEADER: u32 = 0x1A45_DFA3;
+/// Id of the Matroska element that holds everything after the header
+const SEGMENT: u32 = 0x1853_8067;
+/// Id of the Matroska element that holds the media data
+const CLUSTER: u32 = 0x1F43_B675;
+/// Largest element read into memory, metadata elements are a few kilobytes at most
+const MAX_ELEMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+// EXTENSIBLE BINARY META LANGUAGE *****************************************************************
+
+/// An element of an EBML document, the format Matroska and WebM videos are stored in. `data` is
+/// the body of the element, without its id and size header.
+pub struct Element<'a> {
+    pub id: u32,
+    pub data: &'a [u8],
+}
+
+/// Iterates over elements laid out back to back, such as the children of a master element.
+/// Iteration stops at the first element whose size does not fit in the remaining data.
+pub struct Elements<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for Elements<'a> {
+    type Item = Element<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (id, header_size, body_size) = match parse_header(self.data) {
+            Some(header) => header,
+            None => {
+                self.data = &[];
+                return None;
+            }
+        };
+
+        // an unknown size means the element runs until the end of its parent
+        let element_size = match body_size {
+            Some(body_size) => header_size.checked_add(body_size as usize)?,
+            None => self.data.len(),
+        };
+
+        if element_size > self.data.len() {
+            self.data = &[];
+            return None;
+        }
+
+        let data = &self.data[header_size..element_size];
+        self.data = &self.data[element_size..];
+
+        Some(Element { id, data })
+    }
+}
+
+pub fn elements(data: &[u8]) -> Elements<'_> {
+    Elements { data }
+}
+
+/// Returns the body of the first child element with the given id
+pub fn find_element(data: &[u8], id: u32) -> Option<&[u8]> {
+    elements(data)
+        .find(|child| child.id == id)
+        .map(|child| child.data)
+}
+
+/// Reads the bodies of the first children of the Segment with one of the given ids into memory.
+/// Reading stops at the first Cluster, since muxers write the metadata before the media data, so
+/// the clip itself is never read.
+pub fn read_segment_elements<R: Read + Seek>(
+    reader: &mut R,
+    ids: &[u32],
+) -> io::Result<Vec<(u32, Vec<u8>)>> {
+    reader.seek(SeekFrom::Start(0))?;
+
+    let mut found = Vec::new();
+
+    match read_element_header(reader)? {
+        Some((EBML_HEADER, Some(size))) => reader.seek(SeekFrom::Current(size as i64))?,
+        _ => return Ok(found),
+    };
+
+    match read_element_header(reader)? {
+        Some((SEGMENT, _)) => {}
+        _ => return Ok(found),
+    }
+
+    while found.len() < ids.len() {
+        let (id, size) = match read_element_header(reader)? {
+            Some((CLUSTER, _)) | None => break,
+            Some((id, Some(size))) => (id, size),
+            // only clusters are written with an unknown size in practice, nothing can be skipped
+            Some((_, None)) => break,
+        };
+
+        if ids.contains(&id) && found.iter().all(|(found_id, _)| *found_id != id) {
+            if size > MAX_ELEMENT_SIZE {
+                break;
+            }
+
+            let mut body = Vec::new();
+            reader.take(size).read_to_end(&mut body)?;
+            found.push((id, body));
+        } else {
+            reader.seek(SeekFrom::Current(size as i64))?;
+        }
+    }
+
+    Ok(found)
+}
+
+/// Returns the id, header size and body size of the element at the start of `data`. The body size
+/// is `None` if it is unknown.
+fn parse_header(data: &[u8]) -> Option<(u32, usize, Option<u64>)> {
+    let id_size = vint_length(*data.first()?, 4)?;
+    let id = data
+        .get(..id_size)?
+        .iter()
+        .fold(0, |id, byte| (id << 8) | u32::from(*byte));
+
+    let size_data = data.get(id_size..)?;
+    let size_size = vint_length(*size_data.first()?, 8)?;
+    let body_size = read_vint(size_data.get(..size_size)?);
+
+    Some((id, id_size + size_size, body_size))
+}
+
+/// Reads an element header from the current position of `reader`. Returns `None` at the end of
+/// the file or if the header is invalid.
+fn read_element_header<R: Read>(reader: &mut R) -> io::Result<Option<(u32, Option<u64>)>> {
+    let mut header = [0; 12];
+
+    if reader.read(&mut header[..1])? == 0 {
+        return Ok(None);
+    }
+    let id_size = match vint_length(header[0], 4) {
+        Some(id_size) => id_size,
+        None => return Ok(None),
+    };
+    reader.read_exact(&mut header[1..=id_size])?;
+
+    let size_size = match vint_length(header[id_size], 8) {
+        Some(size_size) => size_size,
+        None => return Ok(None),
+    };
+    reader.read_exact(&mut header[id_size + 1..id_size + size_size])?;
+
+    Ok(parse_header(&header[..id_size + size_size]).map(|(id, _, size)| (id, size)))
+}
+
+/// Returns the number of bytes of a variable length integer from its first byte, which has as
+/// many leading zeros as there are bytes after it
+fn vint_length(first_byte: u8, max_length: usize) -> Option<usize> {
+    let length = first_byte.leading_zeros() as usize + 1;
+
+    Some(length).filter(|length| *length <= max_length)
+}
+
+/// Reads a variable length integer without its length marker. All value bits set means the value
+/// is unknown.
+fn read_vint(data: &[u8]) -> Option<u64> {
+    let length = data.len();
+    let first = u64::from(data[0] & 0xFFu8.checked_shr(length as u32).unwrap_or(0));
+    let value = data[1..]
+        .iter()
+        .fold(first, |value, byte| (value << 8) | u64::from(*byte));
+
+    let unknown: u64 = (1 << (7 * length)) - 1;
+    Some(value).filter(|value| *value != unknown)
+}
+
+// ELEMENT VALUES **********************************************************************************
+
+/// Reads a big-endian unsigned integer of up to 8 bytes, an empty body is 0
+pub fn read_uint(data: &[u8]) -> Option<u64> {
+    if data.len() > 8 {
+        return None;
+    }
+
+    Some(
+        data.iter()
+            .fold(0, |value, byte| (value << 8) | u64::from(*byte)),
+    )
+}
+
+/// Reads a 4 or 8 byte big-endian float, an empty body is 0
+pub fn read_float(data: &[u8]) -> Option<f64> {
+    match data.len() {
+        0 => Some(0.0),
+        4 => Some(f64::from(f32::from_be_bytes(data.try_into().ok()?))),
+        8 => Some(f64::from_be_bytes(data.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+/// Reads an ASCII or UTF-8 string, which may be padded with zero bytes
+pub fn read_string(data: &[u8]) -> Option<String> {
+    let end = data
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(data.len());
+
+    std::str::from_utf8(&data[..end]).ok().map(String::from)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const INFO: u32 = 0x1549_A966;
+    const TRACKS: u32 = 0x1654_AE6B;
+
+    /// Builds an element with an 8 byte size around `body`
+    pub fn make_element(id: u32, body: &[u8]) -> Vec<u8> {
+        let mut size = vec![0x01];
+        size.extend_from_slice(&(body.len() as u64).to_be_bytes()[1..]);
+
+        [id_bytes(id), size, body.to_vec()].concat()
+    }
+
+    /// Builds an element of unknown size, which runs until the end of its parent
+    fn make_unknown_size_element(id: u32, body: &[u8]) -> Vec<u8> {
+        [id_bytes(id), vec![0xFF], body.to_vec()].concat()
+    }
+
+    fn id_bytes(id: u32) -> Vec<u8> {
+        id.to_be_bytes()
+            .iter()
+            .copied()
+            .skip_while(|byte| *byte == 0)
+            .collect()
+    }
+
+    fn document(children: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = make_element(EBML_HEADER, &make_element(0x4282, b"webm"));
+        data.extend(make_unknown_size_element(SEGMENT, &children.concat()));
+        data
+    }
+
+    #[test]
+    fn reads_vint_lengths() {
+        assert_eq!(vint_length(0x81, 8), Some(1));
+        assert_eq!(vint_length(0x40, 8), Some(2));
+        assert_eq!(vint_length(0x01, 8), Some(8));
+        assert_eq!(vint_length(0x00, 8), None);
+        assert_eq!(vint_length(0x10, 4), Some(4));
+        assert_eq!(vint_length(0x08, 4), None);
+    }
+
+    #[test]
+    fn reads_vints() {
+        assert_eq!(read_vint(&[0x81]), Some(1));
+        assert_eq!(read_vint(&[0x40, 0x02]), Some(2));
+        assert_eq!(read_vint(&[0x01, 0, 0, 0, 0, 0, 0x01, 0x00]), Some(256));
+    }
+
+    #[test]
+    fn reads_vints_with_every_value_bit_set_as_unknown() {
+        assert_eq!(read_vint(&[0xFF]), None);
+        assert_eq!(read_vint(&[0x7F, 0xFF]), None);
+        assert_eq!(
+            read_vint(&[0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]),
+            None
+        );
+        // one bit short of unknown is a regular value
+        assert_eq!(read_vint(&[0xFE]), Some(0x7E));
+    }
+
+    #[test]
+    fn iterates_unknown_size_element_until_end_of_parent() {
+        let mut data = make_element(0x86, b"V_VP9");
+        data.extend(make_unknown_size_element(0xE0, &[1, 2, 3]));
+
+        let children: Vec<(u32, &[u8])> = elements(&data)
+            .map(|child| (child.id, child.data))
+            .collect();
+
+        assert_eq!(
+            children,
+            vec![(0x86, &b"V_VP9"[..]), (0xE0, &[1, 2, 3][..])]
+        );
+    }
+
+    #[test]
+    fn iterates_elements_until_oversized_element() {
+        let mut data = make_element(0x86, b"V_VP9");
+        let mut oversized = make_element(0x83, &[1]);
+        oversized[8] = 100;
+        data.extend(oversized);
+
+        assert_eq!(elements(&data).count(), 1);
+
+        // a size close to the largest an 8 byte vint holds must not overflow
+        let huge = [0x83, 0x01, 0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFE, 0];
+        assert_eq!(elements(&huge).count(), 0);
+    }
+
+    #[test]
+    fn reads_segment_elements_until_first_cluster() {
+        let info = make_element(INFO, b"info");
+        let tracks = make_element(TRACKS, b"tracks");
+        let file = document(&[
+            make_element(0x114D_9B74, b"seek head"),
+            info,
+            make_unknown_size_element(CLUSTER, b"frames"),
+            tracks,
+        ]);
+
+        let found = read_segment_elements(&mut Cursor::new(file), &[INFO, TRACKS]);
+
+        assert_eq!(found.unwrap(), vec![(INFO, b"info".to_vec())]);
+    }
+
+    #[test]
+    fn does_not_read_segment_elements_larger_than_limit() {
+        let mut info = make_element(INFO, &[]);
+        let size = (MAX_ELEMENT_SIZE + 1).to_be_bytes();
+        let length = info.len();
+        info[length - 7..].copy_from_slice(&size[1..]);
+        let file = document(&[info]);
+
+        let found = read_segment_elements(&mut Cursor::new(file), &[INFO]);
+
+        assert_eq!(found.unwrap(), vec![]);
+    }
+
+    #[test]
+    fn reads_nothing_from_files_that_are_not_ebml() {
+        let found = read_segment_elements(&mut Cursor::new(b"\0\0\0\x18ftypmp42".to_vec()), &[1]);
+
+        assert_eq!(found.unwrap(), vec![]);
+    }
+}
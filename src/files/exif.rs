@@ -0,0 +1,149 @@
+use std::fs::File;
+use std::io::BufReader;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use exif::{Exif, In, Reader, Tag, Value};
+
+// EXIF METADATA ***********************************************************************************
+
+/// Camera metadata read from a photo's EXIF block. Every field is optional since cameras, editors
+/// and phones each write a different subset of tags, and plenty of images have no EXIF at all.
+#[derive(Clone, Debug, Default)]
+pub struct ExifMetadata {
+    pub date_taken: Option<NaiveDateTime>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub lens_model: Option<String>,
+    /// Exposure time as written on the camera, e.g. `1/250` or `2`
+    pub exposure_time: Option<String>,
+    pub f_number: Option<f32>,
+    /// Focal length in millimeters
+    pub focal_length: Option<f32>,
+    pub iso: Option<i32>,
+    /// Raw EXIF orientation, 1 through 8
+    pub orientation: Option<u16>,
+}
+
+impl ExifMetadata {
+    /// Reads the EXIF block of a JPEG, TIFF, PNG, WebP or HEIF file. Files without EXIF, or with
+    /// EXIF that cannot be parsed, produce empty metadata rather than an error.
+    pub fn read(path: &str) -> Self {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(_) => return ExifMetadata::default(),
+        };
+
+        match Reader::new().read_from_container(&mut BufReader::new(file)) {
+            Ok(exif) => ExifMetadata::from_exif(&exif),
+            Err(_) => ExifMetadata::default(),
+        }
+    }
+
+    fn from_exif(exif: &Exif) -> Self {
+        ExifMetadata {
+            date_taken: get_date_time(exif, Tag::DateTimeOriginal)
+                .or_else(|| get_date_time(exif, Tag::DateTime)),
+            camera_make: get_string(exif, Tag::Make),
+            camera_model: get_string(exif, Tag::Model),
+            lens_model: get_string(exif, Tag::LensModel),
+            exposure_time: get_exposure_time(exif),
+            f_number: get_float(exif, Tag::FNumber),
+            focal_length: get_float(exif, Tag::FocalLength),
+            iso: get_uint(exif, Tag::PhotographicSensitivity).map(|iso| iso as i32),
+            orientation: get_uint(exif, Tag::Orientation).map(|orientation| orientation as u16),
+        }
+    }
+
+    /// Maps the EXIF orientation onto the clockwise rotation, in degrees, needed to display the
+    /// photo upright. Mirrored orientations (2, 4, 5 and 7) are treated like their unmirrored
+    /// counterparts, since `rotation` can only hold 0, 90, 180 or 270.
+    pub fn rotation(&self) -> i32 {
+        match self.orientation {
+            Some(3) | Some(4) => 180,
+            Some(5) | Some(6) => 90,
+            Some(7) | Some(8) => 270,
+            _ => 0,
+        }
+    }
+}
+
+fn get_string(exif: &Exif, tag: Tag) -> Option<String> {
+    let field = exif.get_field(tag, In::PRIMARY)?;
+
+    match field.value {
+        Value::Ascii(ref values) => {
+            let value = values.first()?;
+            let value = String::from_utf8_lossy(value)
+                .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+                .to_string();
+
+            if value.is_empty() {
+                None
+            } else {
+                Some(value)
+            }
+        }
+        _ => None,
+    }
+}
+
+fn get_uint(exif: &Exif, tag: Tag) -> Option<u32> {
+    exif.get_field(tag, In::PRIMARY)?.value.get_uint(0)
+}
+
+fn get_float(exif: &Exif, tag: Tag) -> Option<f32> {
+    let field = exif.get_field(tag, In::PRIMARY)?;
+
+    match field.value {
+        Value::Rational(ref values) => values
+            .first()
+            .filter(|value| value.denom != 0)
+            .map(|value| value.to_f32()),
+        _ => None,
+    }
+}
+
+fn get_exposure_time(exif: &Exif) -> Option<String> {
+    let field = exif.get_field(Tag::ExposureTime, In::PRIMARY)?;
+
+    let value = match field.value {
+        Value::Rational(ref values) => values.first()?,
+        _ => return None,
+    };
+
+    if value.num == 0 || value.denom == 0 {
+        None
+    } else if value.num >= value.denom {
+        // long exposures are written in seconds, e.g. `2` or `2.5`
+        Some(format!("{}", value.to_f32()))
+    } else {
+        // short exposures are written as fractions of a second, e.g. `1/250`
+        Some(format!(
+            "1/{}",
+            (value.denom as f32 / value.num as f32).round()
+        ))
+    }
+}
+
+fn get_date_time(exif: &Exif, tag: Tag) -> Option<NaiveDateTime> {
+    let field = exif.get_field(tag, In::PRIMARY)?;
+
+    let value = match field.value {
+        Value::Ascii(ref values) => values.first()?,
+        _ => return None,
+    };
+
+    // EXIF dates carry no time zone, so they are stored as the camera's local time
+    let date_time = exif::DateTime::from_ascii(value).ok()?;
+
+    NaiveDate::from_ymd_opt(
+        i32::from(date_time.year),
+        u32::from(date_time.month),
+        u32::from(date_time.day),
+    )?
+    .and_hms_opt(
+        u32::from(date_time.hour),
+        u32::from(date_time.minute),
+        u32::from(date_time.second),
+    )
+}
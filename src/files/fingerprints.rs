@@ -0,0 +1,208 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::SystemTime;
+
+use chrono::NaiveDateTime;
+use deadpool_postgres::Pool;
+
+use crate::errors::ServiceError;
+use crate::files::photos::FileInfo;
+use crate::jobs::scan::ScanProgress;
+use crate::schemas::new_photo::system_time_to_date_time;
+use crate::schemas::photo::Photo;
+
+// FINGERPRINT *************************************************************************************
+
+/// Size and modification time of a file. Both are cheap to read and stay the same as long as the
+/// file contents do, so a matching fingerprint lets a scan skip hashing the file again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Fingerprint {
+    pub file_size: i64,
+    pub file_modified: NaiveDateTime,
+}
+
+impl Fingerprint {
+    pub fn new(file_size: i64, modified: SystemTime) -> Self {
+        // postgres only stores microseconds, so drop anything finer to compare against stored values
+        let modified = system_time_to_date_time(modified).naive_utc();
+        let micros = modified.timestamp_subsec_micros();
+
+        Fingerprint {
+            file_size,
+            file_modified: NaiveDateTime::from_timestamp(modified.timestamp(), micros * 1_000),
+        }
+    }
+}
+
+// FILE STATUS *************************************************************************************
+
+pub enum FileStatus {
+    /// The path is known and its fingerprint matches
+    Unchanged,
+    /// The path is known, but no fingerprint has been stored for it yet
+    Unfingerprinted(i32),
+    /// The path is known, but the file has been modified since it was imported
+    Changed(i32),
+    /// A photo with the same name and fingerprint used to live at a path that no longer exists
+    Moved { id: i32, from: String },
+    /// Nothing is known about the file, so it has to be hashed
+    New,
+}
+
+/// Files found by a scan, sorted by what needs to happen to them
+#[derive(Default)]
+pub struct ClassifiedFiles {
+    pub new_files: Vec<FileInfo>,
+    pub changed_files: Vec<(i32, FileInfo)>,
+    pub unchanged_count: i32,
+    /// Known photos that are missing a stored fingerprint
+    pub unfingerprinted: Vec<(i32, Fingerprint)>,
+    /// Known photos that now live at a different path, as `(id, old path, new path)`
+    pub moved: Vec<(i32, String, String)>,
+}
+
+impl ClassifiedFiles {
+    /// Stores the missing fingerprints and follows the moves that were found
+    pub async fn apply(&self, pool: &Pool) -> Result<(), ServiceError> {
+        if !self.unfingerprinted.is_empty() {
+            println!("Store {} missing fingerprints...", self.unfingerprinted.len());
+            Photo::update_fingerprints(&self.unfingerprinted, pool).await?;
+        }
+
+        if !self.moved.is_empty() {
+            let moves: Vec<(i32, String)> = self
+                .moved
+                .iter()
+                .map(|(id, _, to)| (*id, to.to_owned()))
+                .collect();
+
+            Photo::update_file_paths(&moves, pool).await?;
+        }
+
+        Ok(())
+    }
+}
+
+// FINGERPRINT INDEX *******************************************************************************
+
+struct KnownPhoto {
+    id: i32,
+    fingerprint: Option<Fingerprint>,
+}
+
+/// Every photo outside the trash keyed by path and by name + fingerprint. Loaded with a single
+/// query so that classifying files does not cost a round trip per file.
+pub struct FingerprintIndex {
+    by_path: HashMap<String, KnownPhoto>,
+    by_fingerprint: HashMap<(String, Fingerprint), Vec<(i32, String)>>,
+    claimed: HashSet<i32>,
+}
+
+impl FingerprintIndex {
+    pub async fn load(pool: &Pool) -> Result<Self, ServiceError> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "SELECT id, file_path, file_name, file_size, file_modified FROM photos \
+                 WHERE date_trashed IS NULL",
+            )
+            .await?;
+        let rows = client.query(&stmt, &[]).await?;
+
+        let mut index = FingerprintIndex {
+            by_path: HashMap::with_capacity(rows.len()),
+            by_fingerprint: HashMap::with_capacity(rows.len()),
+            claimed: HashSet::new(),
+        };
+
+        for row in rows {
+            let id: i32 = row.get("id");
+            let file_path: String = row.get("file_path");
+            let file_name: String = row.get("file_name");
+            let file_size: Option<i64> = row.get("file_size");
+            let file_modified: Option<NaiveDateTime> = row.get("file_modified");
+
+            let fingerprint = match (file_size, file_modified) {
+                (Some(file_size), Some(file_modified)) => Some(Fingerprint {
+                    file_size,
+                    file_modified,
+                }),
+                _ => None,
+            };
+
+            if let Some(fingerprint) = fingerprint {
+                index
+                    .by_fingerprint
+                    .entry((file_name, fingerprint))
+                    .or_default()
+                    .push((id, file_path.clone()));
+            }
+
+            index
+                .by_path
+                .insert(file_path, KnownPhoto { id, fingerprint });
+        }
+
+        Ok(index)
+    }
+
+    pub fn classify(&mut self, file: &FileInfo) -> FileStatus {
+        let fingerprint = file.fingerprint();
+
+        if let Some(known) = self.by_path.get(&file.file_path) {
+            return match known.fingerprint {
+                None => FileStatus::Unfingerprinted(known.id),
+                Some(stored) if stored == fingerprint => FileStatus::Unchanged,
+                Some(_) => FileStatus::Changed(known.id),
+            };
+        }
+
+        if let Some(candidates) = self.by_fingerprint.get(&(file.file_name(), fingerprint)) {
+            for (id, path) in candidates {
+                // each stored photo can only move once, and only if it is gone from its old path
+                if self.claimed.contains(id) || Path::new(path).exists() {
+                    continue;
+                }
+
+                self.claimed.insert(*id);
+
+                return FileStatus::Moved {
+                    id: *id,
+                    from: path.to_owned(),
+                };
+            }
+        }
+
+        FileStatus::New
+    }
+
+    /// Classifies every file. Nothing is written to the database, see `ClassifiedFiles::apply`.
+    pub fn classify_all(
+        &mut self,
+        files: Vec<FileInfo>,
+        progress: &ScanProgress,
+    ) -> Result<ClassifiedFiles, ServiceError> {
+        let mut classified = ClassifiedFiles::default();
+
+        for file in files {
+            progress.check_cancelled()?;
+            progress.advance(1);
+
+            match self.classify(&file) {
+                FileStatus::Unchanged => classified.unchanged_count += 1,
+                FileStatus::Unfingerprinted(id) => {
+                    classified.unfingerprinted.push((id, file.fingerprint()));
+                    classified.unchanged_count += 1;
+                }
+                FileStatus::Changed(id) => classified.changed_files.push((id, file)),
+                FileStatus::Moved { id, from } => {
+                    println!("Moved: {} -> {}", from, file.file_path);
+                    classified.moved.push((id, from, file.file_path));
+                }
+                FileStatus::New => classified.new_files.push(file),
+            }
+        }
+
+        Ok(classified)
+    }
+}
@@ -0,0 +1,204 @@
+use std::fs::File;
+use std::io::BufReader;
+
+use crate::files::bmff::{self, BmffBox};
+
+// HEIF IMAGE INFO *********************************************************************************
+
+/// Dimensions and rotation of the primary image of a HEIF file (`.heic`/`.heif`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HeifInfo {
+    pub width: u32,
+    pub height: u32,
+    /// Clockwise rotation in degrees needed to display the image upright, i.e. the same meaning as
+    /// the `rotation` column
+    pub rotation: i32,
+}
+
+impl HeifInfo {
+    /// Reads the image spatial extent (`ispe`) and rotation (`irot`) properties of the primary
+    /// item. Only the `meta` box is read, the image data itself is never decoded.
+    pub fn read(path: &str) -> Option<Self> {
+        let file = File::open(path).ok()?;
+        let meta = bmff::read_top_level_box(&mut BufReader::new(file), b"meta").ok()??;
+
+        HeifInfo::from_meta(&meta)
+    }
+
+    fn from_meta(meta: &[u8]) -> Option<Self> {
+        // `meta` is a full box, so its children start after the version and flags
+        let children = meta.get(4..)?;
+
+        let primary_item = read_primary_item(bmff::find_box(children, b"pitm")?)?;
+
+        let iprp = bmff::find_box(children, b"iprp")?;
+        let properties: Vec<BmffBox> = bmff::boxes(bmff::find_box(iprp, b"ipco")?).collect();
+
+        let associated: Vec<&BmffBox> = bmff::boxes(iprp)
+            .filter(|child| &child.box_type == b"ipma")
+            .filter_map(|ipma| read_associations(ipma.data, primary_item))
+            .flatten()
+            // property indexes are 1-based, 0 means no property
+            .filter_map(|index| index.checked_sub(1).and_then(|index| properties.get(index)))
+            .collect();
+
+        // fall back to the largest extent in the file if the primary item has none of its own
+        let (width, height) = associated
+            .iter()
+            .find(|property| &property.box_type == b"ispe")
+            .and_then(|ispe| read_extent(ispe.data))
+            .or_else(|| {
+                properties
+                    .iter()
+                    .filter(|property| &property.box_type == b"ispe")
+                    .filter_map(|ispe| read_extent(ispe.data))
+                    .max_by_key(|(width, height)| u64::from(*width) * u64::from(*height))
+            })?;
+
+        // `irot` stores the angle as a multiple of 90 degrees counter-clockwise
+        let rotation = associated
+            .iter()
+            .find(|property| &property.box_type == b"irot")
+            .and_then(|irot| bmff::read_u8(irot.data, 0))
+            .map(|angle| (360 - i32::from(angle & 0x3) * 90) % 360)
+            .unwrap_or(0);
+
+        Some(HeifInfo {
+            width,
+            height,
+            rotation,
+        })
+    }
+}
+
+pub fn is_heif_file(file_name: &str) -> bool {
+    let file_name = file_name.to_lowercase();
+
+    file_name.ends_with(".heic") || file_name.ends_with(".heif")
+}
+
+fn read_primary_item(pitm: &[u8]) -> Option<u32> {
+    match bmff::read_u8(pitm, 0)? {
+        0 => bmff::read_u16(pitm, 4).map(u32::from),
+        _ => bmff::read_u32(pitm, 4),
+    }
+}
+
+fn read_extent(ispe: &[u8]) -> Option<(u32, u32)> {
+    let width = bmff::read_u32(ispe, 4)?;
+    let height = bmff::read_u32(ispe, 8)?;
+
+    Some((width, height))
+}
+
+/// Returns the property indexes associated with `item_id` in an `ipma` box
+fn read_associations(ipma: &[u8], item_id: u32) -> Option<Vec<usize>> {
+    let version = bmff::read_u8(ipma, 0)?;
+    let large_indexes = bmff::read_u8(ipma, 3)? & 1 == 1;
+    let entry_count = bmff::read_u32(ipma, 4)?;
+
+    let mut offset = 8;
+    for _ in 0..entry_count {
+        let id = if version < 1 {
+            offset += 2;
+            u32::from(bmff::read_u16(ipma, offset - 2)?)
+        } else {
+            offset += 4;
+            bmff::read_u32(ipma, offset - 4)?
+        };
+
+        let association_count = usize::from(bmff::read_u8(ipma, offset)?);
+        offset += 1;
+
+        let mut indexes = Vec::with_capacity(association_count);
+        for _ in 0..association_count {
+            // the highest bit flags the property as essential, the rest is the index
+            let index = if large_indexes {
+                offset += 2;
+                usize::from(bmff::read_u16(ipma, offset - 2)? & 0x7fff)
+            } else {
+                offset += 1;
+                usize::from(bmff::read_u8(ipma, offset - 1)? & 0x7f)
+            };
+
+            indexes.push(index);
+        }
+
+        if id == item_id {
+            return Some(indexes);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::bmff::tests::make_box;
+
+    /// Builds the body of a `meta` box whose primary item 1 has a 4032x3024 `ispe` and, if given,
+    /// an `irot` with the angle
+    fn meta(angle: Option<u8>) -> Vec<u8> {
+        let mut ispe = vec![0; 4];
+        ispe.extend_from_slice(&4032u32.to_be_bytes());
+        ispe.extend_from_slice(&3024u32.to_be_bytes());
+
+        let mut ipco = make_box(b"ispe", &ispe);
+        let mut associations = vec![0x81];
+        if let Some(angle) = angle {
+            ipco.extend(make_box(b"irot", &[angle]));
+            associations.push(0x02);
+        }
+
+        let mut ipma = vec![0, 0, 0, 0, 0, 0, 0, 1, 0, 1, associations.len() as u8];
+        ipma.extend(associations);
+
+        let mut iprp = make_box(b"ipco", &ipco);
+        iprp.extend(make_box(b"ipma", &ipma));
+
+        let mut meta = vec![0; 4];
+        meta.extend(make_box(b"pitm", &[0, 0, 0, 0, 0, 1]));
+        meta.extend(make_box(b"iprp", &iprp));
+        meta
+    }
+
+    #[test]
+    fn reads_extent_of_primary_item() {
+        let info = HeifInfo::from_meta(&meta(None)).unwrap();
+
+        assert_eq!((info.width, info.height, info.rotation), (4032, 3024, 0));
+    }
+
+    #[test]
+    fn maps_counter_clockwise_irot_to_clockwise_rotation() {
+        let rotations: Vec<i32> = (0..4)
+            .map(|angle| HeifInfo::from_meta(&meta(Some(angle))).unwrap().rotation)
+            .collect();
+
+        assert_eq!(rotations, vec![0, 270, 180, 90]);
+    }
+
+    #[test]
+    fn falls_back_to_largest_extent_without_associations() {
+        let mut meta = meta(None);
+        // drop the association of the primary item with its `ispe`
+        let last = meta.len() - 1;
+        meta[last] = 0;
+
+        let info = HeifInfo::from_meta(&meta).unwrap();
+
+        assert_eq!((info.width, info.height), (4032, 3024));
+    }
+
+    #[test]
+    fn ignores_truncated_meta() {
+        let meta = meta(Some(1));
+
+        for length in 0..meta.len() - 1 {
+            // must not panic, most truncations lose the primary item or its extent
+            let _ = HeifInfo::from_meta(&meta[..length]);
+        }
+        assert_eq!(HeifInfo::from_meta(&meta[..4]), None);
+    }
+}
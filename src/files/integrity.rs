@@ -0,0 +1,117 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::files::fingerprints::Fingerprint;
+use crate::schemas::new_photo::sha3_hash;
+
+// STORED FILE *************************************************************************************
+
+/// What the database knows about the file of a photo
+#[derive(Clone, Debug)]
+pub struct StoredFile {
+    pub photo_id: i32,
+    pub file_path: String,
+    pub file_hash: String,
+    /// `None` for photos imported before fingerprints were stored
+    pub fingerprint: Option<Fingerprint>,
+}
+
+// VERIFICATION ************************************************************************************
+
+/// Outcome of re-hashing the file of a photo
+#[derive(Clone, Debug, PartialEq)]
+pub enum Verification {
+    Intact,
+    /// The file was modified since it was hashed, so its hash is expected to differ. This is an
+    /// edit rather than corruption, the next scan picks it up.
+    Modified,
+    /// The contents changed without the file being modified, holds the new hash
+    Mismatch(String),
+    Missing,
+    Unreadable(String),
+}
+
+impl Verification {
+    /// Returns the `kind` the outcome is reported as, `None` if there is nothing to report
+    pub fn kind(&self) -> Option<&'static str> {
+        match self {
+            Verification::Intact | Verification::Modified => None,
+            Verification::Mismatch(_) => Some("mismatch"),
+            Verification::Missing => Some("missing"),
+            Verification::Unreadable(_) => Some("unreadable"),
+        }
+    }
+}
+
+/// Re-hashes the file of a photo and compares it with the stored hash. Reading is throttled to
+/// `bytes_per_second` so that verifying the library does not starve everything else of disk
+/// bandwidth, `0` reads at full speed.
+pub fn verify(file: &StoredFile, bytes_per_second: u64) -> Verification {
+    let metadata = match fs::metadata(&file.file_path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Verification::Missing,
+        Err(err) => return Verification::Unreadable(err.to_string()),
+    };
+
+    // bit rot changes the contents without touching the size or modification time
+    let current = metadata
+        .modified()
+        .ok()
+        .map(|modified| Fingerprint::new(metadata.len() as i64, modified));
+    if let (Some(stored), Some(current)) = (file.fingerprint, current) {
+        if stored != current {
+            return Verification::Modified;
+        }
+    }
+
+    let hash = File::open(&file.file_path)
+        .and_then(|f| sha3_hash(&mut ThrottledReader::new(f, bytes_per_second)));
+
+    match hash {
+        Ok(hash) if hash == file.file_hash => Verification::Intact,
+        Ok(hash) => Verification::Mismatch(hash),
+        Err(err) => Verification::Unreadable(err.to_string()),
+    }
+}
+
+// THROTTLED READER ********************************************************************************
+
+/// Sleeps between reads whenever the inner reader gets ahead of the given rate
+struct ThrottledReader<R> {
+    inner: R,
+    bytes_per_second: u64,
+    started: Instant,
+    bytes_read: u64,
+}
+
+impl<R: Read> ThrottledReader<R> {
+    fn new(inner: R, bytes_per_second: u64) -> Self {
+        ThrottledReader {
+            inner,
+            bytes_per_second,
+            started: Instant::now(),
+            bytes_read: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for ThrottledReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+
+        if self.bytes_per_second > 0 {
+            self.bytes_read += read as u64;
+
+            let due =
+                Duration::from_secs_f64(self.bytes_read as f64 / self.bytes_per_second as f64);
+            let elapsed = self.started.elapsed();
+            if due > elapsed {
+                thread::sleep(due - elapsed);
+            }
+        }
+
+        Ok(read)
+    }
+}
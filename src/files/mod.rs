@@ -1 +1,16 @@
+pub mod bmff;
+pub mod ebml;
+pub mod exif;
+pub mod fingerprints;
+pub mod heif;
+pub mod integrity;
+pub mod phash;
 pub mod photos;
+pub mod renditions;
+pub mod scan_rules;
+pub mod thumbnails;
+pub mod trash;
+pub mod validation;
+pub mod video;
+pub mod wallpapers;
+pub mod xmp;
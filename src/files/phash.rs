@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use image::imageops::FilterType;
+use image::ImageFormat;
+
+/// Default maximum Hamming distance between two perceptual hashes for the photos to be considered
+/// near-duplicates. Re-encoded and resized copies usually land well below this.
+pub const DEFAULT_MAX_DISTANCE: u32 = 10;
+
+// PERCEPTUAL HASH *********************************************************************************
+
+/// Computes a 64 bit difference hash (dHash) of an image. The image is shrunk to 9x8 grayscale
+/// pixels and every bit records whether a pixel is brighter than its right-hand neighbour, so the
+/// hash survives re-encoding, resizing and stripped metadata.
+///
+/// Returns `None` for formats the `image` crate cannot decode, such as HEIC.
+pub fn perceptual_hash(path: &str) -> Option<i64> {
+    if !can_decode(path) {
+        return None;
+    }
+
+    let image = image::open(path).ok()?;
+    let pixels = image.resize_exact(9, 8, FilterType::Triangle).to_luma();
+
+    let mut hash: u64 = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let left = pixels.get_pixel(x, y)[0];
+            let right = pixels.get_pixel(x + 1, y)[0];
+
+            hash = (hash << 1) | (left > right) as u64;
+        }
+    }
+
+    // postgres has no unsigned integers, so the bits are stored as-is in a bigint
+    Some(hash as i64)
+}
+
+/// Whether the file is in a format that `perceptual_hash` is able to decode
+pub fn can_decode(path: &str) -> bool {
+    ImageFormat::from_path(Path::new(path)).is_ok()
+}
+
+pub fn hamming_distance(a: i64, b: i64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+// NEAR-DUPLICATE GROUPS ***************************************************************************
+
+/// BK-tree over perceptual hashes. Finding every hash within a small distance only visits a
+/// fraction of the tree, which keeps the library-wide report from comparing every pair of photos.
+struct BkTree {
+    nodes: Vec<BkNode>,
+}
+
+struct BkNode {
+    index: usize,
+    hash: i64,
+    children: BTreeMap<u32, usize>,
+}
+
+impl BkTree {
+    fn new() -> Self {
+        BkTree { nodes: Vec::new() }
+    }
+
+    fn insert(&mut self, index: usize, hash: i64) {
+        let new_node = self.nodes.len();
+
+        if new_node > 0 {
+            let mut current = 0;
+            loop {
+                let distance = hamming_distance(self.nodes[current].hash, hash);
+
+                match self.nodes[current].children.get(&distance) {
+                    Some(&child) => current = child,
+                    None => {
+                        self.nodes[current].children.insert(distance, new_node);
+                        break;
+                    }
+                }
+            }
+        }
+
+        self.nodes.push(BkNode {
+            index,
+            hash,
+            children: BTreeMap::new(),
+        });
+    }
+
+    /// Returns the indexes of every hash within `max_distance` of `hash`
+    fn find(&self, hash: i64, max_distance: u32) -> Vec<usize> {
+        let mut found = Vec::new();
+
+        if self.nodes.is_empty() {
+            return found;
+        }
+
+        let mut stack = vec![0];
+        while let Some(current) = stack.pop() {
+            let node = &self.nodes[current];
+            let distance = hamming_distance(node.hash, hash);
+
+            if distance <= max_distance {
+                found.push(node.index);
+            }
+
+            let low = distance.saturating_sub(max_distance);
+            let high = distance + max_distance;
+            stack.extend(node.children.range(low..=high).map(|(_, &child)| child));
+        }
+
+        found
+    }
+}
+
+/// Groups photos whose perceptual hashes are within `max_distance` of each other. Similarity is
+/// followed transitively, so a group may contain photos further apart than `max_distance` as long
+/// as they are linked through other photos. Photos without a near-duplicate are left out.
+pub fn group_similar(hashes: &[(i32, i64)], max_distance: u32) -> Vec<Vec<i32>> {
+    let mut tree = BkTree::new();
+    for (index, (_, hash)) in hashes.iter().enumerate() {
+        tree.insert(index, *hash);
+    }
+
+    // union-find over the indexes of `hashes`
+    let mut parents: Vec<usize> = (0..hashes.len()).collect();
+
+    fn root(parents: &mut [usize], mut index: usize) -> usize {
+        while parents[index] != index {
+            parents[index] = parents[parents[index]];
+            index = parents[index];
+        }
+        index
+    }
+
+    for (index, (_, hash)) in hashes.iter().enumerate() {
+        for other in tree.find(*hash, max_distance) {
+            let a = root(&mut parents, index);
+            let b = root(&mut parents, other);
+
+            if a != b {
+                parents[a.max(b)] = a.min(b);
+            }
+        }
+    }
+
+    let mut groups: Vec<Vec<i32>> = vec![Vec::new(); hashes.len()];
+    for (index, (id, _)) in hashes.iter().enumerate() {
+        let group = root(&mut parents, index);
+        groups[group].push(*id);
+    }
+
+    groups.into_iter().filter(|ids| ids.len() > 1).collect()
+}
@@ -1,16 +1,33 @@
-use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::SystemTime;
 
-use deadpool_postgres::{Pool, PoolError};
+use actix_web::web;
+use deadpool_postgres::Pool;
 use num_format::{Locale, ToFormattedString};
 use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use serde::{Deserialize, Serialize};
 use walkdir::{DirEntry, WalkDir};
 
 use crate::errors::ServiceError;
-use crate::errors::ServiceError::DuplicateFileError;
+use crate::files::exif::ExifMetadata;
+use crate::files::fingerprints::{Fingerprint, FingerprintIndex};
+use crate::files::heif::HeifInfo;
+use crate::files::phash;
+use crate::files::renditions::Framing;
+use crate::files::scan_rules::{ExcludedFiles, ScanRules, IGNORE_FILE_NAME};
+use crate::files::thumbnails;
+use crate::files::validation::{FileIssue, IssueKind};
+use crate::jobs::scan::{ScanPhase, ScanProgress};
+use crate::schemas::duplicates::DuplicateGroup;
+use crate::schemas::libraries::Library;
 use crate::schemas::new_photo::NewPhoto;
 use crate::schemas::photo::Photo;
-use crate::types::FileCollectionResult;
+use crate::schemas::scan_issues::ScanIssue;
+use crate::types::{DuplicatePhotos, FileCollectionResult};
 
 // FILE SCAN RESULT ********************************************************************************
 
@@ -20,7 +37,13 @@ pub struct FileScanResult {
     pub new_photos_count: i32,
     pub updated_photos_count: i32,
     pub deleted_photos_count: i32,
+    pub hashed_photos_count: i32,
+    pub duplicate_groups_count: i32,
     pub new_photos: Vec<NewPhoto>,
+    /// Files that failed validation, they are recorded in `scan_issues` rather than imported
+    pub issues: Vec<FileIssue>,
+    pub plan: ScanPlan,
+    pub excluded: ExcludedFiles,
 }
 
 impl Default for FileScanResult {
@@ -30,7 +53,40 @@ impl Default for FileScanResult {
             new_photos_count: 0,
             updated_photos_count: 0,
             deleted_photos_count: 0,
+            hashed_photos_count: 0,
+            duplicate_groups_count: 0,
             new_photos: Vec::new(),
+            issues: Vec::new(),
+            plan: ScanPlan::default(),
+            excluded: ExcludedFiles::default(),
+        }
+    }
+}
+
+// SCAN PLAN ***************************************************************************************
+
+/// Per-file changes found by a scan. A dry run stops here, a regular scan applies them.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanPlan {
+    pub would_insert: Vec<String>,
+    pub would_move: Vec<PlannedMove>,
+    pub would_update: Vec<String>,
+    pub would_delete: Vec<String>,
+    pub would_quarantine: Vec<FileIssue>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PlannedMove {
+    pub from: String,
+    pub to: String,
+}
+
+impl PlannedMove {
+    pub fn new(from: &str, to: &str) -> Self {
+        PlannedMove {
+            from: from.to_string(),
+            to: to.to_string(),
         }
     }
 }
@@ -67,62 +123,388 @@ pub struct FileInfo {
     pub file_path: String,
     pub file_extension: String,
     pub date_created: SystemTime,
+    pub date_modified: SystemTime,
+    pub file_size: i64,
 }
 
 impl FileInfo {
-    pub fn new_from_entry(entry: &DirEntry) -> Self {
-        let ext = get_file_extension(&entry).to_lowercase();
-        let metadata = entry.path().metadata().unwrap();
+    pub fn new_from_entry(entry: &DirEntry) -> Result<Self, FileIssue> {
+        FileInfo::new_from_path(entry.path())
+    }
+
+    pub fn new_from_path(path: &Path) -> Result<Self, FileIssue> {
+        let file_path = path.to_str().ok_or_else(|| {
+            FileIssue::new(
+                &path.to_string_lossy(),
+                IssueKind::Unreadable,
+                "File path is not valid UTF-8",
+            )
+        })?;
+
+        let ext = get_file_extension(path)?.to_lowercase();
+        let metadata = path
+            .metadata()
+            .map_err(|err| FileIssue::new(file_path, IssueKind::Unreadable, err))?;
         let dt_created = metadata.created().unwrap_or_else(|_| SystemTime::now());
+        let dt_modified = metadata.modified().unwrap_or(dt_created);
 
-        FileInfo {
-            file_path: entry.path().to_str().unwrap().to_string(),
+        Ok(FileInfo {
+            file_path: file_path.to_string(),
             file_extension: ext,
             date_created: dt_created,
-        }
+            date_modified: dt_modified,
+            file_size: metadata.len() as i64,
+        })
+    }
+
+    pub fn file_name(&self) -> String {
+        Path::new(&self.file_path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    pub fn fingerprint(&self) -> Fingerprint {
+        Fingerprint::new(self.file_size, self.date_modified)
     }
 }
 
-fn get_file_extension(entry: &DirEntry) -> String {
-    let file_name_split: Vec<&str> = entry.file_name().to_str().unwrap().split('.').collect();
+/// Returns the extension of the file, empty if its name has none
+fn get_file_extension(path: &Path) -> Result<String, FileIssue> {
+    let file_name = path.file_name().ok_or_else(|| {
+        FileIssue::new(
+            &path.to_string_lossy(),
+            IssueKind::Unreadable,
+            "File path has no file name",
+        )
+    })?;
+
+    let extension = Path::new(file_name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default();
 
-    // returns file extension
-    (*file_name_split.last().unwrap()).to_string()
+    Ok(extension.to_string())
 }
 
 // SCAN FILES **************************************************************************************
 
-pub async fn scan_all_photos(pool: &Pool) -> Result<FileScanResult, ServiceError> {
-    let photos_dir = "/photos";
+/// Number of threads that hash and decode files during a scan, read from `SCARLETT_SCAN_THREADS`.
+/// Defaults to one per CPU, a lower number keeps scans from saturating slow disks.
+fn scan_threads() -> usize {
+    env::var("SCARLETT_SCAN_THREADS")
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .filter(|threads| *threads > 0)
+        .unwrap_or_else(rayon::current_num_threads)
+}
+
+/// Runs `f` over every item on a pool of `scan_threads` threads, off of the async worker threads.
+/// Each item advances the progress. Items for which `f` returns `None` are dropped.
+async fn process_in_parallel<T, R, F>(
+    items: Vec<T>,
+    progress: &Arc<ScanProgress>,
+    f: F,
+) -> Result<Vec<R>, ServiceError>
+where
+    T: Send + Sync + 'static,
+    R: Send + 'static,
+    F: Fn(&T) -> Option<R> + Send + Sync + 'static,
+{
+    let threads = scan_threads();
+    let task_progress = progress.clone();
+    let results = web::block(move || -> Result<Vec<R>, ServiceError> {
+        let thread_pool = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .map_err(|err| {
+                println!("Unable to start scan threads: {}", err);
+                ServiceError::InternalServerError
+            })?;
+
+        let results = thread_pool.install(|| {
+            items
+                .par_iter()
+                .filter(|_| !task_progress.is_cancelled())
+                .filter_map(|item| {
+                    let result = f(item);
+                    task_progress.advance(1);
+                    result
+                })
+                .collect()
+        });
+
+        Ok(results)
+    })
+    .await?;
+    progress.check_cancelled()?;
+
+    Ok(results)
+}
+
+/// Scans every library that has scanning enabled
+pub async fn scan_all_photos(
+    pool: &Pool,
+    progress: &Arc<ScanProgress>,
+    dry_run: bool,
+) -> Result<FileScanResult, ServiceError> {
+    let roots: Vec<String> = Library::get_all(pool)
+        .await?
+        .into_iter()
+        .filter(|library| library.scan_enabled)
+        .map(|library| library.root_path)
+        .collect();
 
-    scan_all_photos_from_dir(photos_dir, pool).await
+    scan_dirs(roots, pool, progress, dry_run).await
 }
 
 pub async fn scan_all_photos_from_dir(
     dir: &str,
     pool: &Pool,
+    progress: &Arc<ScanProgress>,
+    dry_run: bool,
+) -> Result<FileScanResult, ServiceError> {
+    scan_dirs(vec![dir.to_string()], pool, progress, dry_run).await
+}
+
+/// Scans `dirs` for new, modified, moved and deleted photos. The directories are compared against
+/// the database in a single pass, so a photo moved from one library to another is still a move.
+/// With `dry_run` every check still runs but nothing is written to the database, the planned
+/// changes are only reported in `plan`. A scan can only be cancelled until its first write.
+async fn scan_dirs(
+    dirs: Vec<String>,
+    pool: &Pool,
+    progress: &Arc<ScanProgress>,
+    dry_run: bool,
 ) -> Result<FileScanResult, ServiceError> {
     println!("Collecting files...");
-    let (files, existing_files_count) = collect_files_from_directory(&dir, pool).await?;
+    progress.set_phase(ScanPhase::Collecting, 0);
+    let mut rules = ScanRules::load(pool).await?;
+    let collect_dirs = dirs.clone();
+    let collect_progress = progress.clone();
+    let (files, excluded, collect_issues) = web::block(move || -> Result<_, ServiceError> {
+        let mut files = Vec::new();
+        let mut issues = Vec::new();
+        for dir in &collect_dirs {
+            files.extend(collect_files_from_directory(
+                dir,
+                &mut rules,
+                &mut issues,
+                &collect_progress,
+            )?);
+        }
 
-    let mut result: FileScanResult = Default::default();
-    result.existing_photos_count = existing_files_count;
+        Ok((files, rules.excluded, issues))
+    })
+    .await?;
 
     println!(
-        "Found {} new files. ({} already exist)",
-        &files.len().to_formatted_string(&Locale::en),
-        &existing_files_count.to_formatted_string(&Locale::en),
+        "Excluded {} files.",
+        excluded.total().to_formatted_string(&Locale::en)
     );
 
+    println!("Compare fingerprints...");
+    progress.set_phase(ScanPhase::Fingerprinting, files.len());
+    let mut index = FingerprintIndex::load(pool).await?;
+    let mut classified = index.classify_all(files, progress)?;
+
+    println!(
+        "Found {} new files. ({} already exist, {} moved, {} modified)",
+        &classified.new_files.len().to_formatted_string(&Locale::en),
+        &classified.unchanged_count.to_formatted_string(&Locale::en),
+        &classified.moved.len().to_formatted_string(&Locale::en),
+        &classified.changed_files.len().to_formatted_string(&Locale::en),
+    );
+
+    let fingerprint_moves: Vec<PlannedMove> = classified
+        .moved
+        .iter()
+        .map(|(_, from, to)| PlannedMove::new(from, to))
+        .collect();
+    let changed_files = std::mem::take(&mut classified.changed_files);
+    let changed_paths: Vec<String> = changed_files
+        .iter()
+        .map(|(_, file)| file.file_path.to_owned())
+        .collect();
+
+    // files are hashed before anything is written, so that a scan cancelled while hashing leaves
+    // the database as it was
+    let rehashed = if dry_run {
+        Vec::new()
+    } else {
+        hash_changed_files(changed_files, progress).await?
+    };
+    let hashed = hash_new_files(std::mem::take(&mut classified.new_files), pool, progress).await?;
+
+    let (changed_count, changed_issues) = if dry_run {
+        (changed_paths.len() as i32, Vec::new())
+    } else {
+        progress.begin_writes()?;
+        classified.apply(pool).await?;
+        store_changed_files(rehashed, pool).await?
+    };
+
+    let mut result = import_hashed_files(hashed, pool, progress, dry_run).await?;
+    let quarantined = result.excluded.quarantined;
+    result.excluded = ExcludedFiles {
+        quarantined,
+        ..excluded
+    };
+    result.existing_photos_count = classified.unchanged_count;
+    result.updated_photos_count += fingerprint_moves.len() as i32 + changed_count;
+    if !dry_run {
+        result.hashed_photos_count += changed_count;
+    }
+    result.plan.would_move.extend(fingerprint_moves);
+    result.plan.would_update = changed_paths;
+
+    println!("Delete photos if necessary...");
+    // check if any photos have been deleted in directory. Files that are about to be moved are
+    // only missing at their old path, so they are not deleted.
+    let moved_from: HashSet<&str> = result
+        .plan
+        .would_move
+        .iter()
+        .map(|planned| planned.from.as_str())
+        .collect();
+    let mut deleted: Vec<String> = Vec::new();
+    for dir in &dirs {
+        deleted.extend(
+            check_for_deleted_files_in_dir(dir, pool, progress)
+                .await?
+                .into_iter()
+                .filter(|path| !moved_from.contains(path.as_str())),
+        );
+    }
+
+    if !dry_run {
+        Photo::delete_photos_by_path(&deleted, pool).await?;
+    }
+
+    result.deleted_photos_count = deleted.len() as i32;
+    result.plan.would_delete = deleted;
+
+    if !dry_run {
+        ScanIssue::record(&collect_issues, pool).await?;
+        ScanIssue::record(&changed_issues, pool).await?;
+        for dir in &dirs {
+            ScanIssue::clear_missing(dir, pool).await?;
+        }
+    }
+
+    result.issues.extend(collect_issues);
+    result.issues.extend(changed_issues);
+    if !result.issues.is_empty() {
+        println!(
+            "Quarantined {} files.",
+            result.issues.len().to_formatted_string(&Locale::en)
+        );
+    }
+    result.plan.would_quarantine = result.issues.clone();
+
+    if dry_run {
+        return Ok(result);
+    }
+
+    for dir in &dirs {
+        backfill_exif(dir, pool, progress).await?;
+        backfill_heif_dimensions(dir, pool, progress).await?;
+        backfill_perceptual_hashes(dir, pool, progress).await?;
+    }
+
+    Ok(result)
+}
+
+/// Validates and hashes files that are not yet in the database, checks them for duplicates and
+/// moves, and returns the photos that still need to be inserted. Files that fail validation are
+/// returned in `issues`. With `dry_run` duplicates, moves and issues are only reported.
+pub async fn process_new_files(
+    files: Vec<FileInfo>,
+    pool: &Pool,
+    progress: &Arc<ScanProgress>,
+    dry_run: bool,
+) -> Result<FileScanResult, ServiceError> {
+    let hashed = hash_new_files(files, pool, progress).await?;
+
+    import_hashed_files(hashed, pool, progress, dry_run).await
+}
+
+/// New files that were validated and hashed, but not yet checked against the database
+struct HashedFiles {
+    photos: Vec<NewPhoto>,
+    issues: Vec<FileIssue>,
+    /// Files skipped because their issue was ignored
+    quarantined: usize,
+}
+
+/// Validates and hashes new files. Nothing is written to the database.
+async fn hash_new_files(
+    mut files: Vec<FileInfo>,
+    pool: &Pool,
+    progress: &Arc<ScanProgress>,
+) -> Result<HashedFiles, ServiceError> {
+    let mut quarantined = 0;
+
+    // files whose issue was ignored are skipped until the issue is retried
+    let ignored = ScanIssue::get_ignored_paths(pool).await?;
+    if !ignored.is_empty() {
+        let candidates = files.len();
+        files.retain(|f| !ignored.contains(&f.file_path));
+        quarantined = candidates - files.len();
+    }
+
     println!("Build list of new photo candidates...");
     // build list of new photo candidates
-    let mut photos: Vec<NewPhoto> = files
-        .par_iter()
-        .map(|f| NewPhoto::new(f.file_path.to_string(), f.date_created))
+    progress.set_phase(ScanPhase::Hashing, files.len());
+    let validated: Vec<Result<NewPhoto, FileIssue>> =
+        process_in_parallel(files, progress, |f| Some(NewPhoto::new(f))).await?;
+    let (photos, issues): (Vec<_>, Vec<_>) = validated.into_iter().partition(|r| r.is_ok());
+
+    Ok(HashedFiles {
+        photos: photos.into_iter().filter_map(Result::ok).collect(),
+        issues: issues.into_iter().filter_map(Result::err).collect(),
+        quarantined,
+    })
+}
+
+/// Checks hashed files for duplicates and moves, see `process_new_files`
+async fn import_hashed_files(
+    hashed: HashedFiles,
+    pool: &Pool,
+    progress: &Arc<ScanProgress>,
+    dry_run: bool,
+) -> Result<FileScanResult, ServiceError> {
+    let mut result: FileScanResult = Default::default();
+    let mut photos = hashed.photos;
+    result.issues = hashed.issues;
+    result.excluded.quarantined = hashed.quarantined;
+    result.hashed_photos_count = photos.len() as i32;
+
+    for issue in &result.issues {
+        println!("Quarantined {}: {}", issue.file_path, issue.error);
+    }
+
+    if !dry_run {
+        let validated_paths: Vec<String> =
+            photos.iter().map(|p| p.file_path.to_owned()).collect();
+        ScanIssue::record(&result.issues, pool).await?;
+        ScanIssue::clear(&validated_paths, pool).await?;
+    }
+
+    // every photo that shares a hash with a new one is loaded at once, both the duplicate check
+    // and the move detection work off of it
+    let hashes: Vec<String> = photos
+        .iter()
+        .map(|p| p.file_hash.to_owned())
+        .collect::<BTreeSet<String>>()
+        .into_iter()
         .collect();
+    let existing_by_hash = Photo::get_by_hashes(&hashes, pool).await?;
 
     println!("Check for duplicate photos...");
-    let duplicate_photos: Vec<DuplicatePhoto> = check_for_duplicates(&photos, pool).await?;
+    progress.set_phase(ScanPhase::DuplicateCheck, photos.len());
+    let duplicate_photos = check_for_duplicates(&photos, &existing_by_hash, progress)?;
 
     if !duplicate_photos.is_empty() {
         println!("Number of duplicates: {}", &duplicate_photos.len());
@@ -134,94 +516,273 @@ pub async fn scan_all_photos_from_dir(
                 println!("{}", file);
             }
         }
-        return Err(DuplicateFileError(duplicate_photos));
+
+        // record the duplicates for review rather than aborting the whole scan
+        if !dry_run {
+            DuplicateGroup::record(&duplicate_photos, pool).await?;
+        }
+    } else {
+        println!("No duplicate photos found.");
     }
-    println!("No duplicate photos found.");
+    result.duplicate_groups_count = duplicate_photos.len() as i32;
 
     println!("Check for moved photos...");
-    // check if any photos have been moved
-    let mut updated_photos: Vec<NewPhoto> = Vec::new();
+    // a new file is a moved photo if a photo with the same hash is no longer where the database
+    // says it is. The file name is not compared, so a file that was moved and renamed is still
+    // matched. If several photos with the hash went missing, one with the same name is preferred.
+    progress.set_phase(ScanPhase::MoveDetection, photos.len());
+    let mut claimed: HashSet<i32> = HashSet::new();
+    let mut moves: Vec<(i32, String)> = Vec::new();
     for new_photo in &photos {
-        let name = &new_photo.file_name;
-        let hash = &new_photo.file_hash;
+        progress.check_cancelled()?;
+        progress.advance(1);
+
+        let missing: Vec<&Photo> = existing_by_hash
+            .get(&new_photo.file_hash)
+            .map(|existing| existing.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .filter(|photo| !claimed.contains(&photo.id) && !Path::new(&photo.file_path).exists())
+            .collect();
+
+        let moved_photo = missing
+            .iter()
+            .find(|photo| photo.file_name == new_photo.file_name)
+            .or_else(|| missing.first());
+
+        if let Some(moved_photo) = moved_photo {
+            result.plan.would_move.push(PlannedMove::new(
+                &moved_photo.file_path,
+                &new_photo.file_path,
+            ));
+
+            claimed.insert(moved_photo.id);
+            moves.push((moved_photo.id, new_photo.file_path.to_owned()));
+        }
+    }
+
+    if !dry_run && !moves.is_empty() {
+        Photo::update_file_paths(&moves, pool).await?;
+    }
 
-        if check_if_photo_exists_by_file(name, hash, &pool).await? {
-            // Photo exists, so we can retrieve it without checking for null
-            let mut photo_to_update = Photo::get_photo_by_name(name, hash, pool).await?;
+    let moved_paths: HashSet<String> = moves.into_iter().map(|(_, path)| path).collect();
+    result.updated_photos_count = moved_paths.len() as i32;
+
+    if !moved_paths.is_empty() {
+        println!("Remove moved photos from new photos collection...");
+        photos.retain(|p| !moved_paths.contains(&p.file_path));
+    }
+
+    result.new_photos_count = photos.len() as i32;
+    result.plan.would_insert = photos.iter().map(|p| p.file_path.to_owned()).collect();
+    result.new_photos = photos;
+
+    Ok(result)
+}
+
+/// Re-hashes files that were modified in place and stores their new hash and fingerprint. Files
+/// that no longer pass validation keep their old contents in the database and are returned as
+/// issues.
+pub async fn update_changed_photos(
+    changed_files: Vec<(i32, FileInfo)>,
+    pool: &Pool,
+    progress: &Arc<ScanProgress>,
+) -> Result<(i32, Vec<FileIssue>), ServiceError> {
+    let rehashed = hash_changed_files(changed_files, progress).await?;
+
+    store_changed_files(rehashed, pool).await
+}
 
-            // Update the file path of the existing photo with the file path of the "new" photo
-            photo_to_update.file_path = new_photo.to_owned().file_path;
+/// Re-hashes files that were modified in place. Nothing is written to the database.
+async fn hash_changed_files(
+    changed_files: Vec<(i32, FileInfo)>,
+    progress: &Arc<ScanProgress>,
+) -> Result<Vec<Result<(i32, NewPhoto), FileIssue>>, ServiceError> {
+    if changed_files.is_empty() {
+        return Ok(Vec::new());
+    }
 
-            // Save the changes to the database
-            Photo::update_photo(photo_to_update, pool).await?;
+    println!("Re-hash modified photos...");
+    progress.set_phase(ScanPhase::Hashing, changed_files.len());
+    process_in_parallel(changed_files, progress, |(id, f)| {
+        Some(NewPhoto::new(f).map(|photo| (*id, photo)))
+    })
+    .await
+}
 
-            updated_photos.push(new_photo.to_owned())
+/// Stores the new contents of re-hashed files, see `update_changed_photos`
+async fn store_changed_files(
+    rehashed: Vec<Result<(i32, NewPhoto), FileIssue>>,
+    pool: &Pool,
+) -> Result<(i32, Vec<FileIssue>), ServiceError> {
+    let mut changed = 0;
+    let mut issues = Vec::new();
+    for result in rehashed {
+        match result {
+            Ok((id, photo)) => {
+                Photo::update_file_contents(id, &photo, pool).await?;
+                changed += 1;
+            }
+            Err(issue) => issues.push(issue),
         }
     }
-    result.new_photos_count = photos.len() as i32;
-    result.updated_photos_count = updated_photos.len() as i32;
-    result.new_photos = photos.clone();
 
-    println!("Delete photos if necessary...");
-    // check if any photos have been deleted in directory
-    result.deleted_photos_count = check_for_deleted_files_in_dir(&dir, pool).await? as i32;
+    Ok((changed, issues))
+}
 
-    // If no photos have been updated, return early
-    if updated_photos.is_empty() {
-        return Ok(result);
+/// Reads EXIF metadata for photos that were imported before it was extracted
+async fn backfill_exif(
+    dir: &str,
+    pool: &Pool,
+    progress: &Arc<ScanProgress>,
+) -> Result<(), ServiceError> {
+    let missing = Photo::get_without_exif(dir, pool).await?;
+
+    if missing.is_empty() {
+        return Ok(());
     }
 
-    println!("Remove updated photos from new photos collection...");
-    // Remove updated photos from new photos collection
-    for item in updated_photos {
-        photos.retain(|p| p.file_name != item.file_name && p.file_hash != item.file_hash)
+    println!("Read EXIF metadata of {} photos...", missing.len());
+    progress.set_phase(ScanPhase::ExifExtraction, missing.len());
+    let metadata: Vec<(i32, ExifMetadata)> = process_in_parallel(missing, progress, |(id, path)| {
+        Some((*id, ExifMetadata::read(path)))
+    })
+    .await?;
+
+    Photo::update_exif(&metadata, pool).await?;
+
+    Ok(())
+}
+
+/// Reads the dimensions of HEIF photos that were imported while HEIF could not be read yet
+async fn backfill_heif_dimensions(
+    dir: &str,
+    pool: &Pool,
+    progress: &Arc<ScanProgress>,
+) -> Result<(), ServiceError> {
+    let missing = Photo::get_heif_without_dimensions(dir, pool).await?;
+
+    if missing.is_empty() {
+        return Ok(());
     }
 
-    result.new_photos = photos;
+    println!("Read dimensions of {} HEIF photos...", missing.len());
+    progress.set_phase(ScanPhase::HeifDimensions, missing.len());
+    let dimensions: Vec<(i32, HeifInfo)> = process_in_parallel(missing, progress, |(id, path)| {
+        HeifInfo::read(path).map(|info| (*id, info))
+    })
+    .await?;
 
-    Ok(result)
+    Photo::update_heif_dimensions(&dimensions, pool).await?;
+
+    Ok(())
+}
+
+/// Computes perceptual hashes for photos that were imported before they were stored
+async fn backfill_perceptual_hashes(
+    dir: &str,
+    pool: &Pool,
+    progress: &Arc<ScanProgress>,
+) -> Result<(), ServiceError> {
+    let missing: Vec<(i32, String)> = Photo::get_without_perceptual_hash(dir, pool)
+        .await?
+        .into_iter()
+        .filter(|(_, path)| phash::can_decode(path))
+        .collect();
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    println!("Compute {} missing perceptual hashes...", missing.len());
+    progress.set_phase(ScanPhase::PerceptualHashing, missing.len());
+    let hashes: Vec<(i32, i64)> = process_in_parallel(missing, progress, |(id, path)| {
+        phash::perceptual_hash(path).map(|hash| (*id, hash))
+    })
+    .await?;
+
+    Photo::update_perceptual_hashes(&hashes, pool).await?;
+
+    Ok(())
 }
 
-async fn check_for_duplicates(
+/// Generates the thumbnails of newly imported photos, so that they are ready before the photos are
+/// first viewed. Photos that fail are left for the thumbnails to be generated on request.
+pub async fn generate_thumbnails(
     new_photos: &[NewPhoto],
     pool: &Pool,
-) -> Result<Vec<DuplicatePhoto>, ServiceError> {
-    let mut duplicate_photos = Vec::new();
+    progress: &Arc<ScanProgress>,
+) -> Result<(), ServiceError> {
+    let libraries = Library::get_all(pool).await?;
+
+    let files: Vec<(PathBuf, String, String, i32)> = new_photos
+        .iter()
+        .filter(|photo| thumbnails::can_generate(&photo.file_path))
+        .map(|photo| {
+            (
+                Library::root_of(&libraries, &photo.file_path),
+                photo.file_path.to_owned(),
+                photo.file_hash.to_owned(),
+                photo.rotation,
+            )
+        })
+        .collect();
+
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    println!("Generate thumbnails of {} photos...", files.len());
+    progress.set_phase(ScanPhase::Thumbnails, files.len());
+    let created: Vec<usize> =
+        process_in_parallel(files, progress, |(root, path, hash, rotation)| {
+            // new photos have no focal point yet
+            thumbnails::create_all(root, path, hash, *rotation, Framing::default().focal_point)
+                .map_err(|err| println!("Unable to generate thumbnails of {}: {}", path, err))
+                .ok()
+        })
+        .await?;
 
+    println!("{} thumbnails generated.", created.iter().sum::<usize>());
+
+    Ok(())
+}
+
+/// Finds new photos that share a hash with another new photo or with a photo already in the
+/// database. Rows whose files no longer exist are left out of the report, removing them is up to
+/// the deletion check.
+fn check_for_duplicates(
+    new_photos: &[NewPhoto],
+    existing_by_hash: &HashMap<String, Vec<Photo>>,
+    progress: &ScanProgress,
+) -> Result<DuplicatePhotos, ServiceError> {
+    let mut paths_by_hash: BTreeMap<&str, BTreeSet<String>> = BTreeMap::new();
     for new_photo in new_photos {
-        let hash = &new_photo.file_hash;
-        let file_path = &new_photo.file_path;
+        paths_by_hash
+            .entry(&new_photo.file_hash)
+            .or_default()
+            .insert(new_photo.file_path.to_string());
+    }
 
-        let mut duplicates = check_for_duplicate(hash, pool).await?;
+    let mut duplicate_photos = Vec::new();
 
-        if duplicates.is_empty() {
-            continue;
-        }
+    for (hash, mut paths) in paths_by_hash {
+        progress.check_cancelled()?;
+        progress.advance(paths.len());
 
         // check if both duplicates haven't been deleted
-        for duplicate in duplicates {
-            let exists = Path::new(&duplicate).exists();
-
-            if !exists {
-                Photo::delete_photo_by_path(&duplicate, pool).await?;
+        for duplicate in existing_by_hash.get(hash).into_iter().flatten() {
+            if Path::new(&duplicate.file_path).exists() {
+                paths.insert(duplicate.file_path.to_owned());
             }
         }
 
-        // check again for duplicates
-        duplicates = check_for_duplicate(hash, pool).await?;
-
-        if duplicates.is_empty() {
+        if paths.len() < 2 {
             continue;
         }
 
         // Duplicates exists
-
-        if !duplicates.contains(file_path) {
-            duplicates.push(file_path.to_string());
-        }
-        duplicates.sort();
-
-        let duplicate = DuplicatePhoto::new(hash, duplicates);
+        let duplicate = DuplicatePhoto::new(hash, paths.into_iter().collect());
 
         duplicate_photos.push(duplicate);
     }
@@ -229,36 +790,48 @@ async fn check_for_duplicates(
     Ok(duplicate_photos)
 }
 
-async fn check_for_duplicate(hash: &str, pool: &Pool) -> Result<Vec<String>, ServiceError> {
-    let client = pool.get().await?;
-    let stmt = client
-        .prepare("select file_path from photos where file_hash = $1")
-        .await?;
-    let results = client.query(&stmt, &[&hash]).await?;
-
-    let mut paths = Vec::new();
-
-    if results.is_empty() {
-        return Ok(paths);
-    }
+/// Walks `dir` and returns the files that pass the scan rules. Hidden folders are skipped without
+/// being walked. Entries that cannot be read are added to `issues`.
+fn collect_files_from_directory(
+    dir: &str,
+    rules: &mut ScanRules,
+    issues: &mut Vec<FileIssue>,
+    progress: &ScanProgress,
+) -> FileCollectionResult {
+    let mut files = Vec::new();
+    let mut hidden = 0;
 
-    paths = results.into_iter().map(|row| row.get(0)).collect();
+    let walker = WalkDir::new(dir).into_iter();
+    let entries = walker.filter_entry(|e| {
+        if !is_hidden(e) {
+            return true;
+        }
 
-    Ok(paths)
-}
+        // ignore files are hidden too, but are not what anyone would expect to be counted
+        if e.file_name() != IGNORE_FILE_NAME {
+            hidden += 1;
+        }
 
-async fn collect_files_from_directory(dir: &str, pool: &Pool) -> FileCollectionResult {
-    let mut files = Vec::new();
+        false
+    });
 
-    let image_file_extensions = vec![
-        "jpg", "jpeg", "png", "gif", "bmp", "ico", "tiff", "webp", "pnm", "heic",
-    ];
+    for entry in entries {
+        progress.check_cancelled()?;
 
-    let mut existing_files = 0;
-    let walker = WalkDir::new(dir).into_iter();
-    for entry in walker.filter_entry(|e| !is_hidden(e)) {
-        let entry = entry.unwrap();
-        let metadata = entry.metadata().unwrap();
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                issues.push(walk_issue(dir, err));
+                continue;
+            }
+        };
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                issues.push(walk_issue(dir, err));
+                continue;
+            }
+        };
 
         // We don't care about directories, just the files inside them
         // so we'll skip them
@@ -266,27 +839,33 @@ async fn collect_files_from_directory(dir: &str, pool: &Pool) -> FileCollectionR
             continue;
         }
 
-        let file_info = FileInfo::new_from_entry(&entry);
+        progress.advance(1);
 
-        // Skip the file if we don't consider it to be an image
-        if !image_file_extensions.contains(&file_info.file_extension.as_str()) {
+        // Skip the file if the scan settings or an ignore file exclude it
+        if !rules.includes(entry.path()) {
             continue;
         }
 
-        // Check if the file is currently in the database
-        // if yes, increment counter
-        if is_in_db(&file_info, &pool).await? {
-            existing_files += 1;
-            continue;
+        match FileInfo::new_from_entry(&entry) {
+            Ok(file) => files.push(file),
+            Err(issue) => issues.push(issue),
         }
-
-        // Assume this is a new image
-        files.push(file_info)
     }
 
+    rules.excluded.hidden += hidden;
+
     files.sort_by(|a, b| a.file_path.to_lowercase().cmp(&b.file_path.to_lowercase()));
 
-    Ok((files, existing_files))
+    Ok(files)
+}
+
+fn walk_issue(dir: &str, err: walkdir::Error) -> FileIssue {
+    let path = err
+        .path()
+        .map(|path| path.to_string_lossy().to_string())
+        .unwrap_or_else(|| dir.to_string());
+
+    FileIssue::new(&path, IssueKind::Unreadable, err)
 }
 
 fn is_hidden(entry: &DirEntry) -> bool {
@@ -297,45 +876,38 @@ fn is_hidden(entry: &DirEntry) -> bool {
         .unwrap_or(false)
 }
 
-async fn is_in_db(file_info: &FileInfo, pool: &Pool) -> Result<bool, PoolError> {
-    let client = pool.get().await?;
-    let stmt = client
-        .prepare(
-            "select count(*)
-                                                from photos
-                                                where file_path = $1",
-        )
-        .await?;
-
-    let result = client.query_one(&stmt, &[&file_info.file_path]).await?;
-    let count: i64 = result.get(0);
-
-    Ok(count > 0)
+/// Checks every component of `path`, since a file inside a hidden directory is also hidden
+pub fn is_hidden_path(path: &Path) -> bool {
+    path.components().any(|component| {
+        component
+            .as_os_str()
+            .to_str()
+            .map(|s| s.starts_with('.') && s != "." && s != "..")
+            .unwrap_or(false)
+    })
 }
 
-async fn check_if_photo_exists_by_file(
-    name: &str,
-    hash: &str,
+/// Returns the paths of photos in `dir` whose files no longer exist. Nothing is deleted here, see
+/// `Photo::delete_photos_by_path`.
+pub async fn check_for_deleted_files_in_dir(
+    dir: &str,
     pool: &Pool,
-) -> Result<bool, ServiceError> {
-    let client = pool.get().await?;
-    let stmt = client.prepare("select count(file_hash)
-                                                                from photos
-                                                                where file_hash = $1 and file_name = $2").await?;
-    let result = client.query_one(&stmt, &[&hash, &name]).await?;
-    let count: i64 = result.get(0);
-
-    Ok(count > 0)
-}
-
-async fn check_for_deleted_files_in_dir(dir: &str, pool: &Pool) -> Result<usize, ServiceError> {
+    progress: &ScanProgress,
+) -> Result<Vec<String>, ServiceError> {
     let mut deleted_files: Vec<String> = Vec::new();
 
     let client = pool.get().await?;
 
-    // get file paths
+    // get file paths. Only `dir` itself and paths below it match, not every path that happens to
+    // contain it, since another library may well have a similar name. Trashed photos are left to
+    // the trash, their files live in its directory.
+    let dir = dir.trim_end_matches('/');
     let file_paths_stmt = client
-        .prepare("SELECT file_path FROM photos WHERE file_path LIKE '%' || $1 || '%'")
+        .prepare(
+            "SELECT file_path FROM photos \
+             WHERE (file_path = $1 OR left(file_path, length($1) + 1) = $1 || '/') \
+               AND date_trashed IS NULL",
+        )
         .await?;
     let results = client.query(&file_paths_stmt, &[&dir]).await?;
     let file_paths = results
@@ -343,17 +915,80 @@ async fn check_for_deleted_files_in_dir(dir: &str, pool: &Pool) -> Result<usize,
         .map(|row| row.get(0))
         .collect::<Vec<String>>();
 
+    progress.set_phase(ScanPhase::DeletionCheck, file_paths.len());
+
     // iterate through files and check if any have been deleted
     for file in &file_paths {
+        progress.check_cancelled()?;
+        progress.advance(1);
+
         let exists = Path::new(file).exists();
 
         if !exists {
             deleted_files.push(file.to_owned());
-
-            // Remove file from database
-            Photo::delete_photo_by_path(file, pool).await?;
         }
     }
 
-    Ok(deleted_files.len())
+    Ok(deleted_files)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, process};
+
+    use super::*;
+    use crate::utils::http_server;
+
+    #[test]
+    fn reads_file_extension() {
+        let extension = |path: &str| get_file_extension(Path::new(path)).unwrap();
+
+        assert_eq!(extension("/photos/IMG_0001.JPG"), "JPG");
+        assert_eq!(extension("/photos/archive.tar.gz"), "gz");
+        assert_eq!(extension("/photos/README"), "");
+        assert_eq!(extension("/photos/.hidden"), "");
+    }
+
+    #[test]
+    fn reports_path_without_file_name() {
+        let issue = FileInfo::new_from_path(Path::new("/")).err().unwrap();
+
+        assert_eq!(issue.kind.as_str(), "unreadable");
+    }
+
+    /// Needs the database configured in `.env`, run with `cargo test -- --ignored`
+    #[actix_rt::test]
+    #[ignore]
+    async fn keeps_trashed_photos_when_scanning() {
+        dotenv::dotenv().ok();
+        let pool = http_server::create_pool();
+        let client = pool.get().await.unwrap();
+
+        let dir = env::temp_dir().join(format!("scarlett-trash-scan-{}", process::id()));
+        let trash_path = dir.join(".trash").join("photo.jpg");
+        fs::create_dir_all(trash_path.parent().unwrap()).unwrap();
+        fs::write(&trash_path, b"trashed").unwrap();
+
+        let dir = dir.to_str().unwrap().to_string();
+        let trash_path = trash_path.to_str().unwrap().to_string();
+        let photo_id: i32 = client
+            .query_one(
+                "INSERT INTO photos (file_path, file_name, trashed_from, date_trashed) \
+                 VALUES ($1, 'photo.jpg', $2, current_timestamp) RETURNING id",
+                &[&trash_path, &format!("{}/photo.jpg", dir)],
+            )
+            .await
+            .unwrap()
+            .get(0);
+
+        let progress = Arc::new(ScanProgress::default());
+        let result = scan_all_photos_from_dir(&dir, &pool, &progress, false).await;
+        let trashed = Photo::get_by_id(photo_id, &pool).await;
+
+        let _ = Photo::delete_photos(&[photo_id], &pool).await;
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(result.unwrap().deleted_photos_count, 0);
+        assert_eq!(trashed.unwrap().file_path, trash_path);
+    }
 }
@@ -0,0 +1,529 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageOutputFormat};
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::errors::ServiceError;
+use crate::files::phash;
+
+/// Numbers the partial files of images being written, so that two threads rendering the same image
+/// do not write into the same file
+static PARTIAL_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// RENDITION ***************************************************************************************
+
+/// How the image is fitted into the requested box. `Contain` keeps the whole image and scales it
+/// down until it fits, `Cover` fills the box and crops whatever sticks out.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fit {
+    Contain,
+    Cover,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Jpeg,
+    Png,
+    Webp,
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Png => "png",
+            OutputFormat::Webp => "webp",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::Png => "image/png",
+            OutputFormat::Webp => "image/webp",
+        }
+    }
+}
+
+/// A resized and re-encoded version of a photo. The size applies to the photo as displayed, i.e.
+/// after its rotation, a missing side is not constrained.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rendition {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fit: Fit,
+    /// What `Cover` centers the crop on, see `Framing`
+    pub focal_point: (f64, f64),
+    pub format: OutputFormat,
+    /// Only used for JPEG and WebP
+    pub quality: u8,
+}
+
+impl Rendition {
+    /// Identifies the rendition within the renditions of a file, every parameter is part of it
+    pub fn cache_key(&self) -> String {
+        let side = |side: Option<u32>| side.map(|px| px.to_string()).unwrap_or_default();
+        let fit = match self.fit {
+            Fit::Contain => "contain".to_string(),
+            Fit::Cover => format!("cover-f{:.3}x{:.3}", self.focal_point.0, self.focal_point.1),
+        };
+        let quality = match self.format {
+            OutputFormat::Jpeg | OutputFormat::Webp => format!("-q{}", self.quality),
+            OutputFormat::Png => String::new(),
+        };
+
+        format!(
+            "w{}-h{}-{}{}.{}",
+            side(self.width),
+            side(self.height),
+            fit,
+            quality,
+            self.format.extension()
+        )
+    }
+
+    fn apply(&self, image: &DynamicImage) -> DynamicImage {
+        let (width, height) = image.dimensions();
+
+        match (self.fit, self.width, self.height) {
+            (Fit::Cover, Some(box_width), Some(box_height)) => cover_crop(
+                image,
+                box_width,
+                box_height,
+                self.focal_point,
+                FilterType::Triangle,
+            ),
+            _ => {
+                // photos are never scaled up to fit a box larger than themselves
+                let box_width = self.width.unwrap_or(width);
+                let box_height = self.height.unwrap_or(height);
+
+                if width <= box_width && height <= box_height {
+                    image.clone()
+                } else {
+                    image.resize(box_width, box_height, FilterType::Triangle)
+                }
+            }
+        }
+    }
+
+    fn save(&self, image: &DynamicImage, path: &Path) -> Result<(), ServiceError> {
+        match self.format {
+            OutputFormat::Jpeg => {
+                save_atomically(image, path, ImageOutputFormat::Jpeg(self.quality))
+            }
+            OutputFormat::Png => save_atomically(image, path, ImageOutputFormat::Png),
+            OutputFormat::Webp => save_webp_atomically(image, path, self.quality),
+        }
+    }
+}
+
+/// Returns the directory renditions of the library at `library_root` are cached in, read from
+/// `SCARLETT_MEDIA_CACHE_DIR`. Defaults to a hidden folder inside the library root, which scans
+/// skip.
+pub fn media_cache_dir(library_root: &Path) -> PathBuf {
+    env::var("SCARLETT_MEDIA_CACHE_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| library_root.join(".media-cache"))
+}
+
+/// Returns where a rendition of a file is cached. Like thumbnails, renditions are keyed by the hash
+/// and rotation of the file.
+pub fn rendition_path(
+    library_root: &Path,
+    file_hash: &str,
+    rotation: i32,
+    rendition: &Rendition,
+) -> PathBuf {
+    let prefix = file_hash.get(..2).unwrap_or(file_hash);
+
+    media_cache_dir(library_root).join(prefix).join(format!(
+        "{}-r{}-{}",
+        file_hash,
+        rotation,
+        rendition.cache_key()
+    ))
+}
+
+/// Returns the cached rendition of a file, rendering it first if it does not exist yet
+pub fn get_or_create(
+    library_root: &Path,
+    file_path: &str,
+    file_hash: &str,
+    rotation: i32,
+    rendition: &Rendition,
+) -> Result<PathBuf, ServiceError> {
+    let path = rendition_path(library_root, file_hash, rotation, rendition);
+
+    if path.exists() {
+        // the modification time doubles as the last access, which eviction goes by
+        if let Ok(file) = File::open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+    } else {
+        let image = open_upright(file_path, rotation)?;
+        rendition.save(&rendition.apply(&image), &path)?;
+    }
+
+    Ok(path)
+}
+
+// EVICTION ****************************************************************************************
+
+/// Returns how many bytes of renditions a media cache directory may hold, read from
+/// `SCARLETT_MEDIA_CACHE_MAX_BYTES`. Defaults to 2 GiB, `0` lets the cache grow without bound.
+pub fn max_cache_bytes() -> Option<u64> {
+    let max_bytes = env::var("SCARLETT_MEDIA_CACHE_MAX_BYTES")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(2 * 1024 * 1024 * 1024);
+
+    Some(max_bytes).filter(|max_bytes| *max_bytes > 0)
+}
+
+/// Deletes the least recently used renditions of a media cache directory until it holds no more
+/// than `max_bytes`. Returns the number of renditions deleted.
+pub fn evict(cache_dir: &Path, max_bytes: u64) -> Result<usize, ServiceError> {
+    let mut renditions: Vec<(SystemTime, u64, PathBuf)> = WalkDir::new(cache_dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        // renditions still being written are left alone
+        .filter(|entry| entry.path().extension() != Some("partial".as_ref()))
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            let modified = metadata.modified().ok()?;
+
+            Some((modified, metadata.len(), entry.into_path()))
+        })
+        .collect();
+
+    let mut total: u64 = renditions.iter().map(|(_, size, _)| size).sum();
+    if total <= max_bytes {
+        return Ok(0);
+    }
+
+    renditions.sort();
+
+    let mut evicted = 0;
+    for (_, size, path) in renditions {
+        if total <= max_bytes {
+            break;
+        }
+
+        match fs::remove_file(&path) {
+            Ok(_) => {
+                total -= size;
+                evicted += 1;
+            }
+            Err(err) => println!("Unable to evict {}: {}", path.display(), err),
+        }
+    }
+
+    Ok(evicted)
+}
+
+// FRAMING *****************************************************************************************
+
+/// A rectangle of a photo, given as fractions of the width and height of the upright photo
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct CropRect {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl CropRect {
+    /// Returns the `(x, y, width, height)` of the rectangle in pixels of an image of the given
+    /// size. The rectangle is at least one pixel and never reaches past the edges of the image.
+    pub fn to_pixels(&self, image_width: u32, image_height: u32) -> (u32, u32, u32, u32) {
+        let to_pixels = |fraction: f64, size: u32| (fraction * f64::from(size)).round() as u32;
+
+        let x = to_pixels(self.x, image_width).min(image_width - 1);
+        let y = to_pixels(self.y, image_height).min(image_height - 1);
+        let width = to_pixels(self.width, image_width).clamp(1, image_width - x);
+        let height = to_pixels(self.height, image_height).clamp(1, image_height - y);
+
+        (x, y, width, height)
+    }
+}
+
+/// Which part of a photo a cropped rendition shows. A crop rectangle takes precedence over the
+/// focal point.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Framing {
+    pub focal_point: (f64, f64),
+    pub crop: Option<CropRect>,
+}
+
+impl Default for Framing {
+    fn default() -> Self {
+        Framing {
+            focal_point: (0.5, 0.5),
+            crop: None,
+        }
+    }
+}
+
+impl Framing {
+    /// Returns the part of the image the framing draws from, the whole image without a crop
+    pub fn region(&self, image: &DynamicImage) -> DynamicImage {
+        match self.crop {
+            Some(crop) => {
+                let (image_width, image_height) = image.dimensions();
+                let (x, y, width, height) = crop.to_pixels(image_width, image_height);
+
+                DynamicImage::ImageRgba8(image.view(x, y, width, height).to_image())
+            }
+            None => image.clone(),
+        }
+    }
+
+    /// Fills `width` x `height` with the framed part of the image
+    pub fn apply(
+        &self,
+        image: &DynamicImage,
+        width: u32,
+        height: u32,
+        filter: FilterType,
+    ) -> DynamicImage {
+        match self.crop {
+            // the aspect ratio of a crop chosen by hand rarely matches exactly, what is left over
+            // is cropped evenly from both sides
+            Some(_) => cover_crop(&self.region(image), width, height, (0.5, 0.5), filter),
+            None => cover_crop(image, width, height, self.focal_point, filter),
+        }
+    }
+}
+
+/// Scales the image to fill `width` x `height` and crops whatever sticks out. The crop is centered
+/// on `focal_point`, given as fractions of the width and height of the image, as far as the edges
+/// of the image allow.
+pub fn cover_crop(
+    image: &DynamicImage,
+    width: u32,
+    height: u32,
+    focal_point: (f64, f64),
+    filter: FilterType,
+) -> DynamicImage {
+    let (image_width, image_height) = image.dimensions();
+    let scale = f64::max(
+        f64::from(width) / f64::from(image_width),
+        f64::from(height) / f64::from(image_height),
+    );
+
+    // size of the crop in the coordinates of the image
+    let crop_width = ((f64::from(width) / scale).round() as u32).clamp(1, image_width);
+    let crop_height = ((f64::from(height) / scale).round() as u32).clamp(1, image_height);
+
+    let start = |focus: f64, crop: u32, size: u32| {
+        let start = focus * f64::from(size) - f64::from(crop) / 2.0;
+        start.max(0.0).min(f64::from(size - crop)).round() as u32
+    };
+    let x = start(focal_point.0, crop_width, image_width);
+    let y = start(focal_point.1, crop_height, image_height);
+
+    let crop = image.view(x, y, crop_width, crop_height).to_image();
+
+    DynamicImage::ImageRgba8(crop).resize_exact(width, height, filter)
+}
+
+// DECODING & ENCODING *****************************************************************************
+
+/// Decodes a photo and rotates it by its stored rotation so that it is upright
+pub fn open_upright(file_path: &str, rotation: i32) -> Result<DynamicImage, ServiceError> {
+    if !phash::can_decode(file_path) {
+        return Err(ServiceError::BadRequest(format!(
+            "{} cannot be decoded to be resized",
+            file_path
+        )));
+    }
+
+    if !Path::new(file_path).exists() {
+        return Err(ServiceError::NotFound(format!(
+            "{} does not exist",
+            file_path
+        )));
+    }
+
+    let image = image::open(file_path).map_err(|err| {
+        println!("Unable to decode {}: {}", file_path, err);
+        ServiceError::InternalServerError
+    })?;
+
+    let image = match rotation {
+        90 => image.rotate90(),
+        180 => image.rotate180(),
+        270 => image.rotate270(),
+        _ => image,
+    };
+
+    Ok(image)
+}
+
+/// Encodes the image next to its final location and moves it into place, so that a request
+/// arriving while it is written never reads a partial file
+pub fn save_atomically(
+    image: &DynamicImage,
+    path: &Path,
+    format: ImageOutputFormat,
+) -> Result<(), ServiceError> {
+    // JPEG has no alpha channel
+    let rgb;
+    let image = if let ImageOutputFormat::Jpeg(_) = format {
+        rgb = DynamicImage::ImageRgb8(image.to_rgb());
+        &rgb
+    } else {
+        image
+    };
+
+    write_atomically(path, |writer| image.write_to(writer, format))
+}
+
+/// Encodes the image as lossy WebP, which the `image` crate cannot encode, see `save_atomically`
+pub fn save_webp_atomically(
+    image: &DynamicImage,
+    path: &Path,
+    quality: u8,
+) -> Result<(), ServiceError> {
+    let rgba = image.to_rgba();
+    let (width, height) = rgba.dimensions();
+
+    let webp = webp::Encoder::from_rgba(&rgba, width, height)
+        .encode_simple(false, f32::from(quality))
+        .map_err(|err| {
+            println!("Unable to encode {}: {:?}", path.display(), err);
+            ServiceError::InternalServerError
+        })?;
+
+    write_atomically(path, |writer| writer.write_all(&webp))
+}
+
+fn write_atomically<F, E>(path: &Path, encode: F) -> Result<(), ServiceError>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<(), E>,
+    E: std::fmt::Display,
+{
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let partial = PARTIAL_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let partial_path = path.with_extension(format!("{}-{}.partial", std::process::id(), partial));
+    let mut writer = BufWriter::new(File::create(&partial_path)?);
+    encode(&mut writer).map_err(|err| {
+        println!("Unable to encode {}: {}", path.display(), err);
+        let _ = fs::remove_file(&partial_path);
+        ServiceError::InternalServerError
+    })?;
+    writer.flush()?;
+    drop(writer);
+
+    fs::rename(&partial_path, path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::{Rgba, RgbaImage};
+
+    /// An image whose red channel holds the x coordinate of each pixel
+    fn gradient(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, _| {
+            Rgba([x as u8, 0, 0, 255])
+        }))
+    }
+
+    fn first_column(image: &DynamicImage) -> u8 {
+        image.get_pixel(0, 0)[0]
+    }
+
+    fn rect(x: f64, y: f64, width: f64, height: f64) -> CropRect {
+        CropRect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn converts_crop_to_pixels() {
+        assert_eq!(
+            rect(0.0, 0.0, 1.0, 1.0).to_pixels(200, 100),
+            (0, 0, 200, 100)
+        );
+        assert_eq!(
+            rect(0.25, 0.5, 0.5, 0.25).to_pixels(200, 100),
+            (50, 50, 100, 25)
+        );
+    }
+
+    #[test]
+    fn clamps_crop_to_image() {
+        assert_eq!(
+            rect(0.9, 0.9, 0.5, 0.5).to_pixels(200, 100),
+            (180, 90, 20, 10)
+        );
+        assert_eq!(
+            rect(1.0, 1.0, 0.5, 0.5).to_pixels(200, 100),
+            (199, 99, 1, 1)
+        );
+        assert_eq!(
+            rect(0.5, 0.5, 0.0, 0.001).to_pixels(200, 100),
+            (100, 50, 1, 1)
+        );
+    }
+
+    #[test]
+    fn cover_crop_fills_requested_size() {
+        let cropped = cover_crop(&gradient(100, 10), 20, 10, (0.5, 0.5), FilterType::Nearest);
+
+        assert_eq!(cropped.dimensions(), (20, 10));
+        assert_eq!(first_column(&cropped), 40);
+    }
+
+    #[test]
+    fn cover_crop_centers_on_focal_point() {
+        let crop = |focal_x| {
+            let cropped = cover_crop(
+                &gradient(100, 10),
+                10,
+                10,
+                (focal_x, 0.5),
+                FilterType::Nearest,
+            );
+            first_column(&cropped)
+        };
+
+        assert_eq!(crop(0.5), 45);
+        assert_eq!(crop(0.3), 25);
+        // the crop stops at the edges of the image
+        assert_eq!(crop(0.0), 0);
+        assert_eq!(crop(1.0), 90);
+    }
+
+    #[test]
+    fn crop_takes_precedence_over_focal_point() {
+        let framing = Framing {
+            focal_point: (0.0, 0.5),
+            crop: Some(rect(0.5, 0.0, 0.5, 1.0)),
+        };
+
+        let framed = framing.apply(&gradient(100, 10), 10, 10, FilterType::Nearest);
+
+        assert_eq!(framed.dimensions(), (10, 10));
+        assert_eq!(first_column(&framed), 70);
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use deadpool_postgres::Pool;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::ServiceError;
+use crate::files::photos;
+use crate::schemas::scan_settings::ScanSettings;
+
+/// Name of the gitignore-style files that exclude paths from scans. The rules in such a file apply
+/// to its directory and everything below it, and rules in deeper directories take precedence.
+pub const IGNORE_FILE_NAME: &str = ".scarlettignore";
+
+// EXCLUDED FILES **********************************************************************************
+
+/// Number of files each rule kept out of a scan. Every file is only counted once, for the first
+/// rule that excluded it: hidden, extension, ignore file, exclude pattern, include patterns.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExcludedFiles {
+    /// Hidden files, and hidden folders which are skipped as a whole
+    pub hidden: usize,
+    pub extension: usize,
+    /// Keyed by the path of the `.scarlettignore` file
+    pub ignore_files: BTreeMap<String, usize>,
+    /// Keyed by the pattern from the scan settings
+    pub exclude_patterns: BTreeMap<String, usize>,
+    /// Files that did not match any of the include patterns
+    pub not_included: usize,
+    /// Files whose scan issue was ignored
+    #[serde(default)]
+    pub quarantined: usize,
+}
+
+impl ExcludedFiles {
+    pub fn total(&self) -> usize {
+        self.hidden
+            + self.extension
+            + self.ignore_files.values().sum::<usize>()
+            + self.exclude_patterns.values().sum::<usize>()
+            + self.not_included
+            + self.quarantined
+    }
+}
+
+enum Exclusion {
+    Hidden,
+    Extension,
+    IgnoreFile(PathBuf),
+    ExcludePattern(usize),
+    NotIncluded,
+}
+
+// SCAN RULES **************************************************************************************
+
+/// The scan settings compiled for matching, along with the `.scarlettignore` files found so far.
+/// All matching is case-insensitive.
+pub struct ScanRules {
+    extensions: HashSet<String>,
+    include: GlobSet,
+    exclude: GlobSet,
+    exclude_patterns: Vec<String>,
+    /// `.scarlettignore` of every directory looked at so far, `None` if it has none
+    ignore_files: HashMap<PathBuf, Option<Gitignore>>,
+    pub excluded: ExcludedFiles,
+}
+
+impl ScanRules {
+    pub fn new(settings: &ScanSettings) -> Result<Self, ServiceError> {
+        Ok(ScanRules {
+            extensions: settings
+                .extensions
+                .iter()
+                .map(|extension| extension.to_lowercase())
+                .collect(),
+            include: build_glob_set(&settings.include_patterns)?,
+            exclude: build_glob_set(&settings.exclude_patterns)?,
+            exclude_patterns: settings.exclude_patterns.clone(),
+            ignore_files: HashMap::new(),
+            excluded: ExcludedFiles::default(),
+        })
+    }
+
+    pub async fn load(pool: &Pool) -> Result<Self, ServiceError> {
+        let settings = ScanSettings::get(pool).await?;
+
+        ScanRules::new(&settings)
+    }
+
+    /// Whether a scan should pick up the file. If not, the rule that excluded it is counted.
+    pub fn includes(&mut self, path: &Path) -> bool {
+        let exclusion = match self.exclusion(path) {
+            Some(exclusion) => exclusion,
+            None => return true,
+        };
+
+        match exclusion {
+            Exclusion::Hidden => self.excluded.hidden += 1,
+            Exclusion::Extension => self.excluded.extension += 1,
+            Exclusion::IgnoreFile(ignore_file) => {
+                *self
+                    .excluded
+                    .ignore_files
+                    .entry(ignore_file.to_string_lossy().to_string())
+                    .or_default() += 1
+            }
+            Exclusion::ExcludePattern(index) => {
+                *self
+                    .excluded
+                    .exclude_patterns
+                    .entry(self.exclude_patterns[index].to_owned())
+                    .or_default() += 1
+            }
+            Exclusion::NotIncluded => self.excluded.not_included += 1,
+        }
+
+        false
+    }
+
+    fn exclusion(&mut self, path: &Path) -> Option<Exclusion> {
+        if photos::is_hidden_path(path) {
+            return Some(Exclusion::Hidden);
+        }
+
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase())
+            .unwrap_or_default();
+
+        if !self.extensions.contains(&extension) {
+            return Some(Exclusion::Extension);
+        }
+
+        if let Some(ignore_file) = self.ignored_by(path) {
+            return Some(Exclusion::IgnoreFile(ignore_file));
+        }
+
+        let file_name = path.file_name().map(Path::new).unwrap_or(path);
+
+        let mut excluded_by = self.exclude.matches(path);
+        excluded_by.extend(self.exclude.matches(file_name));
+        if let Some(index) = excluded_by.into_iter().min() {
+            return Some(Exclusion::ExcludePattern(index));
+        }
+
+        if !self.include.is_empty()
+            && !self.include.is_match(path)
+            && !self.include.is_match(file_name)
+        {
+            return Some(Exclusion::NotIncluded);
+        }
+
+        None
+    }
+
+    /// Returns the `.scarlettignore` file that excludes `path`, checking the closest directory
+    /// first. A negated (`!`) rule in a closer file re-includes the path.
+    fn ignored_by(&mut self, path: &Path) -> Option<PathBuf> {
+        for dir in path.ancestors().skip(1) {
+            let ignore_file = self
+                .ignore_files
+                .entry(dir.to_path_buf())
+                .or_insert_with(|| read_ignore_file(dir));
+
+            if let Some(ignore_file) = ignore_file {
+                let matched = ignore_file.matched_path_or_any_parents(path, false);
+
+                if matched.is_ignore() {
+                    return Some(dir.join(IGNORE_FILE_NAME));
+                }
+
+                if matched.is_whitelist() {
+                    return None;
+                }
+            }
+        }
+
+        None
+    }
+}
+
+fn build_glob_set(patterns: &[String]) -> Result<GlobSet, ServiceError> {
+    let mut builder = GlobSetBuilder::new();
+
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .map_err(|err| {
+                ServiceError::BadRequest(format!("Invalid pattern {}: {}", pattern, err))
+            })?;
+
+        builder.add(glob);
+    }
+
+    builder
+        .build()
+        .map_err(|err| ServiceError::BadRequest(format!("Invalid patterns: {}", err)))
+}
+
+fn read_ignore_file(dir: &Path) -> Option<Gitignore> {
+    let path = dir.join(IGNORE_FILE_NAME);
+
+    if !path.is_file() {
+        return None;
+    }
+
+    let mut builder = GitignoreBuilder::new(dir);
+    if let Err(err) = builder.case_insensitive(true) {
+        println!("Unable to read {}: {}", path.display(), err);
+        return None;
+    }
+
+    // a broken line does not stop the rest of the file from applying
+    if let Some(err) = builder.add(&path) {
+        println!("Unable to read {}: {}", path.display(), err);
+    }
+
+    match builder.build() {
+        Ok(ignore_file) => Some(ignore_file),
+        Err(err) => {
+            println!("Unable to read {}: {}", path.display(), err);
+            None
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageOutputFormat};
+
+use crate::errors::ServiceError;
+use crate::files::phash;
+use crate::files::renditions;
+
+/// Sizes generated when `SCARLETT_THUMBNAIL_SIZES` is not set
+const DEFAULT_SIZES: [(&str, u32); 3] = [("small", 320), ("medium", 800), ("large", 1600)];
+
+const JPEG_QUALITY: u8 = 85;
+
+// THUMBNAIL SIZE **********************************************************************************
+
+/// A named size thumbnails are generated in. The longer side of the thumbnail is scaled down to
+/// `max_dimension` pixels, smaller photos are never scaled up. With `cover` the thumbnail is a
+/// square instead, cropped around the focal point of the photo.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ThumbnailSize {
+    pub name: String,
+    pub max_dimension: u32,
+    pub cover: bool,
+}
+
+impl ThumbnailSize {
+    /// Tells the thumbnails of a file in this size apart. Cover thumbnails change with the focal
+    /// point, so it is part of their name.
+    pub fn variant(&self, focal_point: (f64, f64)) -> String {
+        if self.cover {
+            format!("-f{:.3}x{:.3}", focal_point.0, focal_point.1)
+        } else {
+            String::new()
+        }
+    }
+}
+
+/// Returns the configured thumbnail sizes, read from `SCARLETT_THUMBNAIL_SIZES` as a comma
+/// separated list of `name:pixels` pairs, e.g. `small:320,large:1600`. A pair followed by `:cover`
+/// is a cover size, e.g. `square:200:cover`. Pairs that cannot be parsed are skipped, the defaults
+/// are used if none is left.
+pub fn thumbnail_sizes() -> Vec<ThumbnailSize> {
+    let configured: Vec<ThumbnailSize> = env::var("SCARLETT_THUMBNAIL_SIZES")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|pair| {
+            let mut parts = pair.splitn(3, ':');
+            let name = parts.next()?.trim();
+            let max_dimension = parts.next()?.trim().parse::<u32>().ok()?;
+            let cover = match parts.next().map(str::trim) {
+                None => false,
+                Some("cover") => true,
+                Some(_) => return None,
+            };
+
+            let valid_name = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+            if valid_name && max_dimension > 0 {
+                Some(ThumbnailSize {
+                    name: name.to_string(),
+                    max_dimension,
+                    cover,
+                })
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    if !configured.is_empty() {
+        return configured;
+    }
+
+    DEFAULT_SIZES
+        .iter()
+        .map(|(name, max_dimension)| ThumbnailSize {
+            name: name.to_string(),
+            max_dimension: *max_dimension,
+            cover: false,
+        })
+        .collect()
+}
+
+pub fn find_size(name: &str) -> Option<ThumbnailSize> {
+    thumbnail_sizes().into_iter().find(|size| size.name == name)
+}
+
+/// Returns the directory thumbnails of the library at `library_root` are cached in, read from
+/// `SCARLETT_THUMBNAIL_DIR`. Defaults to a hidden folder inside the library root, which scans skip.
+pub fn thumbnail_dir(library_root: &Path) -> PathBuf {
+    env::var("SCARLETT_THUMBNAIL_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| library_root.join(".thumbnails"))
+}
+
+/// Whether scans generate the thumbnails of new photos right away instead of on first request,
+/// enabled by setting `SCARLETT_THUMBNAILS_AT_SCAN` to `true` or `1`
+pub fn generate_at_scan() -> bool {
+    env::var("SCARLETT_THUMBNAILS_AT_SCAN")
+        .map(|value| value == "true" || value == "1")
+        .unwrap_or(false)
+}
+
+/// Whether thumbnails can be generated for the file. Videos and formats the `image` crate cannot
+/// decode, such as HEIC, have none.
+pub fn can_generate(file_path: &str) -> bool {
+    phash::can_decode(file_path)
+}
+
+// THUMBNAIL CACHE *********************************************************************************
+
+/// Returns where the thumbnail of a file is cached. Thumbnails are keyed by the hash of the file,
+/// so copies of a photo share them and an edited file gets new ones. They are stored upright, the
+/// rotation is part of the name so that changing the rotation of a photo does not serve a stale
+/// thumbnail.
+pub fn thumbnail_path(
+    library_root: &Path,
+    file_hash: &str,
+    rotation: i32,
+    focal_point: (f64, f64),
+    size: &ThumbnailSize,
+) -> PathBuf {
+    let variant = size.variant(focal_point);
+    let file_name = match rotation {
+        0 => format!("{}{}.jpg", file_hash, variant),
+        rotation => format!("{}-r{}{}.jpg", file_hash, rotation, variant),
+    };
+
+    // spread the files over subfolders, a single folder with every thumbnail gets slow to list
+    let prefix = file_hash.get(..2).unwrap_or(file_hash);
+
+    thumbnail_dir(library_root)
+        .join(&size.name)
+        .join(prefix)
+        .join(file_name)
+}
+
+/// Returns the cached thumbnail of a file, generating it first if it does not exist yet
+pub fn get_or_create(
+    library_root: &Path,
+    file_path: &str,
+    file_hash: &str,
+    rotation: i32,
+    focal_point: (f64, f64),
+    size: &ThumbnailSize,
+) -> Result<PathBuf, ServiceError> {
+    let path = thumbnail_path(library_root, file_hash, rotation, focal_point, size);
+
+    if !path.exists() {
+        let image = renditions::open_upright(file_path, rotation)?;
+        write_thumbnail(&image, &path, focal_point, size)?;
+    }
+
+    Ok(path)
+}
+
+/// Generates every configured size of a file that is not cached yet. The file is only decoded once
+/// and only if a size is missing. Returns the number of thumbnails written.
+pub fn create_all(
+    library_root: &Path,
+    file_path: &str,
+    file_hash: &str,
+    rotation: i32,
+    focal_point: (f64, f64),
+) -> Result<usize, ServiceError> {
+    let missing: Vec<(ThumbnailSize, PathBuf)> = thumbnail_sizes()
+        .into_iter()
+        .map(|size| {
+            let path = thumbnail_path(library_root, file_hash, rotation, focal_point, &size);
+            (size, path)
+        })
+        .filter(|(_, path)| !path.exists())
+        .collect();
+
+    if missing.is_empty() {
+        return Ok(0);
+    }
+
+    let image = renditions::open_upright(file_path, rotation)?;
+    for (size, path) in &missing {
+        write_thumbnail(&image, path, focal_point, size)?;
+    }
+
+    Ok(missing.len())
+}
+
+fn write_thumbnail(
+    image: &DynamicImage,
+    path: &Path,
+    focal_point: (f64, f64),
+    size: &ThumbnailSize,
+) -> Result<(), ServiceError> {
+    let (width, height) = image.dimensions();
+    let thumbnail = if size.cover {
+        let side = size.max_dimension.min(width).min(height);
+        renditions::cover_crop(image, side, side, focal_point, FilterType::Triangle)
+    } else if width.max(height) > size.max_dimension {
+        image.resize(size.max_dimension, size.max_dimension, FilterType::Triangle)
+    } else {
+        image.clone()
+    };
+
+    renditions::save_atomically(&thumbnail, path, ImageOutputFormat::Jpeg(JPEG_QUALITY))
+}
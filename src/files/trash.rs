@@ -0,0 +1,107 @@
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::errors::ServiceError;
+
+/// Returns the directory that files of the library at `library_root` are trashed to, read from
+/// `SCARLETT_TRASH_DIR`. Defaults to a hidden folder inside the library root, which scans skip.
+pub fn trash_dir(library_root: &Path) -> PathBuf {
+    env::var("SCARLETT_TRASH_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| library_root.join(".trash"))
+}
+
+/// Returns the number of days photos are kept in the trash before they are purged, read from
+/// `SCARLETT_TRASH_RETENTION_DAYS`. Defaults to 30 days, `0` keeps trashed photos until the trash
+/// is emptied by hand.
+pub fn retention_days() -> Option<i32> {
+    let days = env::var("SCARLETT_TRASH_RETENTION_DAYS")
+        .ok()
+        .and_then(|value| value.parse::<i32>().ok())
+        .unwrap_or(30);
+
+    Some(days).filter(|days| *days > 0)
+}
+
+/// Moves a file into the trash directory of its library and returns its new location. The path
+/// relative to the library root is kept so that files with the same name in different folders do
+/// not collide.
+pub fn move_to_trash(file_path: &str, library_root: &Path) -> Result<PathBuf, ServiceError> {
+    let source = Path::new(file_path);
+    let relative = source.strip_prefix(library_root).unwrap_or(source);
+    let relative = relative.strip_prefix("/").unwrap_or(relative);
+    let destination = unused_path(trash_dir(library_root).join(relative));
+
+    move_file(source, &destination)?;
+
+    Ok(destination)
+}
+
+/// Moves a trashed file back to where it was trashed from. Nothing is overwritten, if another file
+/// has taken its place since, the restore fails.
+pub fn restore_from_trash(trash_path: &str, original_path: &str) -> Result<(), ServiceError> {
+    let destination = Path::new(original_path);
+
+    if destination.exists() {
+        return Err(ServiceError::BadRequest(format!(
+            "Unable to restore {}, the file already exists",
+            original_path
+        )));
+    }
+
+    move_file(Path::new(trash_path), destination)
+}
+
+/// Moves a restored file back into the trash, undoing `restore_from_trash` when the photo cannot be
+/// marked as restored
+pub fn return_to_trash(original_path: &str, trash_path: &str) -> Result<(), ServiceError> {
+    move_file(Path::new(original_path), Path::new(trash_path))
+}
+
+/// Permanently deletes a trashed file. Files that are already gone are not an error.
+pub fn remove_from_trash(trash_path: &str) -> Result<(), ServiceError> {
+    match fs::remove_file(trash_path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+fn move_file(source: &Path, destination: &Path) -> Result<(), ServiceError> {
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // renaming only works within a single filesystem, so fall back to copying
+    if fs::rename(source, destination).is_err() {
+        fs::copy(source, destination)?;
+        fs::remove_file(source)?;
+    }
+
+    Ok(())
+}
+
+/// Appends a counter to the file name until it no longer clashes with a file in the trash, which
+/// happens when a photo is trashed from a path that an earlier trashed photo was stored at
+fn unused_path(path: PathBuf) -> PathBuf {
+    if !path.exists() {
+        return path;
+    }
+
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default()
+        .to_string();
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| format!(".{}", extension))
+        .unwrap_or_default();
+
+    (1..)
+        .map(|n| path.with_file_name(format!("{} ({}){}", stem, n, extension)))
+        .find(|candidate| !candidate.exists())
+        .unwrap()
+}
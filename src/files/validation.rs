@@ -0,0 +1,200 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use image::io::Reader;
+use image::ImageFormat;
+use serde::{Deserialize, Serialize};
+
+use crate::files::video::VideoContainer;
+
+/// Number of bytes read from the start of a file to detect its format
+const SIGNATURE_LENGTH: usize = 32;
+
+/// `ftyp` brands of HEIF images
+const HEIF_BRANDS: [&[u8; 4]; 10] = [
+    b"heic", b"heix", b"hevc", b"hevx", b"heim", b"heis", b"hevm", b"hevs", b"mif1", b"msf1",
+];
+
+/// Top level box types that QuickTime files written before `ftyp` existed can start with
+const QUICKTIME_BOXES: [&[u8; 4]; 5] = [b"moov", b"mdat", b"wide", b"free", b"skip"];
+
+/// Magic number of EBML documents, i.e. Matroska and WebM
+const EBML_MAGIC: [u8; 4] = [0x1A, 0x45, 0xDF, 0xA3];
+
+// FILE ISSUE **************************************************************************************
+
+/// Why a file was quarantined rather than imported
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum IssueKind {
+    /// The file could not be opened or read
+    Unreadable,
+    /// The contents do not start with the signature of a format that can be imported
+    UnknownFormat,
+    /// The contents are in a different format than the extension claims
+    ExtensionMismatch,
+    /// The format is known, but the header could not be decoded
+    Corrupt,
+}
+
+impl IssueKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            IssueKind::Unreadable => "unreadable",
+            IssueKind::UnknownFormat => "unknownFormat",
+            IssueKind::ExtensionMismatch => "extensionMismatch",
+            IssueKind::Corrupt => "corrupt",
+        }
+    }
+}
+
+/// A file that failed validation, see `scan_issues`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileIssue {
+    pub file_path: String,
+    pub kind: IssueKind,
+    pub error: String,
+}
+
+impl FileIssue {
+    pub fn new(file_path: &str, kind: IssueKind, error: impl fmt::Display) -> Self {
+        FileIssue {
+            file_path: file_path.to_string(),
+            kind,
+            error: error.to_string(),
+        }
+    }
+}
+
+// FILE FORMAT *************************************************************************************
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileFormat {
+    /// Any format the `image` crate can decode
+    Image(ImageFormat),
+    /// HEIF is only parsed by hand, see `HeifInfo`
+    Heif,
+    /// Clips are only parsed as far as their container, see `VideoInfo`
+    Video(VideoContainer),
+}
+
+impl FileFormat {
+    /// Value of the `media_type` column for files in this format
+    pub fn media_type(self) -> &'static str {
+        match self {
+            FileFormat::Video(_) => "video",
+            _ => "image",
+        }
+    }
+
+    fn from_extension(path: &str) -> Option<Self> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase())?;
+
+        match extension.as_str() {
+            "heic" | "heif" => Some(FileFormat::Heif),
+            "pnm" => Some(FileFormat::Image(ImageFormat::Pnm)),
+            "mp4" | "m4v" | "mov" => Some(FileFormat::Video(VideoContainer::IsoMedia)),
+            "webm" => Some(FileFormat::Video(VideoContainer::Matroska)),
+            _ => ImageFormat::from_path(path).ok().map(FileFormat::Image),
+        }
+    }
+
+    fn from_signature(header: &[u8]) -> Option<Self> {
+        // ISO base media files start with an `ftyp` box, its major brand tells HEIF from video
+        if header.get(4..8) == Some(b"ftyp") {
+            let brand = header.get(8..12)?;
+            let is_heif = HEIF_BRANDS
+                .iter()
+                .any(|heif_brand| &heif_brand[..] == brand);
+
+            return Some(if is_heif {
+                FileFormat::Heif
+            } else {
+                FileFormat::Video(VideoContainer::IsoMedia)
+            });
+        }
+
+        let starts_with_quicktime_box = header
+            .get(4..8)
+            .map(|box_type| {
+                QUICKTIME_BOXES
+                    .iter()
+                    .any(|quicktime| &quicktime[..] == box_type)
+            })
+            .unwrap_or(false);
+        if starts_with_quicktime_box {
+            return Some(FileFormat::Video(VideoContainer::IsoMedia));
+        }
+
+        if header.starts_with(&EBML_MAGIC) {
+            return Some(FileFormat::Video(VideoContainer::Matroska));
+        }
+
+        image::guess_format(header).ok().map(FileFormat::Image)
+    }
+}
+
+impl fmt::Display for FileFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileFormat::Image(format) => write!(f, "{:?}", format),
+            FileFormat::Heif => write!(f, "HEIF"),
+            FileFormat::Video(VideoContainer::IsoMedia) => write!(f, "MP4/MOV"),
+            FileFormat::Video(VideoContainer::Matroska) => write!(f, "WebM"),
+        }
+    }
+}
+
+// VALIDATION **************************************************************************************
+
+/// Reads the start of the file and makes sure its contents match the extension. Returns the
+/// format the file is in.
+pub fn check_format(path: &str) -> Result<FileFormat, FileIssue> {
+    let mut header = Vec::with_capacity(SIGNATURE_LENGTH);
+    File::open(path)
+        .and_then(|file| file.take(SIGNATURE_LENGTH as u64).read_to_end(&mut header))
+        .map_err(|err| FileIssue::new(path, IssueKind::Unreadable, err))?;
+
+    if header.is_empty() {
+        return Err(FileIssue::new(path, IssueKind::Corrupt, "File is empty"));
+    }
+
+    match (
+        FileFormat::from_signature(&header),
+        FileFormat::from_extension(path),
+    ) {
+        (Some(actual), Some(expected)) if actual != expected => Err(FileIssue::new(
+            path,
+            IssueKind::ExtensionMismatch,
+            format!(
+                "Contents are {}, but the extension is for {}",
+                actual, expected
+            ),
+        )),
+        (Some(actual), _) => Ok(actual),
+        // TGA is the only format without a signature
+        (None, Some(FileFormat::Image(ImageFormat::Tga))) => {
+            Ok(FileFormat::Image(ImageFormat::Tga))
+        }
+        (None, _) => Err(FileIssue::new(
+            path,
+            IssueKind::UnknownFormat,
+            "Contents are not in a known image format",
+        )),
+    }
+}
+
+/// Decodes the image header and returns the width and height
+pub fn read_dimensions(path: &str, format: ImageFormat) -> Result<(u32, u32), FileIssue> {
+    let file = File::open(path).map_err(|err| FileIssue::new(path, IssueKind::Unreadable, err))?;
+
+    Reader::with_format(BufReader::new(file), format)
+        .into_dimensions()
+        .map_err(|err| FileIssue::new(path, IssueKind::Corrupt, err))
+}
@@ -0,0 +1,346 @@
+use std::fs::File;
+use std::io::BufReader;
+
+use crate::files::bmff;
+use crate::files::ebml;
+
+// Matroska element ids
+const INFO: u32 = 0x1549_A966;
+const TIMECODE_SCALE: u32 = 0x002A_D7B1;
+const DURATION: u32 = 0x4489;
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+
+/// `TrackType` of video tracks
+const VIDEO_TRACK_TYPE: u64 = 1;
+/// Default `TimecodeScale`, timestamps are in milliseconds
+const DEFAULT_TIMECODE_SCALE: u64 = 1_000_000;
+
+/// Containers that clips are stored in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VideoContainer {
+    /// ISO base media, i.e. MP4 and QuickTime MOV
+    IsoMedia,
+    /// Matroska, which WebM is a subset of
+    Matroska,
+}
+
+// VIDEO INFO **************************************************************************************
+
+/// Dimensions, duration and codec of the first video track of a clip
+#[derive(Clone, Debug, PartialEq)]
+pub struct VideoInfo {
+    pub width: u32,
+    pub height: u32,
+    /// Length of the clip in seconds, `None` if the container does not record it
+    pub duration: Option<f64>,
+    /// Short name of the codec, e.g. `h264` or `vp9`
+    pub codec: Option<String>,
+    /// Clockwise rotation in degrees needed to display the clip upright, i.e. the same meaning as
+    /// the `rotation` column
+    pub rotation: i32,
+}
+
+impl VideoInfo {
+    /// Reads the container's metadata. Only the `moov` box of MP4/MOV files, or the `Info` and
+    /// `Tracks` elements of WebM files, are read, the media data itself is never decoded.
+    pub fn read(path: &str, container: VideoContainer) -> Option<Self> {
+        let file = File::open(path).ok()?;
+        let mut reader = BufReader::new(file);
+
+        match container {
+            VideoContainer::IsoMedia => {
+                let moov = bmff::read_top_level_box(&mut reader, b"moov").ok()??;
+                VideoInfo::from_moov(&moov)
+            }
+            VideoContainer::Matroska => {
+                let elements = ebml::read_segment_elements(&mut reader, &[INFO, TRACKS]).ok()?;
+                VideoInfo::from_segment(&elements)
+            }
+        }
+    }
+
+    fn from_moov(moov: &[u8]) -> Option<Self> {
+        let trak = bmff::boxes(moov)
+            .filter(|child| &child.box_type == b"trak")
+            .find(|trak| is_video_track(trak.data))?;
+
+        let tkhd = bmff::find_box(trak.data, b"tkhd")?;
+        let stsd = bmff::find_nested_box(trak.data, &[b"mdia", b"minf", b"stbl", b"stsd"])?;
+        // `stsd` is a full box with an entry count, the type of the first entry is the codec
+        let sample_entry = bmff::boxes(stsd.get(8..)?).next();
+
+        // the track header holds the display size, the sample entry the size the clip is encoded at
+        let (width, height) = read_track_size(tkhd)
+            .filter(|(width, height)| *width > 0 && *height > 0)
+            .or_else(|| {
+                sample_entry
+                    .as_ref()
+                    .and_then(|entry| read_coded_size(entry.data))
+            })?;
+
+        let duration = bmff::find_box(moov, b"mvhd")
+            .and_then(read_duration)
+            .or_else(|| {
+                bmff::find_nested_box(trak.data, &[b"mdia", b"mdhd"]).and_then(read_duration)
+            });
+
+        Some(VideoInfo {
+            width,
+            height,
+            duration,
+            codec: sample_entry.map(|entry| iso_codec_name(&entry.box_type)),
+            rotation: read_rotation(tkhd).unwrap_or(0),
+        })
+    }
+
+    fn from_segment(elements: &[(u32, Vec<u8>)]) -> Option<Self> {
+        let find = |id: u32| {
+            elements
+                .iter()
+                .find(|(element_id, _)| *element_id == id)
+                .map(|(_, data)| data.as_slice())
+        };
+
+        let track = ebml::elements(find(TRACKS)?)
+            .filter(|child| child.id == TRACK_ENTRY)
+            .find(|entry| {
+                ebml::find_element(entry.data, TRACK_TYPE).and_then(ebml::read_uint)
+                    == Some(VIDEO_TRACK_TYPE)
+            })?;
+
+        let video = ebml::find_element(track.data, VIDEO)?;
+        let width = ebml::find_element(video, PIXEL_WIDTH).and_then(ebml::read_uint)?;
+        let height = ebml::find_element(video, PIXEL_HEIGHT).and_then(ebml::read_uint)?;
+
+        // the duration is a float in units of the timecode scale, which is in nanoseconds
+        let duration = find(INFO).and_then(|info| {
+            let scale = ebml::find_element(info, TIMECODE_SCALE)
+                .and_then(ebml::read_uint)
+                .unwrap_or(DEFAULT_TIMECODE_SCALE);
+            let duration = ebml::find_element(info, DURATION).and_then(ebml::read_float)?;
+
+            Some(duration * scale as f64 / 1_000_000_000.0).filter(|duration| *duration > 0.0)
+        });
+
+        Some(VideoInfo {
+            width: width as u32,
+            height: height as u32,
+            duration,
+            codec: ebml::find_element(track.data, CODEC_ID)
+                .and_then(ebml::read_string)
+                .map(|codec_id| matroska_codec_name(&codec_id)),
+            rotation: 0,
+        })
+    }
+}
+
+fn is_video_track(trak: &[u8]) -> bool {
+    // the handler type follows the version, flags and a reserved field
+    bmff::find_nested_box(trak, &[b"mdia", b"hdlr"])
+        .and_then(|hdlr| hdlr.get(8..12))
+        .map(|handler_type| handler_type == b"vide")
+        .unwrap_or(false)
+}
+
+/// Returns the offset of the transformation matrix in a `tkhd` box, which depends on whether its
+/// times are 32 or 64 bits
+fn matrix_offset(tkhd: &[u8]) -> Option<usize> {
+    match bmff::read_u8(tkhd, 0)? {
+        0 => Some(40),
+        _ => Some(52),
+    }
+}
+
+/// Reads the width and height of a `tkhd` box, which are 16.16 fixed point numbers after the
+/// matrix
+fn read_track_size(tkhd: &[u8]) -> Option<(u32, u32)> {
+    let offset = matrix_offset(tkhd)? + 36;
+    let width = bmff::read_u32(tkhd, offset)? >> 16;
+    let height = bmff::read_u32(tkhd, offset + 4)? >> 16;
+
+    Some((width, height))
+}
+
+/// Reads the width and height of a visual sample entry
+fn read_coded_size(sample_entry: &[u8]) -> Option<(u32, u32)> {
+    let width = bmff::read_u16(sample_entry, 24)?;
+    let height = bmff::read_u16(sample_entry, 26)?;
+
+    Some((u32::from(width), u32::from(height)))
+}
+
+/// Reads the rotation from the transformation matrix of a `tkhd` box. Phones record portrait clips
+/// in landscape and only set the matrix, so it has to be applied the same way as `irot` in HEIF.
+fn read_rotation(tkhd: &[u8]) -> Option<i32> {
+    let offset = matrix_offset(tkhd)?;
+    // the matrix is stored row by row as 16.16 fixed point numbers, a rotation only uses the
+    // top-left 2x2 of it, which is either 0 or ±1
+    let value = |index: usize| {
+        bmff::read_u32(tkhd, offset + index * 4).map(|value| (value as i32).signum())
+    };
+
+    let rotation = match (value(0)?, value(1)?, value(3)?, value(4)?) {
+        (0, 1, -1, 0) => 90,
+        (-1, 0, 0, -1) => 180,
+        (0, -1, 1, 0) => 270,
+        _ => 0,
+    };
+
+    Some(rotation)
+}
+
+/// Reads the duration in seconds of an `mvhd` or `mdhd` box, which share their layout up to it
+fn read_duration(header: &[u8]) -> Option<f64> {
+    let (timescale, duration) = match bmff::read_u8(header, 0)? {
+        0 => (
+            bmff::read_u32(header, 12)?,
+            u64::from(bmff::read_u32(header, 16)?),
+        ),
+        _ => (bmff::read_u32(header, 20)?, bmff::read_u64(header, 24)?),
+    };
+
+    // fragmented files leave the duration at 0, all bits set means it is unknown
+    if timescale == 0 || duration == 0 || duration == u64::from(u32::MAX) || duration == u64::MAX {
+        return None;
+    }
+
+    Some(duration as f64 / f64::from(timescale))
+}
+
+fn iso_codec_name(sample_entry_type: &[u8; 4]) -> String {
+    let name = match sample_entry_type {
+        b"avc1" | b"avc3" => "h264",
+        b"hvc1" | b"hev1" => "hevc",
+        b"vp08" => "vp8",
+        b"vp09" => "vp9",
+        b"av01" => "av1",
+        b"mp4v" => "mpeg4",
+        b"apch" | b"apcn" | b"apcs" | b"apco" | b"ap4h" | b"ap4x" => "prores",
+        b"jpeg" | b"mjpa" | b"mjpb" => "mjpeg",
+        other => return String::from_utf8_lossy(other).trim().to_lowercase(),
+    };
+
+    name.to_string()
+}
+
+fn matroska_codec_name(codec_id: &str) -> String {
+    let name = match codec_id {
+        "V_MPEG4/ISO/AVC" => "h264",
+        "V_MPEGH/ISO/HEVC" => "hevc",
+        "V_VP8" => "vp8",
+        "V_VP9" => "vp9",
+        "V_AV1" => "av1",
+        "V_THEORA" => "theora",
+        other => return other.trim_start_matches("V_").to_lowercase(),
+    };
+
+    name.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::bmff::tests::make_box;
+    use crate::files::ebml::tests::make_element;
+
+    /// 16.16 fixed point numbers of a transformation matrix
+    const ONE: u32 = 0x0001_0000;
+    const MINUS_ONE: u32 = 0xFFFF_0000;
+
+    /// Builds a version 0 `tkhd` with the top-left 2x2 of the matrix and a 1920x1080 track size
+    fn tkhd(a: u32, b: u32, c: u32, d: u32) -> Vec<u8> {
+        let mut tkhd = vec![0; 40];
+        for value in &[a, b, 0, c, d, 0, 0, 0, 0x4000_0000] {
+            tkhd.extend_from_slice(&value.to_be_bytes());
+        }
+        tkhd.extend_from_slice(&(1920u32 << 16).to_be_bytes());
+        tkhd.extend_from_slice(&(1080u32 << 16).to_be_bytes());
+        tkhd
+    }
+
+    #[test]
+    fn maps_tkhd_matrix_to_clockwise_rotation() {
+        assert_eq!(read_rotation(&tkhd(ONE, 0, 0, ONE)), Some(0));
+        assert_eq!(read_rotation(&tkhd(0, ONE, MINUS_ONE, 0)), Some(90));
+        assert_eq!(read_rotation(&tkhd(MINUS_ONE, 0, 0, MINUS_ONE)), Some(180));
+        assert_eq!(read_rotation(&tkhd(0, MINUS_ONE, ONE, 0)), Some(270));
+        // mirrored clips are not rotations
+        assert_eq!(read_rotation(&tkhd(MINUS_ONE, 0, 0, ONE)), Some(0));
+    }
+
+    #[test]
+    fn reads_tkhd_matrix_after_64_bit_times() {
+        let mut tkhd = tkhd(0, ONE, MINUS_ONE, 0);
+        tkhd[0] = 1;
+        tkhd.splice(4..4, vec![0; 12]);
+
+        assert_eq!(read_rotation(&tkhd), Some(90));
+        assert_eq!(read_track_size(&tkhd), Some((1920, 1080)));
+    }
+
+    #[test]
+    fn ignores_truncated_tkhd() {
+        let tkhd = tkhd(0, ONE, MINUS_ONE, 0);
+
+        assert_eq!(read_rotation(&tkhd[..50]), None);
+        assert_eq!(read_track_size(&tkhd[..80]), None);
+    }
+
+    #[test]
+    fn reads_first_video_track_of_moov() {
+        let mut hdlr = vec![0; 8];
+        hdlr.extend_from_slice(b"vide");
+
+        let mut avc1 = vec![0; 24];
+        avc1.extend_from_slice(&1920u16.to_be_bytes());
+        avc1.extend_from_slice(&1080u16.to_be_bytes());
+        let mut stsd = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        stsd.extend(make_box(b"avc1", &avc1));
+
+        let stbl = make_box(b"stbl", &make_box(b"stsd", &stsd));
+        let mut mdia = make_box(b"hdlr", &hdlr);
+        mdia.extend(make_box(b"minf", &stbl));
+
+        let mut trak = make_box(b"tkhd", &tkhd(0, ONE, MINUS_ONE, 0));
+        trak.extend(make_box(b"mdia", &mdia));
+
+        let mut mvhd = vec![0; 12];
+        mvhd.extend_from_slice(&600u32.to_be_bytes());
+        mvhd.extend_from_slice(&3000u32.to_be_bytes());
+
+        let mut moov = make_box(b"mvhd", &mvhd);
+        moov.extend(make_box(b"trak", &trak));
+
+        let info = VideoInfo::from_moov(&moov).unwrap();
+
+        assert_eq!((info.width, info.height, info.rotation), (1920, 1080, 90));
+        assert_eq!(info.duration, Some(5.0));
+        assert_eq!(info.codec.as_deref(), Some("h264"));
+    }
+
+    #[test]
+    fn reads_video_track_of_segment() {
+        let mut video = make_element(PIXEL_WIDTH, &[0x05, 0x00]);
+        video.extend(make_element(PIXEL_HEIGHT, &[0x02, 0xD0]));
+
+        let mut entry = make_element(TRACK_TYPE, &[1]);
+        entry.extend(make_element(CODEC_ID, b"V_VP9"));
+        entry.extend(make_element(VIDEO, &video));
+
+        let mut info = make_element(TIMECODE_SCALE, &[0x0F, 0x42, 0x40]);
+        info.extend(make_element(DURATION, &2500f32.to_be_bytes()));
+
+        let elements = vec![(INFO, info), (TRACKS, make_element(TRACK_ENTRY, &entry))];
+        let info = VideoInfo::from_segment(&elements).unwrap();
+
+        assert_eq!((info.width, info.height), (1280, 720));
+        assert_eq!(info.duration, Some(2.5));
+        assert_eq!(info.codec.as_deref(), Some("vp9"));
+    }
+}
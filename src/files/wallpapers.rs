@@ -0,0 +1,132 @@
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageOutputFormat};
+
+use crate::errors::ServiceError;
+use crate::files::renditions::{self, Framing};
+use crate::schemas::wallpaper_sizes::WallpaperSize;
+
+const JPEG_QUALITY: u8 = 95;
+
+const PREVIEW_MAX_WIDTH: u32 = 1280;
+
+/// Returns the directory wallpapers are written to, read from `SCARLETT_WALLPAPER_DIR`. Defaults to
+/// the `/wallpaper` volume.
+pub fn wallpaper_dir() -> PathBuf {
+    env::var("SCARLETT_WALLPAPER_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("/wallpaper"))
+}
+
+/// Returns where the wallpaper of a photo is written. Every photo has the same file name in every
+/// size, so each size gets a folder named after its dimensions. Wallpapers are always JPEG,
+/// whatever the format of the photo.
+pub fn wallpaper_path(size: &WallpaperSize, wallpaper_file_name: &str) -> PathBuf {
+    wallpaper_dir()
+        .join(format!("{}x{}", size.width, size.height))
+        .join(wallpaper_file_name)
+        .with_extension("jpg")
+}
+
+/// Renders the wallpaper of a photo by cropping the upright original to the aspect ratio of the
+/// size as the framing says and scaling it to the size. Returns the path the wallpaper was written
+/// to.
+pub fn render(
+    file_path: &str,
+    rotation: i32,
+    size: &WallpaperSize,
+    framing: &Framing,
+    wallpaper_file_name: &str,
+) -> Result<PathBuf, ServiceError> {
+    let path = wallpaper_path(size, wallpaper_file_name);
+
+    let wallpaper = render_image(file_path, rotation, size, framing)?;
+    renditions::save_atomically(&wallpaper, &path, ImageOutputFormat::Jpeg(JPEG_QUALITY))?;
+
+    Ok(path)
+}
+
+/// Deletes a rendered wallpaper. Files that are already gone are not an error, and paths outside
+/// of the wallpaper directory are left alone since they were not written by `render`.
+pub fn remove(file_path: &str) -> Result<(), ServiceError> {
+    let path = Path::new(file_path);
+    let escapes = path
+        .components()
+        .any(|component| component == Component::ParentDir);
+    if escapes || !path.starts_with(wallpaper_dir()) {
+        return Ok(());
+    }
+
+    match fs::remove_file(file_path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Renders the wallpaper like `render` without writing it, scaled down to at most
+/// `PREVIEW_MAX_WIDTH` pixels wide and encoded as JPEG
+pub fn preview(
+    file_path: &str,
+    rotation: i32,
+    size: &WallpaperSize,
+    framing: &Framing,
+) -> Result<Vec<u8>, ServiceError> {
+    let wallpaper = render_image(file_path, rotation, size, framing)?;
+
+    let preview = if wallpaper.width() > PREVIEW_MAX_WIDTH {
+        wallpaper.resize(PREVIEW_MAX_WIDTH, u32::MAX, FilterType::Triangle)
+    } else {
+        wallpaper
+    };
+
+    let mut bytes = Vec::new();
+    DynamicImage::ImageRgb8(preview.to_rgb())
+        .write_to(&mut bytes, ImageOutputFormat::Jpeg(JPEG_QUALITY))
+        .map_err(|err| {
+            println!(
+                "Unable to encode wallpaper preview of {}: {}",
+                file_path, err
+            );
+            ServiceError::InternalServerError
+        })?;
+
+    Ok(bytes)
+}
+
+/// Photos, or crops of them, that are smaller than the size are refused rather than scaled up
+fn render_image(
+    file_path: &str,
+    rotation: i32,
+    size: &WallpaperSize,
+    framing: &Framing,
+) -> Result<DynamicImage, ServiceError> {
+    let image = renditions::open_upright(file_path, rotation)?;
+
+    let (width, height) = (size.width as u32, size.height as u32);
+    let (region_width, region_height) = match framing.crop {
+        Some(crop) => {
+            let (_, _, crop_width, crop_height) = crop.to_pixels(image.width(), image.height());
+            (crop_width, crop_height)
+        }
+        None => image.dimensions(),
+    };
+
+    if region_width < width || region_height < height {
+        let what = if framing.crop.is_some() {
+            "The crop of the photo"
+        } else {
+            "The photo"
+        };
+
+        return Err(ServiceError::BadRequest(format!(
+            "{} is {} x {}, which is smaller than `{}` ({} x {})",
+            what, region_width, region_height, size.name, width, height
+        )));
+    }
+
+    Ok(framing.apply(&image, width, height, FilterType::Lanczos3))
+}
@@ -0,0 +1,605 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::schemas::photo_full::PhotoFull;
+
+/// Number of bytes at the start of a file that are searched for an embedded XMP packet. Formats
+/// that embed XMP store it near the start, ahead of the image data.
+const EMBEDDED_SEARCH_LENGTH: u64 = 1024 * 1024;
+
+const RATING: &str = "xmp:Rating";
+const SUBJECT: &str = "dc:subject";
+const PERSON_IN_IMAGE: &str = "Iptc4xmpExt:PersonInImage";
+
+/// Namespaces of the properties that are written, by prefix
+const NAMESPACES: [(&str, &str); 3] = [
+    ("xmp", "http://ns.adobe.com/xap/1.0/"),
+    ("dc", "http://purl.org/dc/elements/1.1/"),
+    ("Iptc4xmpExt", "http://iptc.org/std/Iptc4xmpExt/2008-02-29/"),
+];
+
+// XMP METADATA ************************************************************************************
+
+/// Rating, tags and entities as other photo tools store them in XMP. Tags are the keywords in
+/// `dc:subject` and entities the names in `Iptc4xmpExt:PersonInImage`. Properties are only
+/// recognized under their usual prefixes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct XmpMetadata {
+    /// Rating on the same scale as the `rating` column, see `from_xmp_rating`
+    pub rating: Option<i32>,
+    pub tags: Vec<String>,
+    pub entities: Vec<String>,
+}
+
+impl XmpMetadata {
+    /// Reads the sidecar of a file, or the XMP packet embedded in the file itself if it has no
+    /// sidecar. Files without XMP produce empty metadata rather than an error.
+    pub fn read(path: &str) -> Self {
+        let sidecar = sidecar_paths(path)
+            .iter()
+            .find_map(|sidecar| fs::read_to_string(sidecar).ok());
+
+        match sidecar.or_else(|| read_embedded_packet(path)) {
+            Some(xml) => XmpMetadata::parse(&xml),
+            None => XmpMetadata::default(),
+        }
+    }
+
+    pub fn parse(xml: &str) -> Self {
+        XmpMetadata {
+            rating: find_property(xml, RATING)
+                .and_then(|rating| rating.trim().parse::<f32>().ok())
+                .and_then(|rating| from_xmp_rating(rating.round() as i32)),
+            tags: find_list(xml, SUBJECT)
+                .into_iter()
+                .fold(Vec::new(), |mut tags, tag| {
+                    // tags are stored lowercase, so keywords that only differ in case are one tag
+                    let tag = tag.to_lowercase();
+                    if !tags.contains(&tag) {
+                        tags.push(tag);
+                    }
+                    tags
+                }),
+            entities: find_list(xml, PERSON_IN_IMAGE),
+        }
+    }
+
+    pub fn from_photo(photo: &PhotoFull) -> Self {
+        XmpMetadata {
+            rating: Some(photo.rating),
+            tags: photo.tags.clone().unwrap_or_default(),
+            entities: photo.entities.clone().unwrap_or_default(),
+        }
+    }
+
+    /// Writes the metadata into the sidecar of `path` and returns the sidecar's path. An existing
+    /// sidecar is updated in place, so that whatever else other tools stored in it is kept. New
+    /// sidecars are named after the whole file name, e.g. `IMG_0001.jpg.xmp`, so that files that
+    /// only differ in their extension do not share one.
+    pub fn write_sidecar(&self, path: &str) -> io::Result<PathBuf> {
+        let [full_name, stem] = sidecar_paths(path);
+        let sidecar = if stem.is_file() && !full_name.is_file() {
+            stem
+        } else {
+            full_name
+        };
+
+        let xml = match fs::read_to_string(&sidecar) {
+            Ok(existing) => self
+                .merge_into(&existing)
+                .unwrap_or_else(|| self.to_packet()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => self.to_packet(),
+            Err(err) => return Err(err),
+        };
+
+        fs::write(&sidecar, xml)?;
+
+        Ok(sidecar)
+    }
+
+    /// Replaces the properties in an existing packet. Returns `None` if it has no
+    /// `rdf:Description` to put them in.
+    fn merge_into(&self, xml: &str) -> Option<String> {
+        let mut xml = xml.to_string();
+        for name in &[RATING, SUBJECT, PERSON_IN_IMAGE] {
+            xml = remove_property(&xml, name);
+        }
+
+        let start = xml.find("<rdf:Description")?;
+        let end = start + find_tag_end(&xml[start..])?;
+        let self_closing = xml[..end].ends_with('/');
+        let tag_end = if self_closing { end - 1 } else { end };
+
+        let mut start_tag = xml[start..tag_end].trim_end().to_string();
+        for (prefix, uri) in &NAMESPACES {
+            if !start_tag.contains(&format!("xmlns:{}=", prefix)) {
+                start_tag += &format!("\n    xmlns:{}=\"{}\"", prefix, uri);
+            }
+        }
+
+        let closing = if self_closing {
+            "\n  </rdf:Description>"
+        } else {
+            ""
+        };
+
+        Some(format!(
+            "{}{}>{}{}{}",
+            &xml[..start],
+            start_tag,
+            self.to_properties(),
+            closing,
+            &xml[end + 1..]
+        ))
+    }
+
+    fn to_packet(&self) -> String {
+        let namespaces: String = NAMESPACES
+            .iter()
+            .map(|(prefix, uri)| format!("\n    xmlns:{}=\"{}\"", prefix, uri))
+            .collect();
+
+        format!(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+             <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n \
+             <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n  \
+             <rdf:Description rdf:about=\"\"{}>{}\n  \
+             </rdf:Description>\n \
+             </rdf:RDF>\n\
+             </x:xmpmeta>\n\
+             <?xpacket end=\"w\"?>\n",
+            namespaces,
+            self.to_properties()
+        )
+    }
+
+    fn to_properties(&self) -> String {
+        let mut properties = String::new();
+
+        if let Some(rating) = self.rating.and_then(to_xmp_rating) {
+            properties += &format!("\n   <{0}>{1}</{0}>", RATING, rating);
+        }
+
+        for (name, values) in &[(SUBJECT, &self.tags), (PERSON_IN_IMAGE, &self.entities)] {
+            if values.is_empty() {
+                continue;
+            }
+
+            let items: String = values
+                .iter()
+                .map(|value| format!("\n     <rdf:li>{}</rdf:li>", escape(value)))
+                .collect();
+
+            properties += &format!(
+                "\n   <{0}>\n    <rdf:Bag>{1}\n    </rdf:Bag>\n   </{0}>",
+                name, items
+            );
+        }
+
+        properties
+    }
+}
+
+/// XMP rates from 1 to 5 stars, with 0 for unrated and -1 for rejected photos. Here 1 is pending
+/// delete, 2 hidden, 3 neutral, 4 a wallpaper candidate and 5 a favorite. Only a rejected photo is
+/// pending delete, stars never hide or delete a photo, so 1 to 3 stars are all neutral.
+fn from_xmp_rating(rating: i32) -> Option<i32> {
+    match rating {
+        -1 => Some(1),
+        0 => Some(0),
+        1..=3 => Some(3),
+        4 => Some(4),
+        5 => Some(5),
+        _ => None,
+    }
+}
+
+/// The inverse of `from_xmp_rating`. Hidden has no equivalent in XMP, so no rating is written for
+/// hidden photos, which leaves their rating as it is when the sidecar is imported again.
+fn to_xmp_rating(rating: i32) -> Option<i32> {
+    match rating {
+        0 => Some(0),
+        1 => Some(-1),
+        3..=5 => Some(rating),
+        _ => None,
+    }
+}
+
+/// Returns the paths a sidecar of `path` can have, `IMG_0001.jpg.xmp` and `IMG_0001.xmp`, which
+/// are the two conventions in use
+pub fn sidecar_paths(path: &str) -> [PathBuf; 2] {
+    let path = Path::new(path);
+
+    [
+        PathBuf::from(format!("{}.xmp", path.display())),
+        path.with_extension("xmp"),
+    ]
+}
+
+// SIDECAR EXPORT **********************************************************************************
+
+/// Outcome of writing the sidecars of several photos
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SidecarExport {
+    pub written: usize,
+    /// Photos whose sidecar could not be written, along with the reason
+    pub failed: Vec<String>,
+}
+
+/// Writes the sidecar of every photo, a failure only skips the photo it happened on
+pub fn export_sidecars(photos: &[PhotoFull]) -> SidecarExport {
+    let mut export = SidecarExport::default();
+
+    for photo in photos {
+        match XmpMetadata::from_photo(photo).write_sidecar(&photo.file_path) {
+            Ok(_) => export.written += 1,
+            Err(err) => export.failed.push(format!("{}: {}", photo.file_path, err)),
+        }
+    }
+
+    export
+}
+
+// XML *********************************************************************************************
+
+/// Looks for the start of an XMP packet near the start of a file
+fn read_embedded_packet(path: &str) -> Option<String> {
+    let mut data = Vec::new();
+    File::open(path)
+        .and_then(|file| file.take(EMBEDDED_SEARCH_LENGTH).read_to_end(&mut data))
+        .ok()?;
+
+    let start = find_bytes(&data, b"<x:xmpmeta")?;
+    let end = start + find_bytes(&data[start..], b"</x:xmpmeta>")?;
+
+    String::from_utf8(data[start..end].to_vec()).ok()
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Returns the value of a simple property, written either as an attribute of `rdf:Description`
+/// or as an element of its own
+fn find_property(xml: &str, name: &str) -> Option<String> {
+    if let Some((_, body)) = find_element(xml, name) {
+        return Some(unescape(body.trim()));
+    }
+
+    let attribute = format!("{}=", name);
+    let start = find_name(xml, &attribute)? + attribute.len();
+    let quote = xml[start..].chars().next()?;
+    let value = &xml[start + 1..];
+    let end = value.find(quote)?;
+
+    Some(unescape(&value[..end]))
+}
+
+/// Returns the items of a bag or sequence property
+fn find_list(xml: &str, name: &str) -> Vec<String> {
+    let mut items = Vec::new();
+
+    let mut rest = match find_element(xml, name) {
+        Some((_, body)) => body,
+        None => return items,
+    };
+
+    while let Some((end, body)) = find_element(rest, "rdf:li") {
+        let item = unescape(body.trim());
+        if !item.is_empty() && !items.contains(&item) {
+            items.push(item);
+        }
+        rest = &rest[end..];
+    }
+
+    items
+}
+
+/// Finds the first element called `name` and returns the offset just past it along with its body.
+/// Self-closing elements have an empty body.
+fn find_element<'a>(xml: &'a str, name: &str) -> Option<(usize, &'a str)> {
+    let open = format!("<{}", name);
+    let start = find_name(xml, &open)?;
+    let body_start = start + find_tag_end(&xml[start..])? + 1;
+
+    if xml[..body_start - 1].ends_with('/') {
+        return Some((body_start, ""));
+    }
+
+    let close = format!("</{}>", name);
+    let body_end = body_start + xml[body_start..].find(&close)?;
+
+    Some((body_end + close.len(), &xml[body_start..body_end]))
+}
+
+/// Finds `name` where it is not just the start of a longer name, e.g. `xmp:Rating` but not
+/// `xmp:RatingPercent`
+fn find_name(xml: &str, name: &str) -> Option<usize> {
+    let mut offset = 0;
+
+    while let Some(found) = xml[offset..].find(name) {
+        let start = offset + found;
+        let next = xml[start + name.len()..].chars().next();
+
+        let ends_name = name.ends_with('=')
+            || next
+                .map(|next| next == '>' || next == '/' || next.is_whitespace())
+                .unwrap_or(true);
+        let starts_name = xml[..start]
+            .chars()
+            .last()
+            .map(|previous| !previous.is_alphanumeric() && previous != ':')
+            .unwrap_or(true);
+
+        if ends_name && starts_name {
+            return Some(start);
+        }
+
+        offset = start + name.len();
+    }
+
+    None
+}
+
+/// Returns the offset of the `>` that closes the tag at the start of `xml`, skipping over quoted
+/// attribute values
+fn find_tag_end(xml: &str) -> Option<usize> {
+    let mut quote = None;
+
+    for (index, c) in xml.char_indices() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            (None, '>') => return Some(index),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Removes every occurrence of a property, both as an element and as an attribute
+fn remove_property(xml: &str, name: &str) -> String {
+    let mut xml = xml.to_string();
+
+    while let Some(start) = find_name(&xml, &format!("<{}", name)) {
+        let end = match find_element(&xml[start..], name) {
+            Some((end, _)) => start + end,
+            None => break,
+        };
+
+        // take the indentation of the element along with it
+        let indent_start = xml[..start].trim_end_matches(&[' ', '\t'][..]).len();
+        let start = if xml[..indent_start].ends_with('\n') {
+            indent_start - 1
+        } else {
+            start
+        };
+        xml.replace_range(start..end, "");
+    }
+
+    let attribute = format!("{}=", name);
+    while let Some(start) = find_name(&xml, &attribute) {
+        let value_start = start + attribute.len();
+        let end = match xml[value_start..].chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => xml[value_start + 1..]
+                .find(quote)
+                .map(|end| value_start + 1 + end + 1),
+            _ => None,
+        };
+
+        match end {
+            Some(end) => {
+                let start = xml[..start].trim_end().len();
+                xml.replace_range(start..end, "");
+            }
+            None => break,
+        }
+    }
+
+    xml
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find('&') {
+        result += &rest[..start];
+        rest = &rest[start..];
+
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+
+        let decoded = match &rest[1..end] {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            entity if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16)
+                .ok()
+                .and_then(std::char::from_u32),
+            entity if entity.starts_with('#') => {
+                entity[1..].parse().ok().and_then(std::char::from_u32)
+            }
+            _ => None,
+        };
+
+        match decoded {
+            Some(c) => {
+                result.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    result + rest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(rating: Option<i32>, tags: &[&str], entities: &[&str]) -> XmpMetadata {
+        XmpMetadata {
+            rating,
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            entities: entities.iter().map(|entity| entity.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn parses_properties_written_as_elements() {
+        let xml = r#"<rdf:Description rdf:about="">
+            <xmp:RatingPercent>80</xmp:RatingPercent>
+            <xmp:Rating>4</xmp:Rating>
+            <dc:subject><rdf:Bag>
+                <rdf:li>Beach</rdf:li>
+                <rdf:li>beach</rdf:li>
+                <rdf:li>Sun &amp; Sand</rdf:li>
+            </rdf:Bag></dc:subject>
+            <Iptc4xmpExt:PersonInImage><rdf:Bag>
+                <rdf:li>Jane Doe</rdf:li>
+                <rdf:li> </rdf:li>
+            </rdf:Bag></Iptc4xmpExt:PersonInImage>
+        </rdf:Description>"#;
+
+        assert_eq!(
+            XmpMetadata::parse(xml),
+            metadata(Some(4), &["beach", "sun & sand"], &["Jane Doe"])
+        );
+    }
+
+    #[test]
+    fn parses_rating_written_as_attribute() {
+        let xml = r#"<rdf:Description xmp:RatingPercent="20" xmp:Rating='3.0' />"#;
+
+        assert_eq!(XmpMetadata::parse(xml).rating, Some(3));
+    }
+
+    #[test]
+    fn maps_xmp_ratings() {
+        let rating =
+            |value: &str| XmpMetadata::parse(&format!("<xmp:Rating>{}</xmp:Rating>", value)).rating;
+
+        assert_eq!(rating("-1"), Some(1));
+        assert_eq!(rating("0"), Some(0));
+        assert_eq!(rating("1"), Some(3));
+        assert_eq!(rating("2"), Some(3));
+        assert_eq!(rating("5"), Some(5));
+        assert_eq!(rating("6"), None);
+        assert_eq!(rating("high"), None);
+        assert_eq!(to_xmp_rating(1), Some(-1));
+        assert_eq!(to_xmp_rating(2), None);
+        assert_eq!(to_xmp_rating(4), Some(4));
+    }
+
+    #[test]
+    fn round_trips_ratings() {
+        // a rating read from XMP stays the same when it is written out and read again
+        for xmp_rating in -1..=5 {
+            let rating = from_xmp_rating(xmp_rating).unwrap();
+            if xmp_rating > 0 {
+                assert!(rating > 2, "{} stars imported as {}", xmp_rating, rating);
+            }
+
+            let exported = to_xmp_rating(rating).unwrap();
+            assert_eq!(from_xmp_rating(exported), Some(rating));
+        }
+
+        // and so does every rating of a photo, hidden ones by not being written at all
+        for rating in 0..=5 {
+            let packet = metadata(Some(rating), &[], &[]).to_packet();
+            let imported = XmpMetadata::parse(&packet).rating.unwrap_or(rating);
+
+            assert_eq!(imported, rating);
+        }
+    }
+
+    #[test]
+    fn parses_nothing_from_xml_without_properties() {
+        assert_eq!(XmpMetadata::parse("<x:xmpmeta/>"), XmpMetadata::default());
+        assert_eq!(XmpMetadata::parse("<dc:subject>"), XmpMetadata::default());
+    }
+
+    #[test]
+    fn round_trips_through_new_packet() {
+        let original = metadata(Some(1), &["a <b>", "\"quoted\""], &["Tom & Jerry"]);
+
+        assert_eq!(XmpMetadata::parse(&original.to_packet()), original);
+    }
+
+    #[test]
+    fn merges_into_existing_packet() {
+        let existing = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about="" xmlns:xmp="http://ns.adobe.com/xap/1.0/" xmp:Rating="2">
+   <dc:creator><rdf:Seq><rdf:li>Photographer</rdf:li></rdf:Seq></dc:creator>
+   <dc:subject><rdf:Bag><rdf:li>old</rdf:li></rdf:Bag></dc:subject>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>"#;
+        let updated = metadata(Some(5), &["new"], &[]);
+
+        let merged = updated.merge_into(existing).unwrap();
+
+        assert_eq!(XmpMetadata::parse(&merged), updated);
+        assert!(merged.contains("<rdf:li>Photographer</rdf:li>"));
+        assert!(merged.contains("xmlns:dc=\"http://purl.org/dc/elements/1.1/\""));
+        assert_eq!(merged.matches("xmlns:xmp=").count(), 1);
+    }
+
+    #[test]
+    fn merges_into_self_closing_description() {
+        let existing = r#"<rdf:RDF><rdf:Description rdf:about="" xmp:Rating="2"/></rdf:RDF>"#;
+        let updated = metadata(Some(3), &["tag"], &["Someone"]);
+
+        let merged = updated.merge_into(existing).unwrap();
+
+        assert_eq!(XmpMetadata::parse(&merged), updated);
+        assert!(merged.ends_with("</rdf:Description></rdf:RDF>"));
+    }
+
+    #[test]
+    fn does_not_merge_into_packet_without_description() {
+        assert_eq!(metadata(Some(3), &[], &[]).merge_into("<x:xmpmeta/>"), None);
+    }
+
+    #[test]
+    fn unescapes_entities() {
+        assert_eq!(unescape("a &amp; b &lt;c&gt; &#233;&#xE9;"), "a & b <c> éé");
+        assert_eq!(unescape("&unknown; & &amp"), "&unknown; & &amp");
+    }
+
+    #[test]
+    fn names_sidecars_after_full_name_and_stem() {
+        assert_eq!(
+            sidecar_paths("/photos/IMG_0001.jpg"),
+            [
+                PathBuf::from("/photos/IMG_0001.jpg.xmp"),
+                PathBuf::from("/photos/IMG_0001.xmp")
+            ]
+        );
+    }
+}
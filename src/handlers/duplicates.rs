@@ -0,0 +1,88 @@
+use actix_web::{get, post, web};
+use deadpool_postgres::Pool;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::ServiceError;
+use crate::requests::similar_photos_request::SimilarPhotosRequest;
+use crate::responses::api_response::ApiResponse;
+use crate::schemas::duplicates::{
+    DuplicateGroup, DuplicateResolution, SimilarPhotoGroup, DUPLICATE_GROUP_STATUSES,
+};
+use crate::types::HandlerResult;
+
+// ALL DUPLICATE GROUPS ****************************************************************************
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetDuplicatesRequest {
+    pub status: Option<String>,
+}
+
+#[get("/duplicates")]
+pub async fn get_duplicate_groups(
+    info: web::Query<GetDuplicatesRequest>,
+    pool: web::Data<Pool>,
+) -> HandlerResult {
+    let status = info.into_inner().status;
+
+    if let Some(status) = &status {
+        if !DUPLICATE_GROUP_STATUSES.contains(&status.as_str()) {
+            return Err(ServiceError::BadRequest(format!(
+                "Unknown duplicate group status: {}",
+                status
+            ))
+            .into());
+        }
+    }
+
+    let details = DuplicateGroup::get_all_details(status.as_deref(), &pool).await?;
+
+    Ok(ApiResponse::success(details))
+}
+
+// SIMILAR PHOTOS **********************************************************************************
+
+#[get("/duplicates/similar")]
+pub async fn get_similar_photo_groups(
+    info: web::Query<SimilarPhotosRequest>,
+    pool: web::Data<Pool>,
+) -> HandlerResult {
+    let max_distance = info.into_inner().get_max_distance()?;
+
+    let groups = SimilarPhotoGroup::get_all(max_distance, &pool).await?;
+
+    Ok(ApiResponse::success(groups))
+}
+
+// SINGLE DUPLICATE GROUP **************************************************************************
+
+#[get("/duplicates/{id}")]
+pub async fn get_duplicate_group(info: web::Path<i32>, pool: web::Data<Pool>) -> HandlerResult {
+    let group = DuplicateGroup::get_by_id(info.into_inner(), &pool).await?;
+    let details = group.get_details(&pool).await?;
+
+    Ok(ApiResponse::success(details))
+}
+
+// RESOLVE DUPLICATE GROUP *************************************************************************
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolveDuplicateRequest {
+    pub action: DuplicateResolution,
+    pub keep_photo_id: Option<i32>,
+}
+
+#[post("/duplicates/{id}/resolve")]
+pub async fn resolve_duplicate_group(
+    info: web::Path<i32>,
+    params: web::Json<ResolveDuplicateRequest>,
+    pool: web::Data<Pool>,
+) -> HandlerResult {
+    let params = params.into_inner();
+
+    let details =
+        DuplicateGroup::resolve(info.into_inner(), params.action, params.keep_photo_id, &pool)
+            .await?;
+
+    Ok(ApiResponse::success(details))
+}
@@ -0,0 +1,108 @@
+use actix_web::{delete, get, http::header, post, web, HttpResponse};
+use deadpool_postgres::Pool;
+
+use crate::errors::ServiceError;
+use crate::files::wallpapers;
+use crate::requests::framing_request::{CropRequest, FocalPointRequest};
+use crate::requests::render_wallpaper_request::RenderWallpaperRequest;
+use crate::responses::api_response::ApiResponse;
+use crate::schemas::framing::{PhotoCrop, PhotoFraming};
+use crate::schemas::photo::Photo;
+use crate::schemas::wallpaper_sizes::WallpaperSize;
+use crate::types::HandlerResult;
+
+// PHOTO FRAMING ***********************************************************************************
+
+#[get("/photos/{photo_id}/framing")]
+pub async fn get_photo_framing(info: web::Path<i32>, pool: web::Data<Pool>) -> HandlerResult {
+    let framing = PhotoFraming::get(info.into_inner(), &pool).await?;
+
+    Ok(ApiResponse::success(framing))
+}
+
+// FOCAL POINT *************************************************************************************
+
+#[post("/photos/{photo_id}/focal_point")]
+pub async fn set_focal_point(
+    params: web::Path<i32>,
+    info: web::Json<FocalPointRequest>,
+    pool: web::Data<Pool>,
+) -> HandlerResult {
+    let focal_point = info.validate()?;
+
+    let framing =
+        PhotoFraming::set_focal_point(params.into_inner(), Some(focal_point), &pool).await?;
+
+    Ok(ApiResponse::success(framing))
+}
+
+#[delete("/photos/{photo_id}/focal_point")]
+pub async fn remove_focal_point(params: web::Path<i32>, pool: web::Data<Pool>) -> HandlerResult {
+    let framing = PhotoFraming::set_focal_point(params.into_inner(), None, &pool).await?;
+
+    Ok(ApiResponse::success(framing))
+}
+
+// CROPS *******************************************************************************************
+
+#[post("/photos/{photo_id}/crops/{wallpaper_size_id}")]
+pub async fn set_photo_crop(
+    params: web::Path<(i32, i32)>,
+    info: web::Json<CropRequest>,
+    pool: web::Data<Pool>,
+) -> HandlerResult {
+    let (photo_id, wallpaper_size_id) = params.into_inner();
+    let rect = info.validate()?;
+
+    let crop = PhotoCrop::set(photo_id, wallpaper_size_id, &rect, &pool).await?;
+
+    Ok(ApiResponse::success(crop))
+}
+
+#[delete("/photos/{photo_id}/crops/{wallpaper_size_id}")]
+pub async fn remove_photo_crop(
+    params: web::Path<(i32, i32)>,
+    pool: web::Data<Pool>,
+) -> HandlerResult {
+    let (photo_id, wallpaper_size_id) = params.into_inner();
+
+    let message = PhotoCrop::remove(photo_id, wallpaper_size_id, &pool).await?;
+
+    Ok(ApiResponse::success(message))
+}
+
+// WALLPAPER PREVIEW *******************************************************************************
+
+/// Shows how the wallpaper of a photo in a wallpaper size would be framed, without writing it.
+/// Takes the same query parameters as rendering the wallpaper.
+#[get("/photos/{photo_id}/wallpaper/{wallpaper_size_id}/preview")]
+pub async fn preview_wallpaper(
+    params: web::Path<(i32, i32)>,
+    info: web::Query<RenderWallpaperRequest>,
+    pool: web::Data<Pool>,
+) -> HandlerResult {
+    let (photo_id, wallpaper_size_id) = params.into_inner();
+
+    let photo = Photo::get_visible(photo_id, &pool).await?;
+
+    if photo.media_type == "video" {
+        return Err(ServiceError::BadRequest(
+            "Wallpapers cannot be rendered from videos".to_string(),
+        )
+        .into());
+    }
+
+    let size = WallpaperSize::get_by_id(wallpaper_size_id, &pool).await?;
+    let stored = PhotoFraming::get(photo_id, &pool).await?;
+    let framing = info.framing(stored.for_size(wallpaper_size_id))?;
+
+    let preview =
+        web::block(move || wallpapers::preview(&photo.file_path, photo.rotation, &size, &framing))
+            .await
+            .map_err(ServiceError::from)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("image/jpeg")
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(preview))
+}
@@ -0,0 +1,15 @@
+use actix_web::{get, web};
+use deadpool_postgres::Pool;
+
+use crate::responses::api_response::ApiResponse;
+use crate::schemas::integrity::IntegrityReport;
+use crate::types::HandlerResult;
+
+// INTEGRITY REPORT ********************************************************************************
+
+#[get("/integrity")]
+pub async fn get_integrity_report(pool: web::Data<Pool>) -> HandlerResult {
+    let report = IntegrityReport::get_latest(&pool).await?;
+
+    Ok(ApiResponse::success(report))
+}
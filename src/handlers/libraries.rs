@@ -0,0 +1,59 @@
+use actix_web::{delete, get, patch, post, web};
+use deadpool_postgres::Pool;
+
+use crate::requests::update_library_request::UpdateLibraryRequest;
+use crate::responses::api_response::ApiResponse;
+use crate::schemas::libraries::{Library, NewLibrary};
+use crate::types::HandlerResult;
+
+// ALL LIBRARIES ***********************************************************************************
+
+#[get("/libraries")]
+pub async fn get_libraries(pool: web::Data<Pool>) -> HandlerResult {
+    let libraries = Library::get_all(&pool).await?;
+
+    Ok(ApiResponse::success(libraries))
+}
+
+// SINGLE LIBRARY **********************************************************************************
+
+#[get("/libraries/{id}")]
+pub async fn get_library(info: web::Path<i32>, pool: web::Data<Pool>) -> HandlerResult {
+    let library = Library::get(info.into_inner(), &pool).await?;
+
+    Ok(ApiResponse::success(library))
+}
+
+// CREATE LIBRARY **********************************************************************************
+
+#[post("/libraries")]
+pub async fn create_library(
+    params: web::Json<NewLibrary>,
+    pool: web::Data<Pool>,
+) -> HandlerResult {
+    let new_library = Library::create(params.into_inner(), &pool).await?;
+
+    Ok(ApiResponse::success(new_library))
+}
+
+// UPDATE LIBRARY **********************************************************************************
+
+#[patch("/libraries/{id}")]
+pub async fn update_library(
+    info: web::Path<i32>,
+    params: web::Json<UpdateLibraryRequest>,
+    pool: web::Data<Pool>,
+) -> HandlerResult {
+    let updated_library = Library::update(info.into_inner(), &params, &pool).await?;
+
+    Ok(ApiResponse::success(updated_library))
+}
+
+// DELETE LIBRARY **********************************************************************************
+
+#[delete("/libraries/{id}")]
+pub async fn delete_library(info: web::Path<i32>, pool: web::Data<Pool>) -> HandlerResult {
+    let message = Library::delete(info.into_inner(), &pool).await?;
+
+    Ok(ApiResponse::success(message))
+}
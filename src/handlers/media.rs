@@ -1,44 +1,215 @@
-use std::borrow::ToOwned;
-use std::fs::File;
-use std::io::prelude::*;
-use std::path::Path;
+use std::io;
+use std::path::{Path, PathBuf};
 
 use actix_files as fs;
-use actix_web::{get, http::header, web, HttpRequest, HttpResponse, Result};
+use actix_web::{get, http::header, web, HttpRequest};
+use deadpool_postgres::Pool;
 
 use crate::errors::ServiceError;
-use crate::responses::api_response::ApiResponse;
+use crate::files::renditions;
+use crate::requests::media_request::MediaRequest;
+use crate::responses::etag::ETag;
+use crate::schemas::libraries::Library;
+use crate::schemas::photo::Photo;
 use crate::types::HandlerResult;
 
-#[get("/media/{tail:.*}")]
-pub async fn static_files(req: HttpRequest) -> HandlerResult {
-    let file_path_str = format!(".{}", req.path());
-    let file_path = Path::new(&file_path_str);
-    let file_name = file_path.file_name().unwrap().to_str().unwrap().to_owned();
-    let file_ext = file_path.extension().unwrap().to_str().unwrap().to_owned();
-    let file_mime = fs::file_extension_to_mime(&file_ext);
-    let content_type = format!("{}/{}", file_mime.type_(), file_mime.subtype());
-
-    let res = web::block(move || -> Result<String, ServiceError> {
-        let mut f = File::open(file_path_str)?;
-        let mut buffer = String::new();
-        f.read_to_string(&mut buffer)?;
-        Ok(buffer)
+// LIBRARY FILES ***********************************************************************************
+
+/// Serves the files of the libraries under `/files` and their media prefixes. The library is looked
+/// up on every request, so that libraries created or changed through `/libraries` are served right
+/// away.
+#[get("/files/{path:.*}")]
+pub async fn get_library_file(req: HttpRequest, pool: web::Data<Pool>) -> HandlerResult {
+    let path = req.match_info().query("path");
+
+    let library = Library::get_by_media_path(path, &pool)
+        .await?
+        .ok_or_else(|| ServiceError::NotFound(format!("No library is served under {}", path)))?;
+    let tail = &path[library.media_prefix.len()..];
+    let (path, real_path) = resolve_library_path(&library.root_path, tail)?;
+
+    let file_hash = Photo::get_file_hash_by_path(&path.to_string_lossy(), &pool).await?;
+
+    stream_file(&req, &real_path, file_hash.as_deref())
+}
+
+/// Resolves a path requested under the media prefix of a library to a file in the library. Returns
+/// the path as the scan stores it and the path with every symlink followed. `..` and hidden
+/// segments are refused, as is anything that ends up outside of the library root once symlinks are
+/// followed.
+fn resolve_library_path(root_path: &str, tail: &str) -> Result<(PathBuf, PathBuf), ServiceError> {
+    let mut path = PathBuf::from(root_path);
+    for segment in tail.split('/').filter(|segment| !segment.is_empty()) {
+        // `..` is covered by refusing hidden segments, which also keeps the trash and caches out
+        if segment.starts_with('.') || segment.contains('\\') || segment.contains('\0') {
+            return Err(ServiceError::BadRequest(format!("Invalid path {}", tail)));
+        }
+
+        path.push(segment);
+    }
+
+    let not_found = || ServiceError::NotFound(format!("File {} does not exist", tail));
+
+    let real_root = std::fs::canonicalize(root_path).map_err(|_| not_found())?;
+    let real_path = std::fs::canonicalize(&path).map_err(|_| not_found())?;
+    if !real_path.starts_with(&real_root) || !real_path.is_file() {
+        return Err(not_found());
+    }
+
+    Ok((path, real_path))
+}
+
+// MEDIA *******************************************************************************************
+
+/// Serves the file of a photo. With `width`, `height`, `fit`, `format` or `quality` the photo is
+/// rendered upright at the requested size instead, rounded up to a multiple of 32 pixels.
+/// Renditions are cached on disk so that only the first request for a set of parameters pays for
+/// the resizing, and they are streamed from the cache like any other file. `cover` keeps the focal
+/// point of the photo in view.
+#[get("/media/{photo_id:\\d+}")]
+pub async fn get_media(
+    req: HttpRequest,
+    info: web::Path<i32>,
+    params: web::Query<MediaRequest>,
+    pool: web::Data<Pool>,
+) -> HandlerResult {
+    let photo = Photo::get_visible(info.into_inner(), &pool).await?;
+
+    if params.is_empty() {
+        return stream_file(&req, Path::new(&photo.file_path), Some(&photo.file_hash));
+    }
+
+    if photo.media_type == "video" {
+        return Err(ServiceError::BadRequest("Videos cannot be resized".to_string()).into());
+    }
+
+    let mut rendition = params.to_rendition(&photo.file_path)?;
+    if let Some(focal_point) = photo.focal_point() {
+        rendition.focal_point = focal_point;
+    }
+    let etag = ETag::new(format!(
+        "{}-r{}-{}",
+        photo.file_hash,
+        photo.rotation,
+        rendition.cache_key()
+    ))
+    .with_cache_control("public, max-age=86400");
+    if let Some(res) = etag.not_modified(&req) {
+        return Ok(res);
+    }
+
+    let file_name = Path::new(&photo.file_name)
+        .with_extension(rendition.format.extension())
+        .to_string_lossy()
+        .into_owned();
+    let content_type = rendition.format.content_type();
+
+    let library_root = Library::get_root_of(&photo.file_path, &pool).await?;
+    let file = web::block(move || -> Result<fs::NamedFile, ServiceError> {
+        let path = renditions::get_or_create(
+            &library_root,
+            &photo.file_path,
+            &photo.file_hash,
+            photo.rotation,
+            &rendition,
+        )?;
+
+        Ok(fs::NamedFile::open(path)?)
     })
-    .await;
-
-    match res {
-        Ok(resp) => Ok(HttpResponse::Ok()
-            .header(
-                header::CONTENT_DISPOSITION,
-                header::ContentDisposition {
-                    disposition: header::DispositionType::Inline,
-                    parameters: vec![header::DispositionParam::Filename(file_name.to_owned())],
-                },
-            )
-            .content_type(content_type)
-            .content_length(resp.len() as u64)
-            .body(resp)),
-        Err(err) => Ok(ApiResponse::error(err.to_string())),
+    .await
+    .map_err(ServiceError::from)?;
+
+    // the file checks `Range` itself. The rendition is cached under its parameters, so the ETag
+    // covers it and the modification time of the cached file means nothing to the client.
+    let mut res = file
+        .set_content_disposition(header::ContentDisposition {
+            disposition: header::DispositionType::Inline,
+            parameters: vec![header::DispositionParam::Filename(file_name)],
+        })
+        .use_etag(false)
+        .use_last_modified(false)
+        .into_response(&req)?;
+    res.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static(content_type),
+    );
+    etag.apply(&mut res);
+
+    Ok(res)
+}
+
+// STREAMING ***************************************************************************************
+
+/// Streams a file from disk. Range requests are answered with the requested bytes and conditional
+/// requests with a 304 when the file has not changed. With a `file_hash` the `ETag` is derived from
+/// the contents, so it stays the same when the file is copied or touched. Otherwise it is derived
+/// from the size and modification time.
+fn stream_file(req: &HttpRequest, path: &Path, file_hash: Option<&str>) -> HandlerResult {
+    let file = fs::NamedFile::open(path).map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound => {
+            ServiceError::NotFound(format!("File {} does not exist", path.display()))
+        }
+        _ => ServiceError::IOError(err),
+    })?;
+
+    let file_ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default();
+    let content_type = header::HeaderValue::from_str(&content_type(file_ext))?;
+
+    let mut res = match file_hash {
+        Some(file_hash) => {
+            let etag = ETag::new(file_hash.to_string());
+            if let Some(res) = etag.not_modified(req) {
+                return Ok(res);
+            }
+
+            // the file checks `Range` itself. An `If-None-Match` that did not match takes
+            // precedence over `If-Modified-Since` (RFC 7232 §3.3), so in that case the modification
+            // time is left out of the check and only sent back as `Last-Modified`.
+            let if_none_match = req.headers().contains_key(header::IF_NONE_MATCH);
+            let last_modified = file
+                .file()
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            let mut res = file
+                .use_etag(false)
+                .use_last_modified(!if_none_match)
+                .into_response(req)?;
+            etag.apply(&mut res);
+
+            if let (true, Some(last_modified)) = (if_none_match, last_modified) {
+                res.headers_mut().insert(
+                    header::LAST_MODIFIED,
+                    header::HeaderValue::from_str(
+                        &header::HttpDate::from(last_modified).to_string(),
+                    )?,
+                );
+            }
+
+            res
+        }
+        None => file.into_response(req)?,
+    };
+
+    res.headers_mut().insert(header::CONTENT_TYPE, content_type);
+
+    Ok(res)
+}
+
+/// Returns the MIME type of a file served from the media directory. The guess made from the
+/// extension is corrected for formats that browsers only play or display with a specific type.
+fn content_type(file_ext: &str) -> String {
+    match file_ext.to_lowercase().as_str() {
+        // MPEG-4 clips from Apple devices, which browsers do not play as `video/x-m4v`
+        "m4v" => "video/mp4".to_string(),
+        "heic" => "image/heic".to_string(),
+        "heif" => "image/heif".to_string(),
+        file_ext => {
+            let file_mime = fs::file_extension_to_mime(file_ext);
+            format!("{}/{}", file_mime.type_(), file_mime.subtype())
+        }
     }
 }
@@ -6,13 +6,21 @@ use crate::types::HandlerResult;
 
 pub mod collections;
 pub mod directory_tree;
+pub mod duplicates;
 pub mod entity;
+pub mod framing;
+pub mod integrity;
+pub mod libraries;
 pub mod media;
 pub mod photos;
+pub mod scan_issues;
 pub mod scan_photos;
 pub mod stats;
 pub mod tags;
+pub mod thumbnails;
+pub mod trash;
 pub mod wallpapers;
+pub mod xmp;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Route {
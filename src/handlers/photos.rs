@@ -1,11 +1,22 @@
-use actix_web::{delete, get, post, web};
+use actix_web::{delete, get, patch, post, web};
 use deadpool_postgres::Pool;
 
+use crate::errors::ServiceError;
+use crate::files::wallpapers;
+use crate::requests::bulk_photos_request::BulkPhotosRequest;
 use crate::requests::get_photos_request::GetPhotosRequest;
+use crate::requests::render_wallpaper_request::RenderWallpaperRequest;
+use crate::requests::similar_photos_request::SimilarPhotosRequest;
+use crate::requests::update_photo_request::UpdatePhotoRequest;
 use crate::responses::api_response::ApiResponse;
 use crate::schemas;
+use crate::schemas::bulk_photos::BulkPhotosResult;
+use crate::schemas::framing::PhotoFraming;
 use crate::schemas::photo::Photo;
 use crate::schemas::photo_full::PhotoFull;
+use crate::schemas::photo_path_history::PhotoPathHistory;
+use crate::schemas::trash::TrashedPhoto;
+use crate::schemas::wallpaper_sizes::WallpaperSize;
 use crate::types::HandlerResult;
 
 // ALL PHOTOS **************************************************************************************
@@ -31,19 +42,56 @@ pub async fn get_photo(info: web::Path<i32>, pool: web::Data<Pool>) -> HandlerRe
     Ok(ApiResponse::success(photo))
 }
 
+// SIMILAR PHOTOS **********************************************************************************
+
+#[get("/photos/{photo_id}/similar")]
+pub async fn get_similar_photos(
+    info: web::Path<i32>,
+    query: web::Query<SimilarPhotosRequest>,
+    pool: web::Data<Pool>,
+) -> HandlerResult {
+    let photo_id: i32 = info.into_inner();
+    let max_distance = query.into_inner().get_max_distance()?;
+
+    let photo = Photo::get_visible(photo_id, &pool).await?;
+    if photo.perceptual_hash.is_none() {
+        return Err(ServiceError::BadRequest(format!(
+            "Photo {} has no perceptual hash. Run a scan to compute it.",
+            photo_id
+        ))
+        .into());
+    }
+
+    let photos = PhotoFull::get_similar(photo_id, max_distance as i32, &pool).await?;
+
+    Ok(ApiResponse::success(photos))
+}
+
+// PATH HISTORY ************************************************************************************
+
+#[get("/photos/{photo_id}/history")]
+pub async fn get_photo_history(info: web::Path<i32>, pool: web::Data<Pool>) -> HandlerResult {
+    let photo_id: i32 = info.into_inner();
+
+    // fail with the usual error for unknown photos rather than returning an empty history
+    let photo = Photo::get_visible(photo_id, &pool).await?;
+    let history = PhotoPathHistory::get_for_photo(photo.id, &pool).await?;
+
+    Ok(ApiResponse::success(history))
+}
+
 // UPDATE PHOTO ************************************************************************************
 
-// #[post("/photos/{photo_id}")]
-// pub async fn update_photo(
-//     _: web::Path<i32>,
-//     info: web::Json<Photo>,
-//     pool: web::Data<Pool>,
-// ) -> HandlerResult {
-//     println!("{:?}", &info);
-//     let photo = Photo::update_photo(info.into_inner(), &pool).await?;
-//
-//     Ok(ApiResponse::success(photo))
-// }
+#[patch("/photos/{photo_id}")]
+pub async fn update_photo(
+    info: web::Path<i32>,
+    update: web::Json<UpdatePhotoRequest>,
+    pool: web::Data<Pool>,
+) -> HandlerResult {
+    let photo = apply_update(info.into_inner(), &update, &pool).await?;
+
+    Ok(ApiResponse::success(photo))
+}
 
 #[post("/photos/{photo_id}/viewed")]
 pub async fn update_photo_last_viewed(
@@ -61,21 +109,51 @@ pub async fn update_photo_rating(
     pool: web::Data<Pool>,
 ) -> HandlerResult {
     let (photo_id, rating) = info.into_inner();
-    let mut photo = Photo::get_by_id(photo_id, &pool).await?;
-    photo.rating = rating;
+    let update = UpdatePhotoRequest {
+        rating: Some(rating),
+        ..Default::default()
+    };
 
-    let photo = Photo::update_photo(photo, &pool).await?;
+    let photo = apply_update(photo_id, &update, &pool).await?;
 
     Ok(ApiResponse::success(photo))
 }
 
+async fn apply_update(
+    photo_id: i32,
+    update: &UpdatePhotoRequest,
+    pool: &Pool,
+) -> Result<PhotoFull, ServiceError> {
+    let photo = Photo::get_visible(photo_id, pool).await?;
+
+    update.validate(&photo)?;
+
+    Photo::update_fields(photo_id, update, pool).await
+}
+
+// BULK UPDATE *************************************************************************************
+
+#[post("/photos/bulk")]
+pub async fn bulk_update_photos(
+    info: web::Json<BulkPhotosRequest>,
+    pool: web::Data<Pool>,
+) -> HandlerResult {
+    let request = info.into_inner();
+    request.validate()?;
+
+    let result = BulkPhotosResult::apply(&request, &pool).await?;
+
+    Ok(ApiResponse::success(result))
+}
+
 // DELETE PHOTO ************************************************************************************
 
+/// Moves the photo into the trash, see `/trash`
 #[delete("/photos/{id}")]
 pub async fn delete_photo(info: web::Path<i32>, pool: web::Data<Pool>) -> HandlerResult {
-    let message = Photo::delete_photo(info.into_inner(), &pool).await?;
+    let photo = TrashedPhoto::trash(info.into_inner(), &pool).await?;
 
-    Ok(ApiResponse::success(message))
+    Ok(ApiResponse::success(photo))
 }
 
 // PHOTO ENTITIES **********************************************************************************
@@ -128,27 +206,53 @@ pub async fn remove_tag_from_photo(
 
 // PHOTO WALLPAPERS ********************************************************************************
 
-#[derive(serde::Serialize, serde::Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct NewWallpaper {
-    pub file_path: String,
-}
-
+/// Renders the wallpaper of a photo in a wallpaper size from the original file, writes it to the
+/// wallpaper directory and records it for the photo. The wallpaper is framed by the crop or focal
+/// point stored for the photo unless the query overrides them.
 #[post("/photos/{photo_id}/wallpaper/{wallpaper_size_id}")]
 pub async fn add_wallpaper_to_photo(
     params: web::Path<(i32, i32)>,
-    info: web::Json<NewWallpaper>,
+    info: web::Query<RenderWallpaperRequest>,
     pool: web::Data<Pool>,
 ) -> HandlerResult {
     let (photo_id, wallpaper_size_id) = params.into_inner();
 
-    let message = Photo::add_wallpaper_to_photo(
-        photo_id,
-        wallpaper_size_id,
-        info.into_inner().file_path,
-        &pool,
-    )
-    .await?;
+    let photo = PhotoFull::get_by_id(photo_id, &pool).await?;
+
+    if photo.media_type == "video" {
+        return Err(ServiceError::BadRequest(
+            "Wallpapers cannot be rendered from videos".to_string(),
+        )
+        .into());
+    }
+
+    if photo.ineligible_for_wallpaper {
+        return Err(ServiceError::BadRequest(format!(
+            "Photo {} is ineligible for wallpapers",
+            photo_id
+        ))
+        .into());
+    }
+
+    let size = WallpaperSize::get_by_id(wallpaper_size_id, &pool).await?;
+    let stored = PhotoFraming::get(photo_id, &pool).await?;
+    let framing = info.framing(stored.for_size(wallpaper_size_id))?;
+
+    let path = web::block(move || -> Result<String, ServiceError> {
+        let path = wallpapers::render(
+            &photo.file_path,
+            photo.rotation,
+            &size,
+            &framing,
+            &photo.wallpaper_file_name,
+        )?;
+
+        Ok(path.to_string_lossy().into_owned())
+    })
+    .await
+    .map_err(ServiceError::from)?;
+
+    let message = Photo::add_wallpaper_to_photo(photo_id, wallpaper_size_id, path, &pool).await?;
 
     Ok(ApiResponse::success(message))
 }
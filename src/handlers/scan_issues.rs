@@ -0,0 +1,53 @@
+use actix_web::{get, post, web};
+use deadpool_postgres::Pool;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::ServiceError;
+use crate::responses::api_response::ApiResponse;
+use crate::schemas::scan_issues::{ScanIssue, SCAN_ISSUE_STATUSES};
+use crate::types::HandlerResult;
+
+// ALL SCAN ISSUES *********************************************************************************
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetScanIssuesRequest {
+    pub status: Option<String>,
+}
+
+#[get("/scan/issues")]
+pub async fn get_scan_issues(
+    info: web::Query<GetScanIssuesRequest>,
+    pool: web::Data<Pool>,
+) -> HandlerResult {
+    let status = info.into_inner().status;
+
+    if let Some(status) = &status {
+        if !SCAN_ISSUE_STATUSES.contains(&status.as_str()) {
+            return Err(
+                ServiceError::BadRequest(format!("Unknown scan issue status: {}", status)).into(),
+            );
+        }
+    }
+
+    let issues = ScanIssue::get_all(status.as_deref(), &pool).await?;
+
+    Ok(ApiResponse::success(issues))
+}
+
+// RETRY SCAN ISSUE ********************************************************************************
+
+#[post("/scan/issues/{id}/retry")]
+pub async fn retry_scan_issue(info: web::Path<i32>, pool: web::Data<Pool>) -> HandlerResult {
+    let photo = ScanIssue::retry(info.into_inner(), &pool).await?;
+
+    Ok(ApiResponse::success(photo))
+}
+
+// IGNORE SCAN ISSUE *******************************************************************************
+
+#[post("/scan/issues/{id}/ignore")]
+pub async fn ignore_scan_issue(info: web::Path<i32>, pool: web::Data<Pool>) -> HandlerResult {
+    let issue = ScanIssue::ignore(info.into_inner(), &pool).await?;
+
+    Ok(ApiResponse::success(issue))
+}
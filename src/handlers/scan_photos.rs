@@ -1,14 +1,15 @@
-use std::path::Path;
-
-use actix_web::{get, web};
+use actix_web::{get, patch, post, web};
 use deadpool_postgres::Pool;
-use num_format::{Locale, ToFormattedString};
 use serde::{Deserialize, Serialize};
 
-use crate::{files, schemas};
-use crate::files::photos::FileScanResult;
+use crate::files::photos::{FileScanResult, ScanPlan};
+use crate::files::scan_rules::ExcludedFiles;
+use crate::jobs::scan;
+use crate::jobs::scan::ScanJobs;
 use crate::responses::api_response::ApiResponse;
-use crate::schemas::new_photo::NewPhoto;
+use crate::schemas::libraries::Library;
+use crate::schemas::scan_runs::ScanRun;
+use crate::schemas::scan_settings::ScanSettings;
 use crate::types::HandlerResult;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -20,6 +21,13 @@ pub struct ScanPhotosResult {
     pub existing_photos: i32,
     pub updated_photos: i32,
     pub deleted_photos: i32,
+    pub hashed_photos: i32,
+    pub duplicate_groups: i32,
+    pub quarantined_files: i32,
+    pub excluded_files: ExcludedFiles,
+    pub dry_run: bool,
+    /// Per-file changes, only filled in for dry runs
+    pub plan: Option<ScanPlan>,
 }
 
 impl Default for ScanPhotosResult {
@@ -31,12 +39,18 @@ impl Default for ScanPhotosResult {
             existing_photos: 0,
             updated_photos: 0,
             deleted_photos: 0,
+            hashed_photos: 0,
+            duplicate_groups: 0,
+            quarantined_files: 0,
+            excluded_files: ExcludedFiles::default(),
+            dry_run: false,
+            plan: None,
         }
     }
 }
 
 impl ScanPhotosResult {
-    pub fn from_file_scan_result(folder: String, result: &FileScanResult) -> Self {
+    pub fn from_file_scan_result(folder: String, result: &FileScanResult, dry_run: bool) -> Self {
         ScanPhotosResult {
             folder_scanned: folder,
             new_photos_found: result.new_photos_count > 0,
@@ -44,6 +58,16 @@ impl ScanPhotosResult {
             existing_photos: result.existing_photos_count,
             updated_photos: result.updated_photos_count,
             deleted_photos: result.deleted_photos_count,
+            hashed_photos: result.hashed_photos_count,
+            duplicate_groups: result.duplicate_groups_count,
+            quarantined_files: result.issues.len() as i32,
+            excluded_files: result.excluded.clone(),
+            dry_run,
+            plan: if dry_run {
+                Some(result.plan.clone())
+            } else {
+                None
+            },
         }
     }
 }
@@ -51,6 +75,7 @@ impl ScanPhotosResult {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ScanPhotosRequest {
     pub folder: Option<String>,
+    pub dry_run: Option<bool>,
 }
 
 impl ScanPhotosRequest {
@@ -60,51 +85,90 @@ impl ScanPhotosRequest {
             .unwrap_or_else(|| String::from(""))
             .replace('\"', "")
     }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run.unwrap_or(false)
+    }
 }
 
 #[get("/scan")]
-pub async fn run_scan(info: web::Query<ScanPhotosRequest>, pool: web::Data<Pool>) -> HandlerResult {
-    let folder = info.get_folder();
-    let pool = pool.get_ref();
-
-    // Check if path exists before scanning
-    if !Path::new(&folder).exists() {
-        return Ok(ApiResponse::error(format!(
-            "Directory not found: {}",
-            folder
-        )));
+pub async fn run_scan(
+    info: web::Query<ScanPhotosRequest>,
+    pool: web::Data<Pool>,
+    scan_jobs: web::Data<ScanJobs>,
+) -> HandlerResult {
+    let mut folder = info.get_folder();
+
+    // only directories inside of a library can be scanned
+    if !folder.is_empty() {
+        let libraries = Library::get_all(&pool).await?;
+        folder = Library::resolve_dir(&libraries, &folder)?;
     }
 
-    println!("Scanning {}...", &folder);
+    let job = scan_jobs.submit(&folder, info.is_dry_run())?;
+    actix_rt::spawn(scan::run_scan_job(job.clone(), pool.get_ref().clone()));
 
-    let file_scan_result = if !folder.is_empty() {
-        files::photos::scan_all_photos_from_dir(&folder, pool).await
-    } else {
-        files::photos::scan_all_photos(pool).await
-    };
+    Ok(ApiResponse::success(job.report()))
+}
 
-    if let Err(err) = file_scan_result {
-        return Ok(ApiResponse::error(err.to_string()));
-    }
-    let file_scan_result = file_scan_result.unwrap();
-
-    if file_scan_result.new_photos_count > 0 {
-        println!(
-            "Insert {} new photos into database...",
-            &file_scan_result
-                .new_photos_count
-                .to_formatted_string(&Locale::en)
-        );
-        NewPhoto::bulk_insert(&file_scan_result.new_photos, pool).await?;
-    }
+// SCAN JOBS ***************************************************************************************
+
+#[get("/scan/jobs")]
+pub async fn get_scan_jobs(scan_jobs: web::Data<ScanJobs>) -> HandlerResult {
+    let jobs = scan_jobs.get_all();
+
+    Ok(ApiResponse::success(jobs))
+}
+
+#[get("/scan/jobs/{id}")]
+pub async fn get_scan_job(info: web::Path<i32>, scan_jobs: web::Data<ScanJobs>) -> HandlerResult {
+    let job = scan_jobs.get(info.into_inner())?;
+
+    Ok(ApiResponse::success(job.report()))
+}
 
-    // refresh random order view
-    println!("Refresh random seed...");
-    schemas::reset_seed(&pool).await?;
+#[post("/scan/jobs/{id}/cancel")]
+pub async fn cancel_scan_job(
+    info: web::Path<i32>,
+    scan_jobs: web::Data<ScanJobs>,
+) -> HandlerResult {
+    let job = scan_jobs.get(info.into_inner())?;
+    job.cancel()?;
 
-    let result = ScanPhotosResult::from_file_scan_result(folder, &file_scan_result);
+    Ok(ApiResponse::success(job.report()))
+}
+
+// SCAN HISTORY ************************************************************************************
+
+#[get("/scans")]
+pub async fn get_scan_runs(pool: web::Data<Pool>) -> HandlerResult {
+    let runs = ScanRun::get_all(&pool).await?;
+
+    Ok(ApiResponse::success(runs))
+}
+
+#[get("/scans/{id}")]
+pub async fn get_scan_run(info: web::Path<i32>, pool: web::Data<Pool>) -> HandlerResult {
+    let run = ScanRun::get_details(info.into_inner(), &pool).await?;
+
+    Ok(ApiResponse::success(run))
+}
+
+// SCAN SETTINGS ***********************************************************************************
+
+#[get("/scan/settings")]
+pub async fn get_scan_settings(pool: web::Data<Pool>) -> HandlerResult {
+    let settings = ScanSettings::get(&pool).await?;
+
+    Ok(ApiResponse::success(settings))
+}
 
-    println!("Done!");
+#[patch("/scan/settings")]
+pub async fn update_scan_settings(
+    params: web::Json<ScanSettings>,
+    pool: web::Data<Pool>,
+) -> HandlerResult {
+    let settings = ScanSettings::update(params.into_inner(), &pool).await?;
 
-    Ok(ApiResponse::success(result))
+    Ok(ApiResponse::success(settings))
 }
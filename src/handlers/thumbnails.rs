@@ -0,0 +1,78 @@
+use std::fs;
+
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use deadpool_postgres::Pool;
+
+use crate::errors::ServiceError;
+use crate::files::renditions::Framing;
+use crate::files::thumbnails;
+use crate::responses::etag::ETag;
+use crate::schemas::libraries::Library;
+use crate::schemas::photo::Photo;
+use crate::types::HandlerResult;
+
+// GET THUMBNAIL ***********************************************************************************
+
+/// Serves a thumbnail of a photo, generating it on first request. Thumbnail urls change with the
+/// file hash and rotation of the photo, so clients may cache the response for as long as they like.
+/// Cover thumbnails also change with the focal point, clients have to check those with the `ETag`.
+#[get("/thumbnails/{photo_id}/{size}")]
+pub async fn get_thumbnail(
+    req: HttpRequest,
+    info: web::Path<(i32, String)>,
+    pool: web::Data<Pool>,
+) -> HandlerResult {
+    let (photo_id, size_name) = info.into_inner();
+
+    let size = thumbnails::find_size(&size_name)
+        .ok_or_else(|| ServiceError::NotFound(format!("Unknown thumbnail size {}", size_name)))?;
+
+    let photo = Photo::get_visible(photo_id, &pool).await?;
+    if !thumbnails::can_generate(&photo.file_path) {
+        return Err(ServiceError::NotFound(format!("Photo {} has no thumbnails", photo_id)).into());
+    }
+
+    let focal_point = photo
+        .focal_point()
+        .unwrap_or(Framing::default().focal_point);
+    let etag = ETag::new(format!(
+        "{}-{}-{}{}",
+        photo.file_hash,
+        photo.rotation,
+        size.name,
+        size.variant(focal_point)
+    ));
+    // the url of a thumbnail does not change with the focal point, so cover thumbnails have to be
+    // revalidated
+    let etag = if size.cover {
+        etag.with_cache_control("public, no-cache")
+    } else {
+        etag.with_cache_control("public, max-age=31536000, immutable")
+    };
+    if let Some(res) = etag.not_modified(&req) {
+        return Ok(res);
+    }
+
+    let library_root = Library::get_root_of(&photo.file_path, &pool).await?;
+    let thumbnail = web::block(move || -> Result<Vec<u8>, ServiceError> {
+        let path = thumbnails::get_or_create(
+            &library_root,
+            &photo.file_path,
+            &photo.file_hash,
+            photo.rotation,
+            focal_point,
+            &size,
+        )?;
+
+        Ok(fs::read(path)?)
+    })
+    .await
+    .map_err(ServiceError::from)?;
+
+    let mut res = HttpResponse::Ok()
+        .content_type("image/jpeg")
+        .body(thumbnail);
+    etag.apply(&mut res);
+
+    Ok(res)
+}
@@ -0,0 +1,36 @@
+use actix_web::{delete, get, post, web};
+use deadpool_postgres::Pool;
+
+use crate::responses::api_response::ApiResponse;
+use crate::schemas::trash::TrashedPhoto;
+use crate::types::HandlerResult;
+
+// TRASH *******************************************************************************************
+
+#[get("/trash")]
+pub async fn get_trash(pool: web::Data<Pool>) -> HandlerResult {
+    let photos = TrashedPhoto::get_all(&pool).await?;
+
+    Ok(ApiResponse::success(photos))
+}
+
+// RESTORE PHOTO ***********************************************************************************
+
+#[post("/trash/{id}/restore")]
+pub async fn restore_photo(info: web::Path<i32>, pool: web::Data<Pool>) -> HandlerResult {
+    let photo = TrashedPhoto::restore(info.into_inner(), &pool).await?;
+
+    Ok(ApiResponse::success(photo))
+}
+
+// EMPTY TRASH *************************************************************************************
+
+#[delete("/trash")]
+pub async fn empty_trash(pool: web::Data<Pool>) -> HandlerResult {
+    let count = TrashedPhoto::empty(&pool).await?;
+
+    Ok(ApiResponse::success(format!(
+        "Deleted {} photo(s) from the trash",
+        count
+    )))
+}
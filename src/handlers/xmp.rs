@@ -0,0 +1,36 @@
+use actix_web::{post, web};
+use deadpool_postgres::Pool;
+
+use crate::errors::ServiceError;
+use crate::files::xmp::{self, SidecarExport};
+use crate::responses::api_response::ApiResponse;
+use crate::schemas::photo_full::PhotoFull;
+use crate::types::HandlerResult;
+
+// EXPORT PHOTO SIDECAR ****************************************************************************
+
+#[post("/photos/{photo_id}/xmp")]
+pub async fn export_photo_sidecar(info: web::Path<i32>, pool: web::Data<Pool>) -> HandlerResult {
+    let photo = PhotoFull::get_by_id(info.into_inner(), &pool).await?;
+
+    let export = web::block(move || -> Result<SidecarExport, ServiceError> {
+        Ok(xmp::export_sidecars(&[photo]))
+    })
+    .await?;
+
+    Ok(ApiResponse::success(export))
+}
+
+// EXPORT ALL SIDECARS *****************************************************************************
+
+#[post("/xmp/export")]
+pub async fn export_sidecars(pool: web::Data<Pool>) -> HandlerResult {
+    let photos = PhotoFull::get_all(&pool).await?;
+
+    let export = web::block(move || -> Result<SidecarExport, ServiceError> {
+        Ok(xmp::export_sidecars(&photos))
+    })
+    .await?;
+
+    Ok(ApiResponse::success(export))
+}
@@ -0,0 +1,128 @@
+use std::env;
+use std::time::Duration;
+
+use actix_rt::time::delay_for;
+use actix_web::web;
+use deadpool_postgres::Pool;
+
+use crate::errors::ServiceError;
+use crate::files::integrity::{self, Verification};
+use crate::jobs::scan::ScanJobs;
+use crate::schemas::integrity::IntegrityRun;
+
+/// Number of photos verified between progress updates. An interrupted run repeats at most this
+/// many photos after a restart.
+const BATCH_SIZE: i64 = 50;
+/// How often the job checks whether a new run is due, or whether a scan has finished
+const IDLE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const SCAN_WAIT_INTERVAL: Duration = Duration::from_secs(60);
+
+// INTEGRITY CONFIG ********************************************************************************
+
+pub struct IntegrityConfig {
+    pub enabled: bool,
+    /// Throttle for reading files, `0` reads at full speed
+    pub bytes_per_second: u64,
+    pub interval_days: i32,
+}
+
+impl IntegrityConfig {
+    /// Builds the config from the `SCARLETT_INTEGRITY` (`true` or `1` to enable),
+    /// `SCARLETT_INTEGRITY_RATE` (MiB per second, defaults to 10, `0` for no limit) and
+    /// `SCARLETT_INTEGRITY_INTERVAL_DAYS` (days between runs, defaults to 30) environment variables
+    pub fn from_env() -> Self {
+        let enabled = env::var("SCARLETT_INTEGRITY")
+            .map(|value| value == "true" || value == "1")
+            .unwrap_or(false);
+
+        let rate = env::var("SCARLETT_INTEGRITY_RATE")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(10);
+
+        let interval_days = env::var("SCARLETT_INTEGRITY_INTERVAL_DAYS")
+            .ok()
+            .and_then(|value| value.parse::<i32>().ok())
+            .unwrap_or(30);
+
+        IntegrityConfig {
+            enabled,
+            bytes_per_second: rate * 1024 * 1024,
+            interval_days,
+        }
+    }
+}
+
+// START VERIFICATION ******************************************************************************
+
+/// Re-hashes every photo in the background and reports the ones whose file no longer matches the
+/// stored hash. A run continues from its last batch after a restart, and a new one is started
+/// `interval_days` after the previous one finished. Verification pauses while a scan is running,
+/// since scans legitimately update hashes. Photos that the file watcher, a retried scan issue or
+/// the trash change while their batch is verified are left out when the batch is recorded.
+pub fn start(config: IntegrityConfig, pool: Pool, scan_jobs: ScanJobs) {
+    actix_rt::spawn(async move {
+        loop {
+            while scan_jobs.has_active() {
+                delay_for(SCAN_WAIT_INTERVAL).await;
+            }
+
+            match verify_next_batch(&config, &pool).await {
+                Ok(true) => {}
+                Ok(false) => delay_for(IDLE_INTERVAL).await,
+                Err(err) => {
+                    println!("Unable to verify library integrity: {}", err);
+                    delay_for(IDLE_INTERVAL).await;
+                }
+            }
+        }
+    });
+}
+
+/// Verifies the next batch of the current run. Returns `false` if there is nothing left to verify
+/// until the next run is due.
+async fn verify_next_batch(config: &IntegrityConfig, pool: &Pool) -> Result<bool, ServiceError> {
+    let run = match IntegrityRun::resume_or_start(config.interval_days, pool).await? {
+        Some(run) => run,
+        None => return Ok(false),
+    };
+
+    if run.last_photo_id == 0 {
+        println!("Start verifying library integrity...");
+    }
+
+    let files = IntegrityRun::get_next_files(run.last_photo_id, BATCH_SIZE, pool).await?;
+
+    if files.is_empty() {
+        IntegrityRun::complete(run.id, pool).await?;
+        println!(
+            "Verified library integrity: {} photo(s) checked, {} mismatched, {} missing, {} \
+             unreadable",
+            run.checked_photos, run.mismatched_files, run.missing_files, run.unreadable_files
+        );
+
+        return Ok(false);
+    }
+
+    let bytes_per_second = config.bytes_per_second;
+    let verified = web::block(move || -> Result<Vec<(_, Verification)>, ServiceError> {
+        Ok(files
+            .into_iter()
+            .map(|file| {
+                let verification = integrity::verify(&file, bytes_per_second);
+                (file, verification)
+            })
+            .collect())
+    })
+    .await?;
+
+    for (file, verification) in &verified {
+        if let Some(kind) = verification.kind() {
+            println!("Integrity check failed for {}: {}", file.file_path, kind);
+        }
+    }
+
+    IntegrityRun::record_batch(run.id, &verified, pool).await?;
+
+    Ok(true)
+}
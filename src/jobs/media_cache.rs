@@ -0,0 +1,51 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::time::Duration;
+
+use actix_rt::time::delay_for;
+use actix_web::web;
+use deadpool_postgres::Pool;
+
+use crate::errors::ServiceError;
+use crate::files::renditions;
+use crate::schemas::libraries::Library;
+
+/// How often the media caches are checked against their size limit
+const EVICTION_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Keeps the media cache of every library below `max_bytes`, once right away and then every ten
+/// minutes
+pub fn start(max_bytes: u64, pool: Pool) {
+    actix_rt::spawn(async move {
+        loop {
+            match evict(max_bytes, &pool).await {
+                Ok(0) => {}
+                Ok(count) => println!("Evicted {} rendition(s) from the media cache", count),
+                Err(err) => println!("Unable to evict renditions from the media cache: {}", err),
+            }
+
+            delay_for(EVICTION_INTERVAL).await;
+        }
+    });
+}
+
+async fn evict(max_bytes: u64, pool: &Pool) -> Result<usize, ServiceError> {
+    // libraries share one directory when `SCARLETT_MEDIA_CACHE_DIR` is set
+    let cache_dirs: BTreeSet<_> = Library::get_all(pool)
+        .await?
+        .iter()
+        .map(|library| renditions::media_cache_dir(Path::new(&library.root_path)))
+        .collect();
+
+    let evicted = web::block(move || -> Result<usize, ServiceError> {
+        let mut evicted = 0;
+        for cache_dir in cache_dirs.iter().filter(|dir| dir.is_dir()) {
+            evicted += renditions::evict(cache_dir, max_bytes)?;
+        }
+
+        Ok(evicted)
+    })
+    .await?;
+
+    Ok(evicted)
+}
@@ -0,0 +1,5 @@
+pub mod integrity;
+pub mod media_cache;
+pub mod scan;
+pub mod trash_purge;
+pub mod watcher;
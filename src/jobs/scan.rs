@@ -0,0 +1,524 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI32, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use chrono::{NaiveDateTime, Utc};
+use deadpool_postgres::Pool;
+use num_format::{Locale, ToFormattedString};
+use serde::Serialize;
+
+use crate::errors::ServiceError;
+use crate::files;
+use crate::files::photos::FileScanResult;
+use crate::handlers::scan_photos::ScanPhotosResult;
+use crate::schemas;
+use crate::schemas::new_photo::NewPhoto;
+use crate::schemas::scan_runs::ScanRun;
+
+/// Number of new photos inserted per batch
+const INSERT_BATCH_SIZE: usize = 500;
+
+/// Whether a scan can still be cancelled, see `ScanProgress::begin_writes`
+const CANCELLABLE: u8 = 0;
+const CANCELLED: u8 = 1;
+const WRITING: u8 = 2;
+
+// SCAN PHASE & STATUS *****************************************************************************
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ScanPhase {
+    Queued,
+    Collecting,
+    Fingerprinting,
+    Hashing,
+    ExifExtraction,
+    HeifDimensions,
+    PerceptualHashing,
+    DuplicateCheck,
+    MoveDetection,
+    DeletionCheck,
+    Insert,
+    Thumbnails,
+    Finished,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ScanStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+// PHASE TIMING ************************************************************************************
+
+/// How long a finished phase took and how many files it got through
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PhaseTiming {
+    pub phase: ScanPhase,
+    pub seconds: f64,
+    pub files: usize,
+    pub files_per_second: Option<f64>,
+}
+
+impl PhaseTiming {
+    fn new(phase: ScanPhase, started: Instant, files: usize) -> Self {
+        let seconds = started.elapsed().as_secs_f64();
+
+        PhaseTiming {
+            phase,
+            seconds,
+            files,
+            files_per_second: files_per_second(files, seconds),
+        }
+    }
+}
+
+fn files_per_second(files: usize, seconds: f64) -> Option<f64> {
+    if files == 0 || seconds <= 0.0 {
+        None
+    } else {
+        Some(files as f64 / seconds)
+    }
+}
+
+// SCAN PROGRESS ***********************************************************************************
+
+/// Progress of a single scan, shared between the job registry and the threads doing the work
+pub struct ScanProgress {
+    phase: Mutex<(ScanPhase, Instant)>,
+    files_total: AtomicUsize,
+    files_processed: AtomicUsize,
+    cancellation: AtomicU8,
+    timings: Mutex<Vec<PhaseTiming>>,
+}
+
+impl Default for ScanProgress {
+    fn default() -> Self {
+        ScanProgress {
+            phase: Mutex::new((ScanPhase::Queued, Instant::now())),
+            files_total: AtomicUsize::new(0),
+            files_processed: AtomicUsize::new(0),
+            cancellation: AtomicU8::new(CANCELLABLE),
+            timings: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl ScanProgress {
+    /// Moves the scan into a new phase and resets the file counters. The timing of the previous
+    /// phase is recorded. `files_total` should be 0 if the number of files is not known up front.
+    pub fn set_phase(&self, phase: ScanPhase, files_total: usize) {
+        let mut current = self.phase.lock().unwrap();
+
+        if current.0 != ScanPhase::Queued {
+            let timing = PhaseTiming::new(
+                current.0,
+                current.1,
+                self.files_processed.load(Ordering::SeqCst),
+            );
+
+            println!(
+                "Scan phase {:?} took {:.2}s for {} files ({:.0} files/s)",
+                timing.phase,
+                timing.seconds,
+                timing.files.to_formatted_string(&Locale::en),
+                timing.files_per_second.unwrap_or_default()
+            );
+
+            self.timings.lock().unwrap().push(timing);
+        }
+
+        println!("Scan phase: {:?}", phase);
+
+        *current = (phase, Instant::now());
+        self.files_total.store(files_total, Ordering::SeqCst);
+        self.files_processed.store(0, Ordering::SeqCst);
+    }
+
+    pub fn phase(&self) -> ScanPhase {
+        self.phase.lock().unwrap().0
+    }
+
+    pub fn advance(&self, files: usize) {
+        self.files_processed.fetch_add(files, Ordering::SeqCst);
+    }
+
+    /// Requests cancellation. Returns `false` if the scan has already started writing and can no
+    /// longer be cancelled.
+    pub fn cancel(&self) -> bool {
+        self.cancellation
+            .compare_exchange(CANCELLABLE, CANCELLED, Ordering::SeqCst, Ordering::SeqCst)
+            .map_or_else(|state| state == CANCELLED, |_| true)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.load(Ordering::SeqCst) == CANCELLED
+    }
+
+    /// Returns `ScanCancelled` once a cancellation has been requested so that scan phases can bail
+    /// out with `?` between files
+    pub fn check_cancelled(&self) -> Result<(), ServiceError> {
+        if self.is_cancelled() {
+            Err(ServiceError::ScanCancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Must be called before the scan first writes to the database. A scan that was cancelled up
+    /// to this point returns `ScanCancelled` with nothing written, from here on cancelling is
+    /// refused so that a scan either applies all of its changes or none of them.
+    pub fn begin_writes(&self) -> Result<(), ServiceError> {
+        match self.cancellation.compare_exchange(
+            CANCELLABLE,
+            WRITING,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        ) {
+            Err(CANCELLED) => Err(ServiceError::ScanCancelled),
+            _ => Ok(()),
+        }
+    }
+
+    /// Throughput of the current phase so far
+    fn files_per_second(&self) -> Option<f64> {
+        let started = self.phase.lock().unwrap().1;
+        let processed = self.files_processed.load(Ordering::SeqCst);
+
+        files_per_second(processed, started.elapsed().as_secs_f64())
+    }
+
+    pub fn timings(&self) -> Vec<PhaseTiming> {
+        self.timings.lock().unwrap().clone()
+    }
+
+    /// Estimates the seconds remaining in the current phase from its throughput so far
+    fn eta_seconds(&self) -> Option<u64> {
+        let started = self.phase.lock().unwrap().1;
+        let total = self.files_total.load(Ordering::SeqCst);
+        let processed = self.files_processed.load(Ordering::SeqCst);
+
+        if total == 0 || processed == 0 || processed >= total {
+            return None;
+        }
+
+        let rate = processed as f64 / started.elapsed().as_secs_f64();
+        Some(((total - processed) as f64 / rate).ceil() as u64)
+    }
+}
+
+// SCAN JOB ****************************************************************************************
+
+pub struct ScanJob {
+    pub id: i32,
+    pub folder: String,
+    /// Only report what the scan would change, without writing to the database
+    pub dry_run: bool,
+    pub progress: Arc<ScanProgress>,
+    state: Mutex<ScanJobState>,
+}
+
+struct ScanJobState {
+    status: ScanStatus,
+    date_started: NaiveDateTime,
+    date_finished: Option<NaiveDateTime>,
+    result: Option<ScanPhotosResult>,
+    error: Option<String>,
+    scan_run_id: Option<i32>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanJobReport {
+    pub id: i32,
+    pub folder: String,
+    pub dry_run: bool,
+    pub status: ScanStatus,
+    pub phase: ScanPhase,
+    pub files_total: usize,
+    pub files_processed: usize,
+    pub eta_seconds: Option<u64>,
+    /// Throughput of the current phase
+    pub files_per_second: Option<f64>,
+    /// Every phase the scan has finished so far, in order
+    pub phase_timings: Vec<PhaseTiming>,
+    pub date_started: NaiveDateTime,
+    pub date_finished: Option<NaiveDateTime>,
+    pub result: Option<ScanPhotosResult>,
+    pub error: Option<String>,
+    /// Id of the run in the scan history, see `GET /scans/{id}`
+    pub scan_run_id: Option<i32>,
+}
+
+impl ScanJob {
+    pub fn new(id: i32, folder: &str, dry_run: bool) -> Self {
+        ScanJob {
+            id,
+            folder: folder.to_string(),
+            dry_run,
+            progress: Arc::new(ScanProgress::default()),
+            state: Mutex::new(ScanJobState {
+                status: ScanStatus::Queued,
+                date_started: Utc::now().naive_utc(),
+                date_finished: None,
+                result: None,
+                error: None,
+                scan_run_id: None,
+            }),
+        }
+    }
+
+    pub fn status(&self) -> ScanStatus {
+        self.state.lock().unwrap().status
+    }
+
+    pub fn is_active(&self) -> bool {
+        matches!(self.status(), ScanStatus::Queued | ScanStatus::Running)
+    }
+
+    pub fn report(&self) -> ScanJobReport {
+        let state = self.state.lock().unwrap();
+
+        ScanJobReport {
+            id: self.id,
+            folder: self.folder.clone(),
+            dry_run: self.dry_run,
+            status: state.status,
+            phase: self.progress.phase(),
+            files_total: self.progress.files_total.load(Ordering::SeqCst),
+            files_processed: self.progress.files_processed.load(Ordering::SeqCst),
+            eta_seconds: self.progress.eta_seconds(),
+            files_per_second: self.progress.files_per_second(),
+            phase_timings: self.progress.timings(),
+            date_started: state.date_started,
+            date_finished: state.date_finished,
+            result: state.result.clone(),
+            error: state.error.clone(),
+            scan_run_id: state.scan_run_id,
+        }
+    }
+
+    /// Requests cancellation. The scan stops at the next file, unless it has already started to
+    /// write its changes.
+    pub fn cancel(&self) -> Result<(), ServiceError> {
+        if !self.is_active() {
+            return Err(ServiceError::BadRequest(format!(
+                "Scan job {} is not running",
+                self.id
+            )));
+        }
+
+        if !self.progress.cancel() {
+            return Err(ServiceError::BadRequest(format!(
+                "Scan job {} is already writing its changes and can no longer be cancelled",
+                self.id
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn set_status(&self, status: ScanStatus) {
+        let mut state = self.state.lock().unwrap();
+        state.status = status;
+
+        if !matches!(status, ScanStatus::Queued | ScanStatus::Running) {
+            state.date_finished = Some(Utc::now().naive_utc());
+        }
+    }
+
+    fn complete(&self, result: ScanPhotosResult) {
+        self.state.lock().unwrap().result = Some(result);
+        self.set_status(ScanStatus::Completed);
+    }
+
+    fn fail(&self, error: String) {
+        self.state.lock().unwrap().error = Some(error);
+        self.set_status(ScanStatus::Failed);
+    }
+}
+
+// SCAN JOBS REGISTRY ******************************************************************************
+
+/// In-memory registry of scan jobs. Cloning is cheap and every clone refers to the same jobs, so a
+/// single registry can be shared across all workers.
+#[derive(Clone, Default)]
+pub struct ScanJobs {
+    jobs: Arc<Mutex<HashMap<i32, Arc<ScanJob>>>>,
+    last_id: Arc<AtomicI32>,
+}
+
+impl ScanJobs {
+    /// Registers a new scan job for `folder`. Only one scan may be active at a time.
+    pub fn submit(&self, folder: &str, dry_run: bool) -> Result<Arc<ScanJob>, ServiceError> {
+        let mut jobs = self.jobs.lock().unwrap();
+
+        if let Some(active) = jobs.values().find(|job| job.is_active()) {
+            return Err(ServiceError::BadRequest(format!(
+                "Scan job {} is already running",
+                active.id
+            )));
+        }
+
+        let id = self.last_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let job = Arc::new(ScanJob::new(id, folder, dry_run));
+        jobs.insert(id, job.clone());
+
+        Ok(job)
+    }
+
+    pub fn has_active(&self) -> bool {
+        self.jobs.lock().unwrap().values().any(|job| job.is_active())
+    }
+
+    pub fn get(&self, id: i32) -> Result<Arc<ScanJob>, ServiceError> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| ServiceError::NotFound(format!("Scan job {} not found", id)))
+    }
+
+    pub fn get_all(&self) -> Vec<ScanJobReport> {
+        let mut reports: Vec<ScanJobReport> = self
+            .jobs
+            .lock()
+            .unwrap()
+            .values()
+            .map(|job| job.report())
+            .collect();
+        reports.sort_by_key(|report| Reverse(report.id));
+
+        reports
+    }
+}
+
+// RUN SCAN JOB ************************************************************************************
+
+/// Runs a scan job to completion and records the outcome on the job and in `scan_runs`
+pub async fn run_scan_job(job: Arc<ScanJob>, pool: Pool) {
+    job.set_status(ScanStatus::Running);
+
+    let folder = Some(job.folder.as_str()).filter(|folder| !folder.is_empty());
+    let scan_run_id = match ScanRun::start(folder, job.dry_run, &pool).await {
+        Ok(id) => Some(id),
+        Err(err) => {
+            println!("Unable to record scan job {}: {}", job.id, err);
+            None
+        }
+    };
+    job.state.lock().unwrap().scan_run_id = scan_run_id;
+
+    let outcome = perform_scan(&job, &pool).await;
+
+    if let Some(scan_run_id) = scan_run_id {
+        let recorded = match &outcome {
+            Ok(result) => ScanRun::complete(scan_run_id, result, &pool).await,
+            Err(ServiceError::ScanCancelled) => ScanRun::abort(scan_run_id, None, &pool).await,
+            Err(err) => ScanRun::abort(scan_run_id, Some(&err.to_string()), &pool).await,
+        };
+
+        if let Err(err) = recorded {
+            println!("Unable to record scan job {}: {}", job.id, err);
+        }
+    }
+
+    match outcome {
+        Ok(result) => job.complete(ScanPhotosResult::from_file_scan_result(
+            job.folder.clone(),
+            &result,
+            job.dry_run,
+        )),
+        Err(ServiceError::ScanCancelled) => {
+            println!("Scan job {} cancelled", job.id);
+            job.set_status(ScanStatus::Cancelled)
+        }
+        Err(err) => {
+            println!("Scan job {} failed: {}", job.id, err);
+            job.fail(err.to_string())
+        }
+    }
+}
+
+async fn perform_scan(job: &ScanJob, pool: &Pool) -> Result<FileScanResult, ServiceError> {
+    let progress = &job.progress;
+
+    println!("Scanning {}...", &job.folder);
+
+    let file_scan_result = if !job.folder.is_empty() {
+        files::photos::scan_all_photos_from_dir(&job.folder, pool, progress, job.dry_run).await?
+    } else {
+        files::photos::scan_all_photos(pool, progress, job.dry_run).await?
+    };
+
+    if job.dry_run {
+        progress.set_phase(ScanPhase::Finished, 0);
+        println!("Dry run done, nothing was written.");
+
+        return Ok(file_scan_result);
+    }
+
+    if file_scan_result.new_photos_count > 0 {
+        println!(
+            "Insert {} new photos into database...",
+            &file_scan_result
+                .new_photos_count
+                .to_formatted_string(&Locale::en)
+        );
+
+        progress.set_phase(ScanPhase::Insert, file_scan_result.new_photos.len());
+        for batch in file_scan_result.new_photos.chunks(INSERT_BATCH_SIZE) {
+            NewPhoto::bulk_insert(batch, pool).await?;
+            progress.advance(batch.len());
+        }
+
+        if files::thumbnails::generate_at_scan() {
+            files::photos::generate_thumbnails(&file_scan_result.new_photos, pool, progress)
+                .await?;
+        }
+    }
+
+    // refresh random order view
+    println!("Refresh random seed...");
+    schemas::reset_seed(pool).await?;
+
+    progress.set_phase(ScanPhase::Finished, 0);
+    println!("Done!");
+
+    Ok(file_scan_result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancelling_stops_the_scan_before_it_writes() {
+        let progress = ScanProgress::default();
+
+        assert!(progress.cancel());
+        assert!(progress.check_cancelled().is_err());
+        assert!(matches!(
+            progress.begin_writes(),
+            Err(ServiceError::ScanCancelled)
+        ));
+    }
+
+    #[test]
+    fn refuses_to_cancel_once_writing() {
+        let progress = ScanProgress::default();
+
+        assert!(progress.begin_writes().is_ok());
+        assert!(!progress.cancel());
+        assert!(progress.check_cancelled().is_ok());
+        assert!(progress.begin_writes().is_ok());
+    }
+}
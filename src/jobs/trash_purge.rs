@@ -0,0 +1,25 @@
+use std::time::Duration;
+
+use actix_rt::time::delay_for;
+use deadpool_postgres::Pool;
+
+use crate::schemas::trash::TrashedPhoto;
+
+/// How often the trash is checked for photos past their retention
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Purges photos that have been in the trash for longer than `retention_days`, once right away and
+/// then every hour
+pub fn start(retention_days: i32, pool: Pool) {
+    actix_rt::spawn(async move {
+        loop {
+            match TrashedPhoto::purge_expired(retention_days, &pool).await {
+                Ok(0) => {}
+                Ok(count) => println!("Purged {} photo(s) from the trash", count),
+                Err(err) => println!("Unable to purge the trash: {}", err),
+            }
+
+            delay_for(PURGE_INTERVAL).await;
+        }
+    });
+}
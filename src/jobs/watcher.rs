@@ -0,0 +1,369 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use actix_rt::time::delay_for;
+use deadpool_postgres::Pool;
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::StreamExt;
+use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
+use walkdir::WalkDir;
+
+use crate::errors::ServiceError;
+use crate::files::photos::{self, FileInfo};
+use crate::files::scan_rules::ScanRules;
+use crate::files::validation::FileIssue;
+use crate::jobs::scan::{ScanJobs, ScanProgress};
+use crate::schemas;
+use crate::schemas::libraries::Library;
+use crate::schemas::new_photo::NewPhoto;
+use crate::schemas::photo::Photo;
+use crate::schemas::scan_issues::ScanIssue;
+
+// WATCHER CONFIG **********************************************************************************
+
+pub struct WatcherConfig {
+    pub enabled: bool,
+    pub dirs: Vec<String>,
+    pub debounce: Duration,
+}
+
+impl WatcherConfig {
+    /// Builds the watcher config from the `SCARLETT_WATCH` (`true` or `1` to enable) and
+    /// `SCARLETT_WATCH_DEBOUNCE` (seconds, defaults to 5) environment variables. Every library with
+    /// watching enabled is watched.
+    pub fn from_env(libraries: &[Library]) -> Self {
+        let enabled = env::var("SCARLETT_WATCH")
+            .map(|value| value == "true" || value == "1")
+            .unwrap_or(false);
+
+        let debounce = env::var("SCARLETT_WATCH_DEBOUNCE")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(5);
+
+        WatcherConfig {
+            enabled,
+            dirs: libraries
+                .iter()
+                .filter(|library| library.watch_enabled)
+                .map(|library| library.root_path.to_owned())
+                .collect(),
+            debounce: Duration::from_secs(debounce),
+        }
+    }
+}
+
+// PENDING CHANGES *********************************************************************************
+
+/// Filesystem changes collected since the last quiet period
+#[derive(Default)]
+pub struct PendingChanges {
+    /// Files that were created or written to
+    pub created: BTreeSet<PathBuf>,
+    pub removed: BTreeSet<PathBuf>,
+    pub renamed: Vec<(PathBuf, PathBuf)>,
+}
+
+impl PendingChanges {
+    fn record(&mut self, event: DebouncedEvent) {
+        match event {
+            DebouncedEvent::Create(path) | DebouncedEvent::Write(path) => {
+                self.removed.remove(&path);
+                self.created.insert(path);
+            }
+            DebouncedEvent::Remove(path) => {
+                self.created.remove(&path);
+                self.removed.insert(path);
+            }
+            DebouncedEvent::Rename(from, to) => {
+                // a file that was created and renamed within the same burst is simply new
+                if self.created.remove(&from) {
+                    self.created.insert(to);
+                } else {
+                    self.renamed.push((from, to));
+                }
+            }
+            DebouncedEvent::Rescan => {
+                println!("File watcher lost events. Run a full scan to catch up.");
+            }
+            DebouncedEvent::Error(err, path) => {
+                println!("File watcher error ({:?}): {}", path, err);
+            }
+            _ => {}
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.created.is_empty() && self.removed.is_empty() && self.renamed.is_empty()
+    }
+}
+
+// START WATCHER ***********************************************************************************
+
+/// Starts watching the library directories. Events are gathered on a dedicated thread and handed to
+/// the actix runtime in batches once the directory has been quiet for the configured debounce.
+pub fn start(config: WatcherConfig, pool: Pool, scan_jobs: ScanJobs) {
+    let (batch_tx, mut batch_rx) = unbounded::<PendingChanges>();
+
+    let dirs = config.dirs.clone();
+    let debounce = config.debounce;
+    thread::spawn(move || watch(&dirs, debounce, batch_tx));
+
+    actix_rt::spawn(async move {
+        while let Some(changes) = batch_rx.next().await {
+            // a full scan covers whatever changed, so wait for it rather than racing it
+            while scan_jobs.has_active() {
+                delay_for(debounce).await;
+            }
+
+            if let Err(err) = apply_changes(changes, &pool).await {
+                println!("Unable to apply file watcher changes: {}", err);
+            }
+        }
+    });
+}
+
+fn watch(dirs: &[String], debounce: Duration, batches: UnboundedSender<PendingChanges>) {
+    let (tx, rx) = channel();
+
+    let mut watcher = match watcher(tx, debounce) {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            println!("Unable to start file watcher: {}", err);
+            return;
+        }
+    };
+
+    let mut watching = 0;
+    for dir in dirs {
+        match watcher.watch(dir, RecursiveMode::Recursive) {
+            Ok(_) => {
+                println!("Watching {} for changes...", dir);
+                watching += 1;
+            }
+            Err(err) => println!("Unable to watch {}: {}", dir, err),
+        }
+    }
+
+    if watching == 0 {
+        return;
+    }
+
+    let mut pending = PendingChanges::default();
+    loop {
+        match rx.recv_timeout(debounce) {
+            Ok(event) => pending.record(event),
+            Err(RecvTimeoutError::Timeout) => {
+                if pending.is_empty() {
+                    continue;
+                }
+
+                if batches.unbounded_send(mem::take(&mut pending)).is_err() {
+                    break;
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+}
+
+// APPLY CHANGES ***********************************************************************************
+
+async fn apply_changes(changes: PendingChanges, pool: &Pool) -> Result<(), ServiceError> {
+    let progress = Arc::new(ScanProgress::default());
+
+    let mut created: Vec<PathBuf> = changes.created.into_iter().collect();
+    let mut removed: Vec<PathBuf> = changes.removed.into_iter().collect();
+    let mut rules = ScanRules::load(pool).await?;
+
+    // renames first, so moved files are not hashed again as new photos
+    for (from, to) in changes.renamed {
+        let (from_str, to_str) = match (from.to_str(), to.to_str()) {
+            (Some(from_str), Some(to_str)) => (from_str, to_str),
+            _ => continue,
+        };
+
+        // moving a file somewhere the scanner ignores is the same as deleting it
+        if photos::is_hidden_path(&to) || (!to.is_dir() && !rules.includes(&to)) {
+            removed.push(from);
+            continue;
+        }
+
+        let moved = if to.is_dir() {
+            let moved = Photo::update_folder_path(from_str, to_str, pool).await?;
+
+            // the rules may exclude some of the files at their new location
+            let excluded: Vec<String> = Photo::get_paths_in_dir(to_str, pool)
+                .await?
+                .into_iter()
+                .filter(|path| !rules.includes(Path::new(path)))
+                .collect();
+            let deleted = Photo::delete_photos_by_path(&excluded, pool).await?;
+            if deleted > 0 {
+                println!("Removed {} excluded photo(s) from {}", deleted, to_str);
+            }
+
+            moved
+        } else {
+            Photo::update_file_path(from_str, to_str, pool).await?
+        };
+
+        println!("Moved {} photo(s) from {} to {}", moved, from_str, to_str);
+
+        if moved == 0 {
+            created.push(to);
+        }
+    }
+
+    for path in &removed {
+        if let Some(path) = path.to_str() {
+            let deleted = photos::check_for_deleted_files_in_dir(path, pool, &progress).await?;
+            Photo::delete_photos_by_path(&deleted, pool).await?;
+            ScanIssue::clear_missing(path, pool).await?;
+        }
+    }
+
+    let mut candidates: Vec<FileInfo> = Vec::new();
+    let mut issues: Vec<FileIssue> = Vec::new();
+    for path in collect_created_files(&created) {
+        if !rules.includes(&path) {
+            continue;
+        }
+
+        match FileInfo::new_from_path(&path) {
+            Ok(file) => candidates.push(file),
+            Err(issue) => issues.push(issue),
+        }
+    }
+    ScanIssue::record(&issues, pool).await?;
+
+    // a known path was written to in place, so it is re-hashed unless its fingerprint still matches
+    let candidate_paths: Vec<String> = candidates.iter().map(|f| f.file_path.to_owned()).collect();
+    let known = Photo::get_fingerprints_by_path(&candidate_paths, pool).await?;
+    let mut files: Vec<FileInfo> = Vec::new();
+    let mut changed_files: Vec<(i32, FileInfo)> = Vec::new();
+    for file in candidates {
+        match known.get(&file.file_path) {
+            None => files.push(file),
+            Some((id, fingerprint)) if *fingerprint != Some(file.fingerprint()) => {
+                changed_files.push((*id, file))
+            }
+            Some(_) => {}
+        }
+    }
+
+    if !changed_files.is_empty() {
+        let (changed, issues) =
+            photos::update_changed_photos(changed_files, pool, &progress).await?;
+        ScanIssue::record(&issues, pool).await?;
+        println!("Re-hashed {} modified file(s)", changed);
+    }
+
+    if !files.is_empty() {
+        println!("Ingesting {} new file(s)...", files.len());
+
+        let result = photos::process_new_files(files, pool, &progress, false).await?;
+        NewPhoto::bulk_insert(&result.new_photos, pool).await?;
+    }
+
+    schemas::reset_seed(pool).await?;
+
+    Ok(())
+}
+
+/// Expands created directories into the files inside them, since a folder copied or moved into
+/// the library only produces a single event
+fn collect_created_files(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = BTreeSet::new();
+
+    for path in paths {
+        if photos::is_hidden_path(path) || path.to_str().is_none() {
+            continue;
+        }
+
+        if path.is_dir() {
+            WalkDir::new(path)
+                .into_iter()
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.into_path())
+                .filter(|entry| is_visible_file(entry))
+                .for_each(|entry| {
+                    files.insert(entry);
+                });
+        } else if is_visible_file(path) {
+            files.insert(path.to_owned());
+        }
+    }
+
+    files.into_iter().collect()
+}
+
+fn is_visible_file(path: &Path) -> bool {
+    path.is_file() && path.to_str().is_some() && !photos::is_hidden_path(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::process;
+
+    use image::{DynamicImage, RgbImage};
+
+    use super::*;
+    use crate::utils::http_server;
+
+    /// Needs the database configured in `.env`, run with `cargo test -- --ignored`
+    #[actix_rt::test]
+    #[ignore]
+    async fn rehashes_files_written_in_place() {
+        dotenv::dotenv().ok();
+        let pool = http_server::create_pool();
+        let client = pool.get().await.unwrap();
+
+        let dir = env::temp_dir().join(format!("scarlett-watch-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("photo.png");
+        DynamicImage::ImageRgb8(RgbImage::new(4, 3))
+            .save(&path)
+            .unwrap();
+        let file_path = path.to_string_lossy().to_string();
+
+        let photo_id: i32 = client
+            .query_one(
+                "INSERT INTO photos (file_path, file_name, file_hash) \
+                 VALUES ($1, 'photo.png', 'stale') RETURNING id",
+                &[&file_path],
+            )
+            .await
+            .unwrap()
+            .get(0);
+
+        let mut changes = PendingChanges::default();
+        changes.record(DebouncedEvent::Write(path.clone()));
+        let applied = apply_changes(changes, &pool).await;
+
+        let photos: Vec<i32> = client
+            .query("SELECT id FROM photos WHERE file_path = $1", &[&file_path])
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| row.get(0))
+            .collect();
+        let photo = Photo::get_by_id(photo_id, &pool).await;
+        let _ = Photo::delete_photos(&photos, &pool).await;
+        let _ = fs::remove_dir_all(&dir);
+
+        applied.unwrap();
+        let photo = photo.unwrap();
+        assert_eq!(photos, [photo_id]);
+        assert_ne!(photo.file_hash, "stale");
+        assert_eq!((photo.original_width, photo.original_height), (4, 3));
+        assert!(photo.file_size.is_some());
+    }
+}
@@ -1,6 +1,7 @@
 pub mod errors;
 pub mod files;
 pub mod handlers;
+pub mod jobs;
 pub mod pagination;
 pub mod requests;
 pub mod responses;
@@ -2,9 +2,21 @@ use std::env;
 
 use actix_cors::Cors;
 use actix_web::http::header;
-use actix_web::{middleware, App, HttpServer};
+use actix_web::{middleware, web, App, HttpServer};
 
+use scarlett_server::errors::ServiceError;
+use scarlett_server::files::renditions;
+use scarlett_server::files::trash;
 use scarlett_server::handlers;
+use scarlett_server::jobs::integrity;
+use scarlett_server::jobs::integrity::IntegrityConfig;
+use scarlett_server::jobs::media_cache;
+use scarlett_server::jobs::scan::ScanJobs;
+use scarlett_server::jobs::trash_purge;
+use scarlett_server::jobs::watcher;
+use scarlett_server::jobs::watcher::WatcherConfig;
+use scarlett_server::schemas::libraries::Library;
+use scarlett_server::schemas::scan_runs::ScanRun;
 use scarlett_server::utils::http_server;
 
 #[actix_rt::main]
@@ -18,11 +30,50 @@ async fn main() -> std::io::Result<()> {
     let addr = http_server::get_addr();
     let pool = http_server::create_pool();
     let config = http_server::load_ssl_keys();
+    let scan_jobs = ScanJobs::default();
+
+    let libraries = Library::get_all(&pool)
+        .await
+        .expect("Unable to load libraries from the database.");
+
+    // scans do not survive a restart, so any run still marked as running never finished
+    if let Err(err) = ScanRun::fail_interrupted(&pool).await {
+        println!("Unable to update interrupted scan runs: {}", err);
+    }
+
+    if let Some(retention_days) = trash::retention_days() {
+        trash_purge::start(retention_days, pool.clone());
+    }
+
+    if let Some(max_bytes) = renditions::max_cache_bytes() {
+        media_cache::start(max_bytes, pool.clone());
+    }
+
+    let watcher_config = WatcherConfig::from_env(&libraries);
+    if watcher_config.enabled {
+        watcher::start(watcher_config, pool.clone(), scan_jobs.clone());
+    }
+
+    let integrity_config = IntegrityConfig::from_env();
+    if integrity_config.enabled {
+        integrity::start(integrity_config, pool.clone(), scan_jobs.clone());
+    }
 
     println!("Server running at {}", &addr);
     HttpServer::new(move || {
         App::new()
             .data(pool.clone())
+            .data(scan_jobs.clone())
+            // malformed JSON bodies and query strings get the same 400 response as every other bad
+            // request
+            .app_data(
+                web::JsonConfig::default()
+                    .error_handler(|err, _| ServiceError::BadRequest(err.to_string()).into()),
+            )
+            .app_data(
+                web::QueryConfig::default()
+                    .error_handler(|err, _| ServiceError::BadRequest(err.to_string()).into()),
+            )
             .wrap(
                 Cors::new()
                     .send_wildcard()
@@ -51,21 +102,44 @@ async fn main() -> std::io::Result<()> {
             .service(handlers::collections::delete_collection)
             // DIRECTORY TREE **********************************************************************
             .service(handlers::directory_tree::get_tree)
+            // DUPLICATES **************************************************************************
+            .service(handlers::duplicates::get_duplicate_groups)
+            // registered before `/duplicates/{id}` so that `similar` is not parsed as an id
+            .service(handlers::duplicates::get_similar_photo_groups)
+            .service(handlers::duplicates::get_duplicate_group)
+            .service(handlers::duplicates::resolve_duplicate_group)
             // ENTITIES ****************************************************************************
             .service(handlers::entity::get_entities)
             .service(handlers::entity::create_entity_simple)
             .service(handlers::entity::update_entity)
             .service(handlers::entity::delete_entity)
             .service(handlers::entity::search_entities)
+            // FRAMING *****************************************************************************
+            .service(handlers::framing::get_photo_framing)
+            .service(handlers::framing::set_focal_point)
+            .service(handlers::framing::remove_focal_point)
+            .service(handlers::framing::set_photo_crop)
+            .service(handlers::framing::remove_photo_crop)
+            .service(handlers::framing::preview_wallpaper)
+            // INTEGRITY ***************************************************************************
+            .service(handlers::integrity::get_integrity_report)
+            // LIBRARIES ***************************************************************************
+            .service(handlers::libraries::get_libraries)
+            .service(handlers::libraries::get_library)
+            .service(handlers::libraries::create_library)
+            .service(handlers::libraries::update_library)
+            .service(handlers::libraries::delete_library)
             // MEDIA *******************************************************************************
-            .service(actix_files::Files::new("/media", "/photos").show_files_listing())
-            .service(handlers::media::static_files)
+            .service(handlers::media::get_media)
             // PHOTOS ******************************************************************************
             .service(handlers::photos::get_photos)
             .service(handlers::photos::get_photo)
-            // .service(handlers::photos::update_photo)
+            .service(handlers::photos::get_similar_photos)
+            .service(handlers::photos::get_photo_history)
+            .service(handlers::photos::update_photo)
             .service(handlers::photos::update_photo_rating)
             .service(handlers::photos::update_photo_last_viewed)
+            .service(handlers::photos::bulk_update_photos)
             .service(handlers::photos::delete_photo)
             .service(handlers::photos::add_entity_to_photo)
             .service(handlers::photos::remove_entity_from_photo)
@@ -75,6 +149,16 @@ async fn main() -> std::io::Result<()> {
             .service(handlers::photos::remove_wallpaper_from_photo)
             // SCAN PHOTOS *************************************************************************
             .service(handlers::scan_photos::run_scan)
+            .service(handlers::scan_photos::get_scan_jobs)
+            .service(handlers::scan_photos::get_scan_job)
+            .service(handlers::scan_photos::cancel_scan_job)
+            .service(handlers::scan_photos::get_scan_settings)
+            .service(handlers::scan_photos::update_scan_settings)
+            .service(handlers::scan_photos::get_scan_runs)
+            .service(handlers::scan_photos::get_scan_run)
+            .service(handlers::scan_issues::get_scan_issues)
+            .service(handlers::scan_issues::retry_scan_issue)
+            .service(handlers::scan_issues::ignore_scan_issue)
             // STATS *******************************************************************************
             .service(handlers::stats::get_entity_stats)
             .service(handlers::stats::get_photos_stats)
@@ -85,10 +169,21 @@ async fn main() -> std::io::Result<()> {
             .service(handlers::tags::update_tag)
             .service(handlers::tags::delete_tag)
             .service(handlers::tags::search_tags)
+            // THUMBNAILS **************************************************************************
+            .service(handlers::thumbnails::get_thumbnail)
+            // TRASH *******************************************************************************
+            .service(handlers::trash::get_trash)
+            .service(handlers::trash::restore_photo)
+            .service(handlers::trash::empty_trash)
             // WALLPAPER SIZES *********************************************************************
             .service(handlers::wallpapers::get_wallpaper_sizes)
+            // XMP SIDECARS ************************************************************************
+            .service(handlers::xmp::export_photo_sidecar)
+            .service(handlers::xmp::export_sidecars)
             // RESET SEED **************************************************************************
             .service(handlers::photos::reset_seed)
+            // LIBRARY FILES ***********************************************************************
+            .service(handlers::media::get_library_file)
     })
     .bind_openssl(&addr, config)?
     .run()
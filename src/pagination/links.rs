@@ -96,6 +96,33 @@ fn build_link(page: i64, req: &GetPhotosRequest) -> String {
     //    }
     //
     url.query_pairs_mut().append_pair("folder", folder);
+
+    if let Some(camera_make) = req.get_camera_make() {
+        url.query_pairs_mut().append_pair("camera_make", camera_make);
+    }
+
+    if let Some(camera_model) = req.get_camera_model() {
+        url.query_pairs_mut()
+            .append_pair("camera_model", camera_model);
+    }
+
+    if let Some(lens_model) = req.get_lens_model() {
+        url.query_pairs_mut().append_pair("lens_model", lens_model);
+    }
+
+    if let Some(media_type) = req.get_media_type() {
+        url.query_pairs_mut().append_pair("media_type", media_type);
+    }
+
+    if let Some(taken_after) = req.get_taken_after() {
+        url.query_pairs_mut()
+            .append_pair("taken_after", &taken_after.to_string());
+    }
+
+    if let Some(taken_before) = req.get_taken_before() {
+        url.query_pairs_mut()
+            .append_pair("taken_before", &taken_before.to_string());
+    }
     //
     //    if req.get_raw_ignore_folders().is_some() {
     //        url.query_pairs_mut()
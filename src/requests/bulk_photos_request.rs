@@ -0,0 +1,183 @@
+use chrono::NaiveDate;
+use serde::Deserialize;
+
+use crate::errors::{FieldError, ServiceError};
+use crate::requests::get_photos_request::GetPhotosRequest;
+
+/// Actions applied to a selection of photos, either an explicit list of `ids` or every photo that
+/// matches `filter`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct BulkPhotosRequest {
+    pub ids: Option<Vec<i32>>,
+    pub filter: Option<BulkPhotosFilter>,
+    pub actions: BulkPhotoActions,
+}
+
+/// The collection and filters of `GET /photos` in camelCase, without pagination and sorting.
+/// Unknown fields are rejected, since a mistyped filter would otherwise select every photo.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct BulkPhotosFilter {
+    pub collection_id: Option<i32>,
+    pub folder: Option<String>,
+    pub exclude_ratings: Option<String>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub lens_model: Option<String>,
+    pub media_type: Option<String>,
+    pub taken_after: Option<NaiveDate>,
+    pub taken_before: Option<NaiveDate>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct BulkPhotoActions {
+    pub rating: Option<i32>,
+    #[serde(default)]
+    pub add_tags: Vec<i32>,
+    #[serde(default)]
+    pub remove_tags: Vec<i32>,
+    #[serde(default)]
+    pub add_entities: Vec<i32>,
+    #[serde(default)]
+    pub remove_entities: Vec<i32>,
+    pub ineligible_for_wallpaper: Option<bool>,
+    pub anonymous_entities: Option<bool>,
+}
+
+impl BulkPhotosRequest {
+    /// Checks that exactly one selection is given and that the actions are valid. Whether the tags
+    /// and entities exist is checked when the actions are applied.
+    pub fn validate(&self) -> Result<(), ServiceError> {
+        let mut errors = Vec::new();
+
+        match (&self.ids, &self.filter) {
+            (Some(_), Some(_)) => {
+                errors.push(FieldError::new("ids", "cannot be combined with `filter`"));
+            }
+            (None, None) => {
+                errors.push(FieldError::new(
+                    "ids",
+                    "either `ids` or `filter` is required",
+                ));
+            }
+            (Some(ids), None) if ids.is_empty() => {
+                errors.push(FieldError::new("ids", "must not be empty"));
+            }
+            (None, Some(filter)) if !GetPhotosRequest::from(filter).has_collection_or_filters() => {
+                errors.push(FieldError::new(
+                    "filter",
+                    "must set a collection or at least one filter",
+                ));
+            }
+            _ => {}
+        }
+
+        let actions = &self.actions;
+
+        if let Some(rating) = actions.rating {
+            if !(0..=5).contains(&rating) {
+                errors.push(FieldError::new("actions.rating", "must be between 0 and 5"));
+            }
+        }
+
+        if actions
+            .add_tags
+            .iter()
+            .any(|id| actions.remove_tags.contains(id))
+        {
+            errors.push(FieldError::new(
+                "actions.removeTags",
+                "cannot remove a tag that is also being added",
+            ));
+        }
+
+        if actions
+            .add_entities
+            .iter()
+            .any(|id| actions.remove_entities.contains(id))
+        {
+            errors.push(FieldError::new(
+                "actions.removeEntities",
+                "cannot remove an entity that is also being added",
+            ));
+        }
+
+        if actions.is_empty() {
+            errors.push(FieldError::new(
+                "actions",
+                "at least one action is required",
+            ));
+        }
+
+        if !errors.is_empty() {
+            return Err(ServiceError::ValidationFailed(errors));
+        }
+
+        Ok(())
+    }
+}
+
+impl BulkPhotoActions {
+    fn is_empty(&self) -> bool {
+        self.rating.is_none()
+            && self.add_tags.is_empty()
+            && self.remove_tags.is_empty()
+            && self.add_entities.is_empty()
+            && self.remove_entities.is_empty()
+            && self.ineligible_for_wallpaper.is_none()
+            && self.anonymous_entities.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(filter: &str) -> serde_json::Result<BulkPhotosRequest> {
+        serde_json::from_str(&format!(
+            r#"{{"filter": {}, "actions": {{"rating": 3}}}}"#,
+            filter
+        ))
+    }
+
+    fn filter_is_rejected(filter: &str) -> bool {
+        match request(filter).unwrap().validate() {
+            Err(ServiceError::ValidationFailed(errors)) => {
+                errors.iter().any(|error| error.field == "filter")
+            }
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn rejects_filter_that_selects_every_photo() {
+        assert!(filter_is_rejected("{}"));
+        assert!(filter_is_rejected(r#"{"excludeRatings": "6,x"}"#));
+        assert!(filter_is_rejected(r#"{"mediaType": "audio"}"#));
+    }
+
+    #[test]
+    fn rejects_unknown_filter_fields() {
+        assert!(request(r#"{"raiting": 5}"#).is_err());
+        assert!(request(r#"{"exclude_ratings": "5"}"#).is_err());
+        assert!(request(r#"{"pageSize": 10}"#).is_err());
+    }
+
+    #[test]
+    fn accepts_any_single_filter() {
+        let filters = [
+            r#"{"collectionId": 1}"#,
+            r#"{"folder": "/photos/2019"}"#,
+            r#"{"excludeRatings": "0,1"}"#,
+            r#"{"cameraMake": "Canon"}"#,
+            r#"{"mediaType": "video"}"#,
+            r#"{"takenBefore": "2020-01-01"}"#,
+        ];
+
+        for filter in filters.iter() {
+            assert!(request(filter).unwrap().validate().is_ok(), "{}", filter);
+        }
+    }
+}
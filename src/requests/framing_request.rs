@@ -0,0 +1,105 @@
+use serde::Deserialize;
+
+use crate::errors::{FieldError, ServiceError};
+use crate::files::renditions::CropRect;
+use crate::schemas::framing::FocalPoint;
+
+/// Body of `POST /photos/{id}/focal_point`, as fractions of the width and height of the upright
+/// photo
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FocalPointRequest {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl FocalPointRequest {
+    pub fn validate(&self) -> Result<FocalPoint, ServiceError> {
+        let mut errors = Vec::new();
+
+        for (field, value) in &[("x", self.x), ("y", self.y)] {
+            if !(0.0..=1.0).contains(value) {
+                errors.push(FieldError::new(field, "must be between 0 and 1"));
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(ServiceError::ValidationFailed(errors));
+        }
+
+        Ok(FocalPoint {
+            x: self.x,
+            y: self.y,
+        })
+    }
+}
+
+/// Body of `POST /photos/{id}/crops/{size_id}`, as fractions of the width and height of the upright
+/// photo
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CropRequest {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl CropRequest {
+    pub fn validate(&self) -> Result<CropRect, ServiceError> {
+        let rect = CropRect {
+            x: self.x,
+            y: self.y,
+            width: self.width,
+            height: self.height,
+        };
+
+        let errors = crop_errors(&rect, ["x", "y", "width", "height"]);
+        if !errors.is_empty() {
+            return Err(ServiceError::ValidationFailed(errors));
+        }
+
+        Ok(rect)
+    }
+}
+
+/// Checks that the rectangle lies within the photo. `fields` names the x, y, width and height of
+/// the rectangle in the request.
+pub fn crop_errors(rect: &CropRect, fields: [&str; 4]) -> Vec<FieldError> {
+    let [x_field, y_field, width_field, height_field] = fields;
+    let mut errors = Vec::new();
+
+    for (field, value) in &[(x_field, rect.x), (y_field, rect.y)] {
+        if !(0.0..1.0).contains(value) {
+            errors.push(FieldError::new(field, "must be at least 0 and less than 1"));
+        }
+    }
+
+    // a little slack for fractions that do not add up to exactly 1
+    let edges = [
+        (
+            width_field,
+            rect.width,
+            rect.x,
+            "the right edge of the photo",
+        ),
+        (
+            height_field,
+            rect.height,
+            rect.y,
+            "the bottom edge of the photo",
+        ),
+    ];
+    for (field, size, start, edge) in &edges {
+        if *size <= 0.0 {
+            errors.push(FieldError::new(field, "must be greater than 0"));
+        } else if start + size > 1.0 + 1e-6 {
+            errors.push(FieldError::new(
+                field,
+                &format!("must not reach past {}", edge),
+            ));
+        }
+    }
+
+    errors
+}
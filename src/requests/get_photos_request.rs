@@ -1,7 +1,10 @@
+use chrono::NaiveDate;
 use serde::Deserialize;
 
+use crate::requests::bulk_photos_request::BulkPhotosFilter;
 use crate::utils::strings;
 
+/// Query of `GET /photos`. Bulk requests build one from their `BulkPhotosFilter`.
 #[derive(Debug, Clone, Deserialize)]
 pub struct GetPhotosRequest {
     // pagination
@@ -17,6 +20,12 @@ pub struct GetPhotosRequest {
     // filters
     folder: Option<String>,
     exclude_ratings: Option<String>,
+    camera_make: Option<String>,
+    camera_model: Option<String>,
+    lens_model: Option<String>,
+    media_type: Option<String>,
+    taken_after: Option<NaiveDate>,
+    taken_before: Option<NaiveDate>,
 }
 
 impl GetPhotosRequest {
@@ -39,7 +48,20 @@ impl GetPhotosRequest {
     }
 
     pub fn get_sort_by(&self) -> Option<Vec<String>> {
-        let valid_sort_options = vec!["id", "date_created", "date_updated", "file_name", "folder"];
+        let valid_sort_options = vec![
+            "id",
+            "date_created",
+            "date_updated",
+            "file_name",
+            "folder",
+            "date_taken",
+            "camera_make",
+            "camera_model",
+            "lens_model",
+            "f_number",
+            "focal_length",
+            "iso",
+        ];
 
         let temp = match &self.sort_by {
             Some(val) => val,
@@ -65,6 +87,12 @@ impl GetPhotosRequest {
         self.folder.to_owned().unwrap_or_else(|| "/".to_string())
     }
 
+    /// Returns the folder to filter by. Unlike `get_folder` there is no default, since every photo
+    /// is below `/`.
+    pub fn get_folder_filter(&self) -> Option<&String> {
+        self.folder.as_ref()
+    }
+
     pub fn get_exclude_ratings(&self) -> Option<Vec<String>> {
         let valid_ratings = vec!["0", "1", "2", "3", "4", "5"];
 
@@ -86,8 +114,39 @@ impl GetPhotosRequest {
         }
     }
 
+    pub fn get_camera_make(&self) -> Option<&String> {
+        self.camera_make.as_ref()
+    }
+
+    pub fn get_camera_model(&self) -> Option<&String> {
+        self.camera_model.as_ref()
+    }
+
+    pub fn get_lens_model(&self) -> Option<&String> {
+        self.lens_model.as_ref()
+    }
+
+    /// Returns the media type to filter by, `image` or `video`. Other values are ignored.
+    pub fn get_media_type(&self) -> Option<&String> {
+        let valid_media_types = ["image", "video"];
+
+        self.media_type
+            .as_ref()
+            .filter(|media_type| valid_media_types.contains(&media_type.as_str()))
+    }
+
+    pub fn get_taken_after(&self) -> Option<&NaiveDate> {
+        self.taken_after.as_ref()
+    }
+
+    pub fn get_taken_before(&self) -> Option<&NaiveDate> {
+        self.taken_before.as_ref()
+    }
+
     // misc
 
+    /// Returns whether the request narrows down the photos. Ratings and media types that are
+    /// ignored as invalid do not count.
     pub fn has_collection_or_filters(&self) -> bool {
         // collections first
         if self.collection_id.is_some() {
@@ -100,7 +159,37 @@ impl GetPhotosRequest {
             return true;
         }
 
+        if self.get_exclude_ratings().is_some()
+            || self.camera_make.is_some()
+            || self.camera_model.is_some()
+            || self.lens_model.is_some()
+            || self.get_media_type().is_some()
+            || self.taken_after.is_some()
+            || self.taken_before.is_some()
+        {
+            return true;
+        }
+
         // default value last
         false
     }
 }
+
+impl From<&BulkPhotosFilter> for GetPhotosRequest {
+    fn from(filter: &BulkPhotosFilter) -> Self {
+        GetPhotosRequest {
+            page: None,
+            page_size: None,
+            sort_by: None,
+            collection_id: filter.collection_id,
+            folder: filter.folder.to_owned(),
+            exclude_ratings: filter.exclude_ratings.to_owned(),
+            camera_make: filter.camera_make.to_owned(),
+            camera_model: filter.camera_model.to_owned(),
+            lens_model: filter.lens_model.to_owned(),
+            media_type: filter.media_type.to_owned(),
+            taken_after: filter.taken_after,
+            taken_before: filter.taken_before,
+        }
+    }
+}
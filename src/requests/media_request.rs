@@ -0,0 +1,120 @@
+use serde::Deserialize;
+
+use crate::errors::{FieldError, ServiceError};
+use crate::files::renditions::{Fit, OutputFormat, Rendition};
+
+/// Largest width or height a photo can be rendered at
+const MAX_DIMENSION: u32 = 8192;
+
+/// Widths and heights are rounded up to a multiple of this, so that clients asking for slightly
+/// different sizes share renditions instead of each filling the cache with their own
+const SIZE_STEP: u32 = 32;
+
+/// Qualities are rounded up to a multiple of this, for the same reason
+const QUALITY_STEP: u8 = 5;
+
+const DEFAULT_QUALITY: u8 = 85;
+
+/// Query parameters of `GET /media/{id}`. Without any of them the original file is served.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MediaRequest {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// `contain` or `cover`
+    pub fit: Option<String>,
+    /// `jpeg`, `png` or `webp`
+    pub format: Option<String>,
+    /// JPEG or WebP quality between 1 and 100
+    pub quality: Option<u8>,
+}
+
+impl MediaRequest {
+    pub fn is_empty(&self) -> bool {
+        self.width.is_none()
+            && self.height.is_none()
+            && self.fit.is_none()
+            && self.format.is_none()
+            && self.quality.is_none()
+    }
+
+    /// Validates the parameters and resolves them into a rendition of `file_path`. Without a
+    /// `format`, JPEG and PNG files keep their format and everything else is converted to JPEG. The
+    /// size and quality are rounded up to the next step, see `SIZE_STEP`. The rendition is centered
+    /// on the middle of the photo.
+    pub fn to_rendition(&self, file_path: &str) -> Result<Rendition, ServiceError> {
+        let mut errors = Vec::new();
+
+        for (field, side) in &[("width", self.width), ("height", self.height)] {
+            if let Some(side) = side {
+                if !(1..=MAX_DIMENSION).contains(side) {
+                    errors.push(FieldError::new(
+                        field,
+                        &format!("must be between 1 and {}", MAX_DIMENSION),
+                    ));
+                }
+            }
+        }
+
+        let fit = match self.fit.as_deref() {
+            None | Some("contain") => Fit::Contain,
+            Some("cover") => {
+                if self.width.is_none() || self.height.is_none() {
+                    errors.push(FieldError::new(
+                        "fit",
+                        "`cover` requires both `width` and `height`",
+                    ));
+                }
+                Fit::Cover
+            }
+            Some(_) => {
+                errors.push(FieldError::new("fit", "must be `contain` or `cover`"));
+                Fit::Contain
+            }
+        };
+
+        let format = match self.format.as_deref() {
+            None => {
+                if file_path.to_lowercase().ends_with(".png") {
+                    OutputFormat::Png
+                } else {
+                    OutputFormat::Jpeg
+                }
+            }
+            Some("jpeg") | Some("jpg") => OutputFormat::Jpeg,
+            Some("png") => OutputFormat::Png,
+            Some("webp") => OutputFormat::Webp,
+            Some(_) => {
+                errors.push(FieldError::new("format", "must be `jpeg`, `png` or `webp`"));
+                OutputFormat::Jpeg
+            }
+        };
+
+        if let Some(quality) = self.quality {
+            if !(1..=100).contains(&quality) {
+                errors.push(FieldError::new("quality", "must be between 1 and 100"));
+            } else if format == OutputFormat::Png {
+                errors.push(FieldError::new(
+                    "quality",
+                    "only applies to `jpeg` and `webp`",
+                ));
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(ServiceError::ValidationFailed(errors));
+        }
+
+        let round_up = |side: u32| (side.div_ceil(SIZE_STEP) * SIZE_STEP).min(MAX_DIMENSION);
+        let quality = self.quality.unwrap_or(DEFAULT_QUALITY);
+
+        Ok(Rendition {
+            width: self.width.map(round_up),
+            height: self.height.map(round_up),
+            fit,
+            focal_point: (0.5, 0.5),
+            format,
+            quality: quality.div_ceil(QUALITY_STEP) * QUALITY_STEP,
+        })
+    }
+}
@@ -1,2 +1,9 @@
+pub mod bulk_photos_request;
+pub mod framing_request;
 pub mod get_photos_request;
+pub mod media_request;
+pub mod render_wallpaper_request;
 pub mod search_request;
+pub mod similar_photos_request;
+pub mod update_library_request;
+pub mod update_photo_request;
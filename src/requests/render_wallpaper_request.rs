@@ -0,0 +1,79 @@
+use serde::Deserialize;
+
+use crate::errors::{FieldError, ServiceError};
+use crate::files::renditions::{CropRect, Framing};
+use crate::requests::framing_request::crop_errors;
+
+/// Query parameters of `POST /photos/{id}/wallpaper/{size_id}` and of its preview. The focal point
+/// and crop are given as fractions of the width and height of the upright photo. They override the
+/// framing stored for the photo, so that a framing can be tried out before it is saved.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct RenderWallpaperRequest {
+    pub focal_x: Option<f64>,
+    pub focal_y: Option<f64>,
+    pub crop_x: Option<f64>,
+    pub crop_y: Option<f64>,
+    pub crop_width: Option<f64>,
+    pub crop_height: Option<f64>,
+}
+
+impl RenderWallpaperRequest {
+    /// Returns the framing to render with. A crop in the request wins over a focal point in the
+    /// request, which wins over the `stored` framing. A missing focal coordinate is centered.
+    pub fn framing(&self, stored: Framing) -> Result<Framing, ServiceError> {
+        let mut errors = Vec::new();
+
+        for (field, value) in &[("focalX", self.focal_x), ("focalY", self.focal_y)] {
+            if let Some(value) = value {
+                if !(0.0..=1.0).contains(value) {
+                    errors.push(FieldError::new(field, "must be between 0 and 1"));
+                }
+            }
+        }
+
+        let crop = match (self.crop_x, self.crop_y, self.crop_width, self.crop_height) {
+            (Some(x), Some(y), Some(width), Some(height)) => {
+                let rect = CropRect {
+                    x,
+                    y,
+                    width,
+                    height,
+                };
+                errors.extend(crop_errors(
+                    &rect,
+                    ["cropX", "cropY", "cropWidth", "cropHeight"],
+                ));
+                Some(rect)
+            }
+            (None, None, None, None) => None,
+            _ => {
+                errors.push(FieldError::new(
+                    "cropX",
+                    "`cropX`, `cropY`, `cropWidth` and `cropHeight` must be given together",
+                ));
+                None
+            }
+        };
+
+        if !errors.is_empty() {
+            return Err(ServiceError::ValidationFailed(errors));
+        }
+
+        if crop.is_some() {
+            return Ok(Framing {
+                focal_point: stored.focal_point,
+                crop,
+            });
+        }
+
+        if self.focal_x.is_some() || self.focal_y.is_some() {
+            return Ok(Framing {
+                focal_point: (self.focal_x.unwrap_or(0.5), self.focal_y.unwrap_or(0.5)),
+                crop: None,
+            });
+        }
+
+        Ok(stored)
+    }
+}
@@ -0,0 +1,25 @@
+use serde::Deserialize;
+
+use crate::errors::ServiceError;
+use crate::files::phash;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SimilarPhotosRequest {
+    max_distance: Option<u32>,
+}
+
+impl SimilarPhotosRequest {
+    /// Maximum number of bits two perceptual hashes may differ by. A 64 bit hash can differ by at
+    /// most 64 bits, so anything above that is rejected.
+    pub fn get_max_distance(&self) -> Result<u32, ServiceError> {
+        let max_distance = self.max_distance.unwrap_or(phash::DEFAULT_MAX_DISTANCE);
+
+        if max_distance > 64 {
+            return Err(ServiceError::BadRequest(
+                "`max_distance` must be between 0 and 64".to_string(),
+            ));
+        }
+
+        Ok(max_distance)
+    }
+}
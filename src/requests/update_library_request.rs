@@ -0,0 +1,43 @@
+use serde::Deserialize;
+
+use crate::errors::ServiceError;
+use crate::schemas::libraries::{Library, NewLibrary};
+
+/// Partial update of a library. Only the fields that are present are changed, unknown fields are
+/// rejected so that a typo does not silently leave a field as it was.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct UpdateLibraryRequest {
+    pub name: Option<String>,
+    pub root_path: Option<String>,
+    pub media_prefix: Option<String>,
+    pub scan_enabled: Option<bool>,
+    pub watch_enabled: Option<bool>,
+}
+
+impl UpdateLibraryRequest {
+    /// Returns the library with the present fields changed, ready to be validated like a new one
+    pub fn apply_to(&self, library: Library) -> Result<NewLibrary, ServiceError> {
+        if self.is_empty() {
+            return Err(ServiceError::BadRequest(
+                "At least one field to update is required".to_string(),
+            ));
+        }
+
+        Ok(NewLibrary {
+            name: self.name.to_owned().unwrap_or(library.name),
+            root_path: self.root_path.to_owned().unwrap_or(library.root_path),
+            media_prefix: self.media_prefix.to_owned().unwrap_or(library.media_prefix),
+            scan_enabled: Some(self.scan_enabled.unwrap_or(library.scan_enabled)),
+            watch_enabled: Some(self.watch_enabled.unwrap_or(library.watch_enabled)),
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.name.is_none()
+            && self.root_path.is_none()
+            && self.media_prefix.is_none()
+            && self.scan_enabled.is_none()
+            && self.watch_enabled.is_none()
+    }
+}
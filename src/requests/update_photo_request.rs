@@ -0,0 +1,198 @@
+use serde::Deserialize;
+
+use crate::errors::{FieldError, ServiceError};
+use crate::schemas::photo::Photo;
+
+const ROTATIONS: [i32; 4] = [0, 90, 180, 270];
+
+/// Partial update of a photo. Only the fields that are present are changed, unknown fields are
+/// rejected so that a typo does not silently leave a field as it was.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct UpdatePhotoRequest {
+    pub rating: Option<i32>,
+    pub rotation: Option<i32>,
+    pub ineligible_for_wallpaper: Option<bool>,
+    pub anonymous_entities: Option<bool>,
+    pub original_width: Option<i32>,
+    pub original_height: Option<i32>,
+}
+
+impl UpdatePhotoRequest {
+    /// Checks the fields against the constraints of the `photos` table, returning every field that
+    /// fails rather than just the first one
+    pub fn validate(&self, photo: &Photo) -> Result<(), ServiceError> {
+        let mut errors = Vec::new();
+
+        if let Some(rating) = self.rating {
+            if !(0..=5).contains(&rating) {
+                errors.push(FieldError::new("rating", "must be between 0 and 5"));
+            }
+        }
+
+        if let Some(rotation) = self.rotation {
+            if !ROTATIONS.contains(&rotation) {
+                errors.push(FieldError::new("rotation", "must be 0, 90, 180 or 270"));
+            }
+        }
+
+        if self.ineligible_for_wallpaper == Some(false) && photo.media_type == "video" {
+            errors.push(FieldError::new(
+                "ineligibleForWallpaper",
+                "videos cannot be used as wallpapers",
+            ));
+        }
+
+        if let Some(width) = self.original_width {
+            if width < 0 {
+                errors.push(FieldError::new("originalWidth", "must not be negative"));
+            }
+        }
+
+        if let Some(height) = self.original_height {
+            if height < 0 {
+                errors.push(FieldError::new("originalHeight", "must not be negative"));
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(ServiceError::ValidationFailed(errors));
+        }
+
+        if self.is_empty() {
+            return Err(ServiceError::BadRequest(
+                "At least one field to update is required".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.rating.is_none()
+            && self.rotation.is_none()
+            && self.ineligible_for_wallpaper.is_none()
+            && self.anonymous_entities.is_none()
+            && self.original_width.is_none()
+            && self.original_height.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDate;
+
+    fn photo(media_type: &str) -> Photo {
+        let date = NaiveDate::from_ymd(2020, 1, 1).and_hms(0, 0, 0);
+
+        Photo {
+            id: 1,
+            file_path: "/photos/a.jpg".to_string(),
+            file_name: "a.jpg".to_string(),
+            file_hash: String::new(),
+            rating: 0,
+            date_created: date,
+            date_updated: date,
+            last_viewed: None,
+            original_width: 0,
+            original_height: 0,
+            rotation: 0,
+            ineligible_for_wallpaper: false,
+            anonymous_entities: false,
+            file_size: None,
+            file_modified: None,
+            perceptual_hash: None,
+            date_taken: None,
+            camera_make: None,
+            camera_model: None,
+            lens_model: None,
+            exposure_time: None,
+            f_number: None,
+            focal_length: None,
+            iso: None,
+            exif_extracted: false,
+            date_trashed: None,
+            trashed_from: None,
+            media_type: media_type.to_string(),
+            duration: None,
+            codec: None,
+            focal_x: None,
+            focal_y: None,
+        }
+    }
+
+    fn failed_fields(request: &UpdatePhotoRequest, photo: &Photo) -> Vec<String> {
+        match request.validate(photo) {
+            Err(ServiceError::ValidationFailed(errors)) => {
+                errors.into_iter().map(|error| error.field).collect()
+            }
+            other => panic!("expected a validation error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn accepts_valid_fields() {
+        let request = UpdatePhotoRequest {
+            rating: Some(5),
+            rotation: Some(270),
+            ineligible_for_wallpaper: Some(false),
+            original_width: Some(0),
+            ..Default::default()
+        };
+
+        assert!(request.validate(&photo("image")).is_ok());
+    }
+
+    #[test]
+    fn reports_every_invalid_field() {
+        let request = UpdatePhotoRequest {
+            rating: Some(6),
+            rotation: Some(45),
+            original_width: Some(-1),
+            original_height: Some(-1),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            failed_fields(&request, &photo("image")),
+            ["rating", "rotation", "originalWidth", "originalHeight"]
+        );
+    }
+
+    #[test]
+    fn keeps_videos_out_of_wallpapers() {
+        let request = UpdatePhotoRequest {
+            ineligible_for_wallpaper: Some(false),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            failed_fields(&request, &photo("video")),
+            ["ineligibleForWallpaper"]
+        );
+
+        let request = UpdatePhotoRequest {
+            ineligible_for_wallpaper: Some(true),
+            ..Default::default()
+        };
+
+        assert!(request.validate(&photo("video")).is_ok());
+    }
+
+    #[test]
+    fn rejects_empty_update() {
+        match UpdatePhotoRequest::default().validate(&photo("image")) {
+            Err(ServiceError::BadRequest(_)) => {}
+            other => panic!("expected a bad request, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        let request = serde_json::from_str::<UpdatePhotoRequest>(r#"{"ratting": 3}"#);
+
+        assert!(request.is_err());
+    }
+}
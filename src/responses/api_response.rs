@@ -53,6 +53,15 @@ impl<T: Serialize> ApiResponse<T> {
         ))
     }
 
+    pub fn not_found(data: T) -> HttpResponse {
+        HttpResponse::NotFound().json(ApiResponse::new(
+            "Not Found",
+            404,
+            "The requested resource could not be found",
+            data,
+        ))
+    }
+
     pub fn error(data: T) -> HttpResponse {
         HttpResponse::InternalServerError().json(ApiResponse::new(
             "Internal Server Error",
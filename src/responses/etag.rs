@@ -0,0 +1,62 @@
+use actix_web::http::header::{self, EntityTag, HeaderValue};
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+
+// ETAG ********************************************************************************************
+
+/// The `ETag` of a response whose body only changes along with the tag, and how long clients may
+/// cache it. Requests that already have the body are answered with a 304 instead.
+#[derive(Debug, Clone)]
+pub struct ETag {
+    tag: EntityTag,
+    cache_control: Option<&'static str>,
+}
+
+impl ETag {
+    pub fn new(tag: String) -> Self {
+        ETag {
+            tag: EntityTag::strong(tag),
+            cache_control: None,
+        }
+    }
+
+    pub fn with_cache_control(mut self, cache_control: &'static str) -> Self {
+        self.cache_control = Some(cache_control);
+        self
+    }
+
+    /// Returns a 304 if the `If-None-Match` header of the request matches the tag, `None` if the
+    /// body has to be sent
+    pub fn not_modified(&self, req: &HttpRequest) -> Option<HttpResponse> {
+        let matches = match req.get_header::<header::IfNoneMatch>() {
+            Some(header::IfNoneMatch::Any) => true,
+            Some(header::IfNoneMatch::Items(ref items)) => {
+                items.iter().any(|item| item.weak_eq(&self.tag))
+            }
+            None => false,
+        };
+
+        if !matches {
+            return None;
+        }
+
+        let mut res = HttpResponse::NotModified().finish();
+        self.apply(&mut res);
+
+        Some(res)
+    }
+
+    /// Sets the `ETag` and `Cache-Control` headers of a response
+    pub fn apply(&self, res: &mut HttpResponse) {
+        // tags are built from hashes and parameters, which are always valid header values
+        if let Ok(value) = HeaderValue::from_str(&self.tag.to_string()) {
+            res.headers_mut().insert(header::ETAG, value);
+        }
+
+        if let Some(cache_control) = self.cache_control {
+            res.headers_mut().insert(
+                header::CACHE_CONTROL,
+                HeaderValue::from_static(cache_control),
+            );
+        }
+    }
+}
@@ -1 +1,2 @@
 pub mod api_response;
+pub mod etag;
@@ -0,0 +1,273 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use deadpool_postgres::{Pool, Transaction};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{FieldError, ServiceError};
+use crate::requests::bulk_photos_request::{BulkPhotoActions, BulkPhotosRequest};
+use crate::requests::get_photos_request::GetPhotosRequest;
+use crate::schemas::photo_full::PhotoFull;
+use crate::types::DbSingleResult;
+
+// BULK PHOTO OUTCOME ******************************************************************************
+
+/// What a bulk request did to a single photo. `status` is `updated`, `unchanged` when every action
+/// was already in effect, or `notFound` for ids that do not exist or are in the trash.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkPhotoOutcome {
+    pub photo_id: i32,
+    pub status: String,
+    /// Why an action was skipped for the photo
+    pub message: Option<String>,
+}
+
+impl BulkPhotoOutcome {
+    fn new(photo_id: i32, status: &str) -> Self {
+        BulkPhotoOutcome {
+            photo_id,
+            status: status.to_string(),
+            message: None,
+        }
+    }
+}
+
+// BULK PHOTOS RESULT ******************************************************************************
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkPhotosResult {
+    pub matched: usize,
+    pub updated: usize,
+    pub outcomes: Vec<BulkPhotoOutcome>,
+}
+
+impl BulkPhotosResult {
+    /// Applies the actions to every selected photo within a single transaction, so either every
+    /// photo is updated or none is. A `filter` is resolved within the same transaction and the
+    /// selected rows are locked, so photos cannot change between being selected and updated.
+    /// Unknown tags or entities fail the whole request.
+    pub async fn apply(request: &BulkPhotosRequest, pool: &Pool) -> DbSingleResult<Self> {
+        let actions = &request.actions;
+
+        let mut client = pool.get().await?;
+        let tx = client.transaction().await?;
+
+        BulkPhotosResult::check_links_exist(&tx, actions).await?;
+
+        let photo_ids = match (&request.ids, &request.filter) {
+            (Some(ids), _) => ids.to_owned(),
+            (None, Some(filter)) => {
+                PhotoFull::get_ids(&GetPhotosRequest::from(filter), &tx, pool).await?
+            }
+            (None, None) => Vec::new(),
+        };
+
+        // duplicates are dropped, the order of the request is kept for the outcomes
+        let mut seen = HashSet::new();
+        let photo_ids: Vec<i32> = photo_ids
+            .into_iter()
+            .filter(|id| seen.insert(*id))
+            .collect();
+
+        let stmt = tx
+            .prepare(
+                "select id, media_type from photos \
+                 where id = any($1) and date_trashed is null \
+                 for update",
+            )
+            .await?;
+        let media_types: HashMap<i32, String> = tx
+            .query(&stmt, &[&photo_ids])
+            .await?
+            .into_iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+        let found: Vec<i32> = photo_ids
+            .iter()
+            .copied()
+            .filter(|id| media_types.contains_key(id))
+            .collect();
+
+        let mut changed = BTreeSet::new();
+
+        if let Some(rating) = actions.rating {
+            let stmt = tx
+                .prepare(
+                    "update photos set rating = $2 \
+                     where id = any($1) and rating <> $2 \
+                     returning id",
+                )
+                .await?;
+            for row in tx.query(&stmt, &[&found, &rating]).await? {
+                changed.insert(row.get::<_, i32>(0));
+            }
+        }
+
+        let flags = [
+            (
+                "update photos set ineligible_for_wallpaper = $2 \
+                 where id = any($1) and ineligible_for_wallpaper <> $2 \
+                   and ($2 or media_type <> 'video') \
+                 returning id",
+                actions.ineligible_for_wallpaper,
+            ),
+            (
+                "update photos set anonymous_entities = $2 \
+                 where id = any($1) and anonymous_entities <> $2 \
+                 returning id",
+                actions.anonymous_entities,
+            ),
+        ];
+
+        for (statement, value) in flags.iter() {
+            if let Some(value) = value {
+                let stmt = tx.prepare(statement).await?;
+                for row in tx.query(&stmt, &[&found, value]).await? {
+                    changed.insert(row.get::<_, i32>(0));
+                }
+            }
+        }
+
+        let links = [
+            (
+                "insert into photo_tag (photo_id, tag_id) \
+                 select photo_id, tag_id \
+                 from unnest($1::int[]) photo_id cross join unnest($2::int[]) tag_id \
+                 on conflict do nothing \
+                 returning photo_id",
+                &actions.add_tags,
+            ),
+            (
+                "delete from photo_tag where photo_id = any($1) and tag_id = any($2) \
+                 returning photo_id",
+                &actions.remove_tags,
+            ),
+            (
+                "insert into photo_entity (photo_id, entity_id) \
+                 select photo_id, entity_id \
+                 from unnest($1::int[]) photo_id cross join unnest($2::int[]) entity_id \
+                 on conflict do nothing \
+                 returning photo_id",
+                &actions.add_entities,
+            ),
+            (
+                "delete from photo_entity where photo_id = any($1) and entity_id = any($2) \
+                 returning photo_id",
+                &actions.remove_entities,
+            ),
+        ];
+
+        for (statement, link_ids) in links.iter() {
+            if link_ids.is_empty() {
+                continue;
+            }
+
+            let stmt = tx.prepare(statement).await?;
+            for row in tx.query(&stmt, &[&found, link_ids]).await? {
+                changed.insert(row.get::<_, i32>(0));
+            }
+        }
+
+        let changed: Vec<i32> = changed.into_iter().collect();
+        let stmt = tx
+            .prepare("update photos set date_updated = current_timestamp where id = any($1)")
+            .await?;
+        let _ = tx.execute(&stmt, &[&changed]).await?;
+
+        tx.commit().await?;
+
+        let outcomes = photo_ids
+            .iter()
+            .map(|id| {
+                let media_type = match media_types.get(id) {
+                    Some(media_type) => media_type,
+                    None => return BulkPhotoOutcome::new(*id, "notFound"),
+                };
+
+                let status = if changed.binary_search(id).is_ok() {
+                    "updated"
+                } else {
+                    "unchanged"
+                };
+                let mut outcome = BulkPhotoOutcome::new(*id, status);
+
+                if actions.ineligible_for_wallpaper == Some(false) && media_type == "video" {
+                    outcome.message = Some("Videos cannot be used as wallpapers".to_string());
+                }
+
+                outcome
+            })
+            .collect();
+
+        Ok(BulkPhotosResult {
+            matched: found.len(),
+            updated: changed.len(),
+            outcomes,
+        })
+    }
+
+    /// Fails with a validation error naming every tag and entity id that does not exist
+    async fn check_links_exist(
+        tx: &Transaction<'_>,
+        actions: &BulkPhotoActions,
+    ) -> Result<(), ServiceError> {
+        let checks = [
+            (
+                "select id from tags where id = any($1)",
+                "actions.addTags",
+                &actions.add_tags,
+            ),
+            (
+                "select id from tags where id = any($1)",
+                "actions.removeTags",
+                &actions.remove_tags,
+            ),
+            (
+                "select id from entity where id = any($1)",
+                "actions.addEntities",
+                &actions.add_entities,
+            ),
+            (
+                "select id from entity where id = any($1)",
+                "actions.removeEntities",
+                &actions.remove_entities,
+            ),
+        ];
+
+        let mut errors = Vec::new();
+
+        for (statement, field, ids) in checks.iter() {
+            if ids.is_empty() {
+                continue;
+            }
+
+            let stmt = tx.prepare(statement).await?;
+            let existing: HashSet<i32> = tx
+                .query(&stmt, &[ids])
+                .await?
+                .into_iter()
+                .map(|row| row.get(0))
+                .collect();
+
+            let unknown: Vec<String> = ids
+                .iter()
+                .filter(|id| !existing.contains(id))
+                .map(|id| id.to_string())
+                .collect();
+
+            if !unknown.is_empty() {
+                errors.push(FieldError::new(
+                    field,
+                    &format!("unknown id(s): {}", unknown.join(", ")),
+                ));
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(ServiceError::ValidationFailed(errors));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,363 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use actix_web::web;
+use chrono::NaiveDateTime;
+use deadpool_postgres::{Pool, Transaction};
+use serde::{Deserialize, Serialize};
+use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_pg_mapper_derive::PostgresMapper;
+
+use crate::errors::ServiceError;
+use crate::files::photos::DuplicatePhoto;
+use crate::files::phash;
+use crate::files::trash;
+use crate::schemas::photo::Photo;
+use crate::schemas::photo_full::PhotoFull;
+use crate::schemas::trash::TrashedPhoto;
+use crate::types::{DbSingleResult, DbVecResult};
+
+pub const DUPLICATE_GROUP_STATUSES: [&str; 3] = ["pending", "resolved", "intentional"];
+
+#[derive(Serialize, Deserialize, Debug, Clone, PostgresMapper)]
+#[serde(rename_all = "camelCase")]
+#[pg_mapper(table = "duplicate_groups")]
+pub struct DuplicateGroup {
+    pub id: i32,
+    pub file_hash: String,
+    pub status: String,
+    pub kept_photo_id: Option<i32>,
+    pub date_created: NaiveDateTime,
+    pub date_resolved: Option<NaiveDateTime>,
+}
+
+/// A duplicate group along with every photo that currently shares its hash
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateGroupDetails {
+    #[serde(flatten)]
+    pub group: DuplicateGroup,
+    pub photos: Vec<PhotoFull>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum DuplicateResolution {
+    /// Keep one photo and permanently delete the other files
+    Delete,
+    /// Keep one photo and move the other photos into the trash
+    Trash,
+    /// The copies are deliberate, so all of them are kept
+    Intentional,
+}
+
+impl DuplicateGroup {
+    pub async fn get_all(status: Option<&str>, pool: &Pool) -> DbVecResult<Self> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "select * \
+                 from duplicate_groups \
+                 where $1::text is null or status = $1 \
+                 order by date_created desc, id desc",
+            )
+            .await?;
+        let results = client.query(&stmt, &[&status]).await?;
+
+        let groups: Vec<DuplicateGroup> = results
+            .into_iter()
+            .map(|result| DuplicateGroup::from_row(result).unwrap())
+            .collect();
+
+        Ok(groups)
+    }
+
+    pub async fn get_by_id(id: i32, pool: &Pool) -> DbSingleResult<Self> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare("select * from duplicate_groups where id = $1")
+            .await?;
+        let result = client.query_opt(&stmt, &[&id]).await?;
+
+        match result {
+            Some(row) => Ok(DuplicateGroup::from_row(row).unwrap()),
+            None => Err(ServiceError::NotFound(format!(
+                "Duplicate group {} not found",
+                id
+            ))),
+        }
+    }
+
+    /// Same as `get_all` with the photos of every group, which are loaded in a single query
+    pub async fn get_all_details(
+        status: Option<&str>,
+        pool: &Pool,
+    ) -> DbVecResult<DuplicateGroupDetails> {
+        let groups = DuplicateGroup::get_all(status, pool).await?;
+
+        let hashes: Vec<&str> = groups
+            .iter()
+            .map(|group| group.file_hash.as_str())
+            .collect();
+        let mut photos = PhotoFull::get_by_hashes(&hashes, pool).await?;
+
+        let details = groups
+            .into_iter()
+            .map(|group| DuplicateGroupDetails {
+                photos: photos.remove(&group.file_hash).unwrap_or_default(),
+                group,
+            })
+            .collect();
+
+        Ok(details)
+    }
+
+    pub async fn get_details(self, pool: &Pool) -> DbSingleResult<DuplicateGroupDetails> {
+        let photos = PhotoFull::get_by_hash(&self.file_hash, pool).await?;
+
+        Ok(DuplicateGroupDetails {
+            group: self,
+            photos,
+        })
+    }
+
+    /// Records duplicates found by a scan. Groups that were already resolved are reopened, since a
+    /// new copy has shown up. Groups marked as intentional are left alone.
+    pub async fn record(duplicates: &[DuplicatePhoto], pool: &Pool) -> DbSingleResult<u64> {
+        // a statement may not update the same row twice, so every hash is only passed once
+        let hashes: Vec<&str> = duplicates
+            .iter()
+            .map(|duplicate| duplicate.file_hash.as_str())
+            .collect::<HashSet<&str>>()
+            .into_iter()
+            .collect();
+
+        if hashes.is_empty() {
+            return Ok(0);
+        }
+
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "insert into duplicate_groups (file_hash) \
+                 select unnest($1::text[]) \
+                 on conflict (file_hash) do update \
+                 set status = 'pending', kept_photo_id = null, date_resolved = null \
+                 where duplicate_groups.status = 'resolved'",
+            )
+            .await?;
+        let count = client.execute(&stmt, &[&hashes]).await?;
+
+        Ok(count)
+    }
+
+    /// Resolves a pending group. For `Delete` and `Trash`, `keep_photo_id` survives and inherits
+    /// the tags, entities, wallpapers and best rating of the other photos, which are then deleted
+    /// or trashed.
+    pub async fn resolve(
+        id: i32,
+        resolution: DuplicateResolution,
+        keep_photo_id: Option<i32>,
+        pool: &Pool,
+    ) -> DbSingleResult<DuplicateGroupDetails> {
+        let group = DuplicateGroup::get_by_id(id, pool).await?;
+
+        if group.status != "pending" {
+            return Err(ServiceError::BadRequest(format!(
+                "Duplicate group {} has already been resolved",
+                id
+            )));
+        }
+
+        if resolution == DuplicateResolution::Intentional {
+            let mut client = pool.get().await?;
+            let tx = client.transaction().await?;
+            DuplicateGroup::set_status(&tx, id, "intentional", None).await?;
+            tx.commit().await?;
+
+            return DuplicateGroup::get_by_id(id, pool)
+                .await?
+                .get_details(pool)
+                .await;
+        }
+
+        let keep_photo_id = keep_photo_id.ok_or_else(|| {
+            ServiceError::BadRequest("`keepPhotoId` is required to resolve duplicates".to_string())
+        })?;
+
+        let photos = Photo::get_by_hash(&group.file_hash, pool).await?;
+        if !photos.iter().any(|photo| photo.id == keep_photo_id) {
+            return Err(ServiceError::BadRequest(format!(
+                "Photo {} is not part of duplicate group {}",
+                keep_photo_id, id
+            )));
+        }
+
+        let others: Vec<&Photo> = photos
+            .iter()
+            .filter(|photo| photo.id != keep_photo_id)
+            .collect();
+
+        let other_ids: Vec<i32> = others.iter().map(|photo| photo.id).collect();
+
+        if resolution == DuplicateResolution::Trash {
+            // Trashed copies keep their rows, so they can still be restored. The files are moved
+            // first and put back if the rows cannot be updated, so the group is never left half
+            // resolved.
+            let moved = TrashedPhoto::move_files(&others, pool).await?;
+
+            if let Err(err) = DuplicateGroup::mark_trashed(id, keep_photo_id, &moved, pool).await {
+                TrashedPhoto::put_back(&moved);
+                return Err(err);
+            }
+        } else {
+            // Deleting cannot be undone, so the files are only moved aside into the trash until
+            // the rows are gone, and put back if they cannot be deleted. Files that are already
+            // missing only have their rows deleted.
+            let present: Vec<&Photo> = others
+                .iter()
+                .copied()
+                .filter(|photo| Path::new(&photo.file_path).exists())
+                .collect();
+            let moved = TrashedPhoto::move_files(&present, pool).await?;
+
+            if let Err(err) =
+                DuplicateGroup::mark_deleted(id, keep_photo_id, &other_ids, pool).await
+            {
+                TrashedPhoto::put_back(&moved);
+                return Err(err);
+            }
+
+            for (_, _, trash_path) in &moved {
+                if let Err(err) = trash::remove_from_trash(trash_path) {
+                    println!("Unable to delete {}: {}", trash_path, err);
+                }
+            }
+        }
+
+        DuplicateGroup::get_by_id(id, pool)
+            .await?
+            .get_details(pool)
+            .await
+    }
+
+    async fn set_status(
+        tx: &Transaction<'_>,
+        id: i32,
+        status: &str,
+        kept_photo_id: Option<i32>,
+    ) -> DbSingleResult<()> {
+        let stmt = tx
+            .prepare(
+                "update duplicate_groups \
+                 set status = $2, kept_photo_id = $3, date_resolved = current_timestamp \
+                 where id = $1",
+            )
+            .await?;
+        let _ = tx.execute(&stmt, &[&id, &status, &kept_photo_id]).await?;
+
+        Ok(())
+    }
+
+    /// Deletes the rows of the copies and marks the group as resolved, all within one transaction
+    async fn mark_deleted(
+        id: i32,
+        keep_photo_id: i32,
+        other_ids: &[i32],
+        pool: &Pool,
+    ) -> DbSingleResult<()> {
+        let mut client = pool.get().await?;
+        let tx = client.transaction().await?;
+
+        Photo::carry_over(&tx, keep_photo_id, other_ids).await?;
+        Photo::delete_rows(&tx, other_ids).await?;
+        DuplicateGroup::set_status(&tx, id, "resolved", Some(keep_photo_id)).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Marks the copies moved into the trash as trashed and the group as resolved, all within one
+    /// transaction
+    async fn mark_trashed(
+        id: i32,
+        keep_photo_id: i32,
+        moved: &[(i32, String, String)],
+        pool: &Pool,
+    ) -> DbSingleResult<()> {
+        let other_ids: Vec<i32> = moved.iter().map(|(photo_id, _, _)| *photo_id).collect();
+
+        let mut client = pool.get().await?;
+        let tx = client.transaction().await?;
+
+        TrashedPhoto::mark_trashed(&tx, moved).await?;
+        Photo::carry_over(&tx, keep_photo_id, &other_ids).await?;
+        DuplicateGroup::set_status(&tx, id, "resolved", Some(keep_photo_id)).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+// SIMILAR PHOTO GROUPS ****************************************************************************
+
+/// Photos that are not byte-for-byte copies, but whose perceptual hashes are close enough for them
+/// to likely be the same image re-encoded, resized or stripped of metadata
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SimilarPhotoGroup {
+    pub photos: Vec<PhotoFull>,
+}
+
+impl SimilarPhotoGroup {
+    /// Builds the library-wide near-duplicate report. Groups where every photo shares the same
+    /// file hash are left out, since those are exact duplicates and already tracked as such.
+    pub async fn get_all(max_distance: u32, pool: &Pool) -> DbVecResult<Self> {
+        let hashes = Photo::get_perceptual_hashes(pool).await?;
+
+        let file_hashes: HashMap<i32, String> = hashes
+            .iter()
+            .map(|(id, file_hash, _)| (*id, file_hash.to_owned()))
+            .collect();
+
+        let groups = web::block(move || -> Result<_, ServiceError> {
+            let perceptual_hashes: Vec<(i32, i64)> = hashes
+                .into_iter()
+                .map(|(id, _, perceptual_hash)| (id, perceptual_hash))
+                .collect();
+
+            Ok(phash::group_similar(&perceptual_hashes, max_distance))
+        })
+        .await?;
+
+        let groups: Vec<Vec<i32>> = groups
+            .into_iter()
+            .filter(|ids| {
+                let distinct: HashSet<&String> = ids.iter().map(|id| &file_hashes[id]).collect();
+                distinct.len() > 1
+            })
+            .collect();
+
+        // the photos of every group are loaded at once and sorted into their groups here
+        let ids: Vec<i32> = groups.iter().flatten().copied().collect();
+        let group_of: HashMap<i32, usize> = groups
+            .iter()
+            .enumerate()
+            .flat_map(|(index, ids)| ids.iter().map(move |id| (*id, index)))
+            .collect();
+
+        let mut similar: Vec<SimilarPhotoGroup> = groups
+            .iter()
+            .map(|_| SimilarPhotoGroup { photos: Vec::new() })
+            .collect();
+        for photo in PhotoFull::get_by_ids(&ids, pool).await? {
+            if let Some(index) = group_of.get(&photo.id) {
+                similar[*index].photos.push(photo);
+            }
+        }
+
+        Ok(similar)
+    }
+}
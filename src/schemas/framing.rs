@@ -0,0 +1,215 @@
+use chrono::NaiveDateTime;
+use deadpool_postgres::Pool;
+use serde::{Deserialize, Serialize};
+use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_pg_mapper_derive::PostgresMapper;
+
+use crate::errors::ServiceError;
+use crate::files::renditions::{CropRect, Framing};
+use crate::types::{DbMessageResult, DbSingleResult};
+
+// PHOTO CROP **************************************************************************************
+
+/// Crop rectangle chosen by hand for the wallpaper of a photo in one wallpaper size
+#[derive(Serialize, Deserialize, Debug, Clone, PostgresMapper)]
+#[serde(rename_all = "camelCase")]
+#[pg_mapper(table = "photo_crops")]
+pub struct PhotoCrop {
+    pub id: i32,
+    pub photo_id: i32,
+    pub wallpaper_size_id: i32,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub date_updated: NaiveDateTime,
+}
+
+impl PhotoCrop {
+    pub fn rect(&self) -> CropRect {
+        CropRect {
+            x: f64::from(self.x),
+            y: f64::from(self.y),
+            width: f64::from(self.width),
+            height: f64::from(self.height),
+        }
+    }
+
+    /// Stores the crop of a photo in a wallpaper size, replacing the one it had
+    pub async fn set(
+        photo_id: i32,
+        wallpaper_size_id: i32,
+        rect: &CropRect,
+        pool: &Pool,
+    ) -> DbSingleResult<Self> {
+        let client = pool.get().await?;
+
+        // answer with a 404 rather than a foreign key violation
+        let stmt = client
+            .prepare(
+                "select exists(select 1 from photos where id = $1), \
+                        exists(select 1 from wallpaper_sizes where id = $2)",
+            )
+            .await?;
+        let row = client
+            .query_one(&stmt, &[&photo_id, &wallpaper_size_id])
+            .await?;
+        if !row.get::<_, bool>(0) {
+            return Err(ServiceError::NotFound(format!(
+                "Photo {} does not exist",
+                photo_id
+            )));
+        }
+        if !row.get::<_, bool>(1) {
+            return Err(ServiceError::NotFound(format!(
+                "Wallpaper size {} does not exist",
+                wallpaper_size_id
+            )));
+        }
+
+        let stmt = client
+            .prepare(
+                "insert into photo_crops (photo_id, wallpaper_size_id, x, y, width, height) \
+                 values ($1, $2, $3, $4, $5, $6) \
+                 on conflict (photo_id, wallpaper_size_id) do update \
+                 set x = $3, y = $4, width = $5, height = $6, date_updated = current_timestamp \
+                 returning *",
+            )
+            .await?;
+        let row = client
+            .query_one(
+                &stmt,
+                &[
+                    &photo_id,
+                    &wallpaper_size_id,
+                    &(rect.x as f32),
+                    &(rect.y as f32),
+                    &(rect.width as f32),
+                    &(rect.height as f32),
+                ],
+            )
+            .await?;
+
+        Ok(PhotoCrop::from_row(row).unwrap())
+    }
+
+    pub async fn remove(photo_id: i32, wallpaper_size_id: i32, pool: &Pool) -> DbMessageResult {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare("delete from photo_crops where photo_id = $1 and wallpaper_size_id = $2")
+            .await?;
+        let removed = client
+            .execute(&stmt, &[&photo_id, &wallpaper_size_id])
+            .await?;
+
+        if removed == 0 {
+            return Err(ServiceError::NotFound(format!(
+                "Photo {} has no crop for wallpaper size {}",
+                photo_id, wallpaper_size_id
+            )));
+        }
+
+        Ok("Crop removed from photo successfully".to_string())
+    }
+}
+
+// FOCAL POINT *************************************************************************************
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct FocalPoint {
+    pub x: f32,
+    pub y: f32,
+}
+
+// PHOTO FRAMING ***********************************************************************************
+
+/// Everything that decides which part of a photo its wallpapers, cover-fit renditions and cover
+/// thumbnails show
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PhotoFraming {
+    pub photo_id: i32,
+    /// `None` while the photo is centered
+    pub focal_point: Option<FocalPoint>,
+    pub crops: Vec<PhotoCrop>,
+}
+
+impl PhotoFraming {
+    pub async fn get(photo_id: i32, pool: &Pool) -> DbSingleResult<Self> {
+        let client = pool.get().await?;
+
+        let stmt = client
+            .prepare("select focal_x, focal_y from photos where id = $1")
+            .await?;
+        let photo = client
+            .query_opt(&stmt, &[&photo_id])
+            .await?
+            .ok_or_else(|| ServiceError::NotFound(format!("Photo {} does not exist", photo_id)))?;
+
+        let stmt = client
+            .prepare("select * from photo_crops where photo_id = $1 order by wallpaper_size_id")
+            .await?;
+        let crops = client
+            .query(&stmt, &[&photo_id])
+            .await?
+            .into_iter()
+            .map(|row| PhotoCrop::from_row(row).unwrap())
+            .collect();
+
+        let focal_point = match (photo.get("focal_x"), photo.get("focal_y")) {
+            (Some(x), Some(y)) => Some(FocalPoint { x, y }),
+            _ => None,
+        };
+
+        Ok(PhotoFraming {
+            photo_id,
+            focal_point,
+            crops,
+        })
+    }
+
+    /// Sets the focal point of a photo, `None` centers it again
+    pub async fn set_focal_point(
+        photo_id: i32,
+        focal_point: Option<FocalPoint>,
+        pool: &Pool,
+    ) -> DbSingleResult<Self> {
+        let (x, y) = match focal_point {
+            Some(focal_point) => (Some(focal_point.x), Some(focal_point.y)),
+            None => (None, None),
+        };
+
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "update photos set focal_x = $2, focal_y = $3, date_updated = current_timestamp \
+                 where id = $1",
+            )
+            .await?;
+        let updated = client.execute(&stmt, &[&photo_id, &x, &y]).await?;
+
+        if updated == 0 {
+            return Err(ServiceError::NotFound(format!(
+                "Photo {} does not exist",
+                photo_id
+            )));
+        }
+
+        PhotoFraming::get(photo_id, pool).await
+    }
+
+    /// Returns how the wallpaper of the photo in a wallpaper size is framed
+    pub fn for_size(&self, wallpaper_size_id: i32) -> Framing {
+        let focal_point = self
+            .focal_point
+            .map(|focal_point| (f64::from(focal_point.x), f64::from(focal_point.y)))
+            .unwrap_or((0.5, 0.5));
+        let crop = self
+            .crops
+            .iter()
+            .find(|crop| crop.wallpaper_size_id == wallpaper_size_id)
+            .map(PhotoCrop::rect);
+
+        Framing { focal_point, crop }
+    }
+}
@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+
+use chrono::NaiveDateTime;
+use deadpool_postgres::Pool;
+use serde::{Deserialize, Serialize};
+use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_pg_mapper_derive::PostgresMapper;
+
+use crate::files::fingerprints::Fingerprint;
+use crate::files::integrity::{StoredFile, Verification};
+use crate::types::{DbSingleResult, DbVecResult};
+
+// INTEGRITY RUN ***********************************************************************************
+
+/// A pass of the integrity verification job over the whole library
+#[derive(Serialize, Deserialize, Debug, Clone, PostgresMapper)]
+#[serde(rename_all = "camelCase")]
+#[pg_mapper(table = "integrity_runs")]
+pub struct IntegrityRun {
+    pub id: i32,
+    pub status: String,
+    pub date_started: NaiveDateTime,
+    pub date_finished: Option<NaiveDateTime>,
+    /// Photos are verified in id order, this is the last one that was
+    pub last_photo_id: i32,
+    pub checked_photos: i32,
+    pub mismatched_files: i32,
+    pub missing_files: i32,
+    pub unreadable_files: i32,
+}
+
+impl IntegrityRun {
+    pub async fn get_latest(pool: &Pool) -> DbSingleResult<Option<Self>> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare("select * from integrity_runs order by date_started desc, id desc limit 1")
+            .await?;
+        let result = client.query_opt(&stmt, &[]).await?;
+
+        Ok(result.map(|row| IntegrityRun::from_row(row).unwrap()))
+    }
+
+    /// Returns the run that is still in progress, or starts a new one once the last one finished
+    /// at least `interval_days` ago. Returns `None` if the library is not due for verification.
+    pub async fn resume_or_start(interval_days: i32, pool: &Pool) -> DbSingleResult<Option<Self>> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "insert into integrity_runs (status) \
+                 select 'running' \
+                 where not exists(select 1 \
+                                  from integrity_runs \
+                                  where status = 'running' \
+                                     or date_finished > current_timestamp - $1::int * \
+                                                                             interval '1 day')",
+            )
+            .await?;
+        let _ = client.execute(&stmt, &[&interval_days]).await?;
+
+        let stmt = client
+            .prepare(
+                "select * from integrity_runs where status = 'running' order by id desc limit 1",
+            )
+            .await?;
+        let result = client.query_opt(&stmt, &[]).await?;
+
+        Ok(result.map(|row| IntegrityRun::from_row(row).unwrap()))
+    }
+
+    /// Returns the files of the next `limit` photos after `last_photo_id`. Trashed photos are left
+    /// out, since purging the trash removes their files.
+    pub async fn get_next_files(
+        last_photo_id: i32,
+        limit: i64,
+        pool: &Pool,
+    ) -> DbVecResult<StoredFile> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "select id, file_path, file_hash, file_size, file_modified \
+                 from photos \
+                 where id > $1 and date_trashed is null \
+                 order by id \
+                 limit $2",
+            )
+            .await?;
+        let rows = client.query(&stmt, &[&last_photo_id, &limit]).await?;
+
+        let files = rows
+            .into_iter()
+            .map(|row| {
+                let file_size: Option<i64> = row.get("file_size");
+                let file_modified: Option<NaiveDateTime> = row.get("file_modified");
+
+                StoredFile {
+                    photo_id: row.get("id"),
+                    file_path: row.get("file_path"),
+                    file_hash: row.get("file_hash"),
+                    fingerprint: file_size.and_then(|file_size| {
+                        file_modified.map(|file_modified| Fingerprint {
+                            file_size,
+                            file_modified,
+                        })
+                    }),
+                }
+            })
+            .collect();
+
+        Ok(files)
+    }
+
+    /// Records the outcome of a batch of verified files and moves the run past them. Both happen
+    /// in one transaction, so a batch interrupted by a restart is verified again rather than
+    /// reported twice. Photos whose path or hash changed while the batch was verified, e.g. by the
+    /// file watcher, or that were trashed or deleted in the meantime are not reported.
+    pub async fn record_batch(
+        id: i32,
+        verified: &[(StoredFile, Verification)],
+        pool: &Pool,
+    ) -> DbSingleResult<()> {
+        let last_photo_id = match verified.last() {
+            Some((file, _)) => file.photo_id,
+            None => return Ok(()),
+        };
+
+        let mut client = pool.get().await?;
+        let tx = client.transaction().await?;
+
+        let verified_ids: Vec<i32> = verified.iter().map(|(file, _)| file.photo_id).collect();
+        let stmt = tx
+            .prepare(
+                "select id, file_path, file_hash from photos \
+                 where id = any($1) and date_trashed is null \
+                 for share",
+            )
+            .await?;
+        let current: HashMap<i32, (String, String)> = tx
+            .query(&stmt, &[&verified_ids])
+            .await?
+            .into_iter()
+            .map(|row| (row.get(0), (row.get(1), row.get(2))))
+            .collect();
+
+        let issues: Vec<&(StoredFile, Verification)> = verified
+            .iter()
+            .filter(|(_, verification)| verification.kind().is_some())
+            .filter(|(file, _)| match current.get(&file.photo_id) {
+                Some((file_path, file_hash)) => {
+                    *file_path == file.file_path && *file_hash == file.file_hash
+                }
+                None => false,
+            })
+            .collect();
+        let count = |kind: &str| {
+            issues
+                .iter()
+                .filter(|(_, verification)| verification.kind() == Some(kind))
+                .count() as i32
+        };
+
+        let photo_ids: Vec<i32> = issues.iter().map(|(file, _)| file.photo_id).collect();
+        let paths: Vec<&str> = issues
+            .iter()
+            .map(|(file, _)| file.file_path.as_str())
+            .collect();
+        let kinds: Vec<&str> = issues.iter().filter_map(|(_, v)| v.kind()).collect();
+        let expected_hashes: Vec<&str> = issues
+            .iter()
+            .map(|(file, _)| file.file_hash.as_str())
+            .collect();
+        let actual_hashes: Vec<Option<&str>> = issues
+            .iter()
+            .map(|(_, verification)| match verification {
+                Verification::Mismatch(hash) => Some(hash.as_str()),
+                _ => None,
+            })
+            .collect();
+        let errors: Vec<Option<&str>> = issues
+            .iter()
+            .map(|(_, verification)| match verification {
+                Verification::Unreadable(error) => Some(error.as_str()),
+                _ => None,
+            })
+            .collect();
+
+        let stmt = tx
+            .prepare(
+                "insert into integrity_issues (run_id, photo_id, file_path, kind, expected_hash, \
+                                               actual_hash, error) \
+                 select $1, photo_id, file_path, kind, expected_hash, actual_hash, error \
+                 from unnest($2::int[], $3::text[], $4::text[], $5::text[], $6::text[], \
+                             $7::text[]) \
+                      as u (photo_id, file_path, kind, expected_hash, actual_hash, error)",
+            )
+            .await?;
+        let _ = tx
+            .execute(
+                &stmt,
+                &[
+                    &id,
+                    &photo_ids,
+                    &paths,
+                    &kinds,
+                    &expected_hashes,
+                    &actual_hashes,
+                    &errors,
+                ],
+            )
+            .await?;
+
+        let stmt = tx
+            .prepare(
+                "update integrity_runs \
+                 set last_photo_id = $2, checked_photos = checked_photos + $3, \
+                     mismatched_files = mismatched_files + $4, \
+                     missing_files = missing_files + $5, \
+                     unreadable_files = unreadable_files + $6 \
+                 where id = $1",
+            )
+            .await?;
+        let _ = tx
+            .execute(
+                &stmt,
+                &[
+                    &id,
+                    &last_photo_id,
+                    &(verified.len() as i32),
+                    &count("mismatch"),
+                    &count("missing"),
+                    &count("unreadable"),
+                ],
+            )
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn complete(id: i32, pool: &Pool) -> DbSingleResult<()> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "update integrity_runs \
+                 set status = 'completed', date_finished = current_timestamp \
+                 where id = $1",
+            )
+            .await?;
+        let _ = client.execute(&stmt, &[&id]).await?;
+
+        Ok(())
+    }
+}
+
+// INTEGRITY ISSUE *********************************************************************************
+
+/// A file that an integrity run found to be corrupt (`mismatch`), `missing` or `unreadable`
+#[derive(Serialize, Deserialize, Debug, Clone, PostgresMapper)]
+#[serde(rename_all = "camelCase")]
+#[pg_mapper(table = "integrity_issues")]
+pub struct IntegrityIssue {
+    pub id: i32,
+    pub run_id: i32,
+    /// `None` once the photo has been deleted
+    pub photo_id: Option<i32>,
+    pub file_path: String,
+    pub kind: String,
+    pub expected_hash: String,
+    pub actual_hash: Option<String>,
+    pub error: Option<String>,
+    pub date_detected: NaiveDateTime,
+}
+
+impl IntegrityIssue {
+    pub async fn get_by_run(run_id: i32, pool: &Pool) -> DbVecResult<Self> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare("select * from integrity_issues where run_id = $1 order by file_path")
+            .await?;
+        let results = client.query(&stmt, &[&run_id]).await?;
+
+        let issues = results
+            .into_iter()
+            .map(|result| IntegrityIssue::from_row(result).unwrap())
+            .collect();
+
+        Ok(issues)
+    }
+}
+
+// INTEGRITY REPORT ********************************************************************************
+
+/// The latest integrity run along with the issues it found so far
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IntegrityReport {
+    /// `None` until the verification job ran for the first time
+    pub run: Option<IntegrityRun>,
+    pub issues: Vec<IntegrityIssue>,
+}
+
+impl IntegrityReport {
+    pub async fn get_latest(pool: &Pool) -> DbSingleResult<Self> {
+        let run = IntegrityRun::get_latest(pool).await?;
+        let issues = match &run {
+            Some(run) => IntegrityIssue::get_by_run(run.id, pool).await?,
+            None => Vec::new(),
+        };
+
+        Ok(IntegrityReport { run, issues })
+    }
+}
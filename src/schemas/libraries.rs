@@ -0,0 +1,321 @@
+use std::path::{Path, PathBuf};
+
+use deadpool_postgres::Pool;
+use serde::{Deserialize, Serialize};
+use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_pg_mapper_derive::PostgresMapper;
+
+use crate::errors::ServiceError;
+use crate::requests::update_library_request::UpdateLibraryRequest;
+use crate::types::{DbMessageResult, DbSingleResult, DbVecResult};
+
+// LIBRARY *****************************************************************************************
+
+/// A root directory that photos are scanned from and served out of
+#[derive(Serialize, Deserialize, Debug, Clone, PostgresMapper)]
+#[serde(rename_all = "camelCase")]
+#[pg_mapper(table = "libraries")]
+pub struct Library {
+    pub id: i32,
+    pub name: String,
+    /// Directory on disk that holds the library, without a trailing slash
+    pub root_path: String,
+    /// URL path the files are served under, e.g. `media` for `https://{hostname}/files/media/...`
+    pub media_prefix: String,
+    /// Whether a full scan includes this library
+    pub scan_enabled: bool,
+    /// Whether the file watcher follows changes in this library
+    pub watch_enabled: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct NewLibrary {
+    pub name: String,
+    pub root_path: String,
+    pub media_prefix: String,
+    pub scan_enabled: Option<bool>,
+    pub watch_enabled: Option<bool>,
+}
+
+impl Library {
+    pub async fn get_all(pool: &Pool) -> DbVecResult<Self> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare("select * from libraries order by name")
+            .await?;
+        let results = client.query(&stmt, &[]).await?;
+
+        let libraries: Vec<Library> = results
+            .into_iter()
+            .map(|result| Library::from_row(result).unwrap())
+            .collect();
+
+        Ok(libraries)
+    }
+
+    pub async fn get(id: i32, pool: &Pool) -> DbSingleResult<Self> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare("select * from libraries where id = $1")
+            .await?;
+        let result = client.query_opt(&stmt, &[&id]).await?;
+
+        match result {
+            Some(row) => Ok(Library::from_row(row).unwrap()),
+            None => Err(ServiceError::NotFound(format!("Library {} not found", id))),
+        }
+    }
+
+    /// Returns the root of the library that contains the file, the deepest one if roots are nested.
+    /// Files outside of every library fall back to their own directory.
+    pub fn root_of(libraries: &[Library], file_path: &str) -> PathBuf {
+        let path = Path::new(file_path);
+
+        libraries
+            .iter()
+            .filter(|library| path.starts_with(&library.root_path))
+            .max_by_key(|library| library.root_path.len())
+            .map(|library| PathBuf::from(&library.root_path))
+            .or_else(|| path.parent().map(Path::to_path_buf))
+            .unwrap_or_default()
+    }
+
+    /// Resolves `dir` to the same directory below the root of the library that contains it, so that
+    /// the files scanned from it get the paths a scan of the whole library gives them. Symlinks and
+    /// `..` are resolved first, directories outside of every library are rejected.
+    pub fn resolve_dir(libraries: &[Library], dir: &str) -> Result<String, ServiceError> {
+        let canonical = Path::new(dir)
+            .canonicalize()
+            .map_err(|_| ServiceError::NotFound(format!("Directory not found: {}", dir)))?;
+
+        let (library, relative) = libraries
+            .iter()
+            .filter_map(|library| {
+                let root = Path::new(&library.root_path).canonicalize().ok()?;
+                let relative = canonical.strip_prefix(root).ok()?.to_path_buf();
+
+                Some((library, relative))
+            })
+            .min_by_key(|(_, relative)| relative.components().count())
+            .ok_or_else(|| {
+                ServiceError::BadRequest(format!("{} is not inside of a library", dir))
+            })?;
+
+        if relative.as_os_str().is_empty() {
+            return Ok(library.root_path.to_owned());
+        }
+
+        let resolved = Path::new(&library.root_path).join(relative);
+        resolved.to_str().map(str::to_string).ok_or_else(|| {
+            ServiceError::BadRequest(format!("Directory path is not valid UTF-8: {}", dir))
+        })
+    }
+
+    /// Same as `root_of`, looking the libraries up first
+    pub async fn get_root_of(file_path: &str, pool: &Pool) -> DbSingleResult<PathBuf> {
+        let libraries = Library::get_all(pool).await?;
+
+        Ok(Library::root_of(&libraries, file_path))
+    }
+
+    /// Returns the library whose media prefix `path` starts with. Prefixes may be nested, e.g.
+    /// `media/archive` in `media`, in which case the longest one wins.
+    pub async fn get_by_media_path(path: &str, pool: &Pool) -> DbSingleResult<Option<Self>> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "select * from libraries \
+                 where $1 = media_prefix \
+                    or left($1, length(media_prefix) + 1) = media_prefix || '/' \
+                 order by length(media_prefix) desc \
+                 limit 1",
+            )
+            .await?;
+        let result = client.query_opt(&stmt, &[&path]).await?;
+
+        Ok(result.map(|row| Library::from_row(row).unwrap()))
+    }
+
+    pub async fn create(library: NewLibrary, pool: &Pool) -> DbSingleResult<Self> {
+        let library = library.validate()?;
+        Library::check_media_prefix_available(&library.media_prefix, None, pool).await?;
+
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "insert into libraries (name, root_path, media_prefix, scan_enabled, watch_enabled) \
+                 values ($1, $2, $3, $4, $5) \
+                 returning id",
+            )
+            .await?;
+        let result = client
+            .query_one(
+                &stmt,
+                &[
+                    &library.name,
+                    &library.root_path,
+                    &library.media_prefix,
+                    &library.scan_enabled.unwrap_or(true),
+                    &library.watch_enabled.unwrap_or(true),
+                ],
+            )
+            .await?;
+
+        Library::reassign_photos(pool).await?;
+
+        let library = Library::get(result.get(0), pool).await?;
+
+        Ok(library)
+    }
+
+    pub async fn update(
+        id: i32,
+        update: &UpdateLibraryRequest,
+        pool: &Pool,
+    ) -> DbSingleResult<Self> {
+        let library = Library::get(id, pool).await?;
+
+        let validated = update.apply_to(library)?.validate()?;
+        Library::check_media_prefix_available(&validated.media_prefix, Some(id), pool).await?;
+
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "update libraries \
+                 set name = $1, root_path = $2, media_prefix = $3, \
+                     scan_enabled = $4, watch_enabled = $5 \
+                 where id = $6",
+            )
+            .await?;
+        let count = client
+            .execute(
+                &stmt,
+                &[
+                    &validated.name,
+                    &validated.root_path,
+                    &validated.media_prefix,
+                    &validated.scan_enabled,
+                    &validated.watch_enabled,
+                    &id,
+                ],
+            )
+            .await?;
+
+        if count == 0 {
+            return Err(ServiceError::NotFound(format!("Library {} not found", id)));
+        }
+
+        Library::reassign_photos(pool).await?;
+
+        let result = Library::get(id, pool).await?;
+
+        Ok(result)
+    }
+
+    /// Removes the library. Its photos stay in the database, but are left without a library until
+    /// they fall under another root.
+    pub async fn delete(id: i32, pool: &Pool) -> DbMessageResult {
+        let library = Library::get(id, pool).await?;
+
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare("delete from libraries where id = $1")
+            .await?;
+        let _ = client.execute(&stmt, &[&library.id]).await?;
+
+        Library::reassign_photos(pool).await?;
+
+        Ok("Library deleted successfully".to_string())
+    }
+
+    /// Fails if another library than `library_id` is already served under the prefix
+    async fn check_media_prefix_available(
+        media_prefix: &str,
+        library_id: Option<i32>,
+        pool: &Pool,
+    ) -> DbSingleResult<()> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "select name from libraries \
+                 where media_prefix = $1 and ($2::int is null or id <> $2)",
+            )
+            .await?;
+        let result = client
+            .query_opt(&stmt, &[&media_prefix, &library_id])
+            .await?;
+
+        match result {
+            Some(row) => Err(ServiceError::BadRequest(format!(
+                "Media prefix {} is already used by library {}",
+                media_prefix,
+                row.get::<_, String>(0)
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Points every photo at the library that now contains it. Needed whenever roots change, since
+    /// the trigger on `photos` only runs when a photo itself is inserted or moved.
+    async fn reassign_photos(pool: &Pool) -> DbSingleResult<u64> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "update photos \
+                 set library_id = find_library(file_path) \
+                 where library_id is distinct from find_library(file_path)",
+            )
+            .await?;
+        let count = client.execute(&stmt, &[]).await?;
+
+        Ok(count)
+    }
+}
+
+impl NewLibrary {
+    /// Normalizes the root and prefix and checks that the root exists
+    pub fn validate(self) -> Result<Self, ServiceError> {
+        let name = self.name.trim().to_string();
+        let root_path = self.root_path.trim().trim_end_matches('/').to_string();
+        let media_prefix = self.media_prefix.trim().trim_matches('/').to_string();
+
+        if name.is_empty() {
+            return Err(ServiceError::BadRequest(
+                "A library needs a name".to_string(),
+            ));
+        }
+
+        if !Path::new(&root_path).is_absolute() || !Path::new(&root_path).is_dir() {
+            return Err(ServiceError::BadRequest(format!(
+                "Library root must be an existing absolute directory: {}",
+                self.root_path
+            )));
+        }
+
+        if media_prefix.is_empty() {
+            return Err(ServiceError::BadRequest(
+                "A library needs a media prefix".to_string(),
+            ));
+        }
+
+        // media urls are resolved segment by segment, see `handlers::media`
+        let invalid_segment = media_prefix.split('/').any(|segment| {
+            segment.is_empty() || segment.starts_with('.') || segment.contains('\\')
+        });
+        if invalid_segment {
+            return Err(ServiceError::BadRequest(format!(
+                "Invalid media prefix: {}",
+                self.media_prefix
+            )));
+        }
+
+        Ok(NewLibrary {
+            name,
+            root_path,
+            media_prefix,
+            scan_enabled: self.scan_enabled,
+            watch_enabled: self.watch_enabled,
+        })
+    }
+}
@@ -2,13 +2,23 @@ use deadpool_postgres::Pool;
 
 use crate::types::DbSingleResult;
 
+pub mod bulk_photos;
 pub mod collections;
 pub mod directory_tree;
+pub mod duplicates;
 pub mod entity;
+pub mod framing;
+pub mod integrity;
+pub mod libraries;
 pub mod new_photo;
 pub mod photo;
 pub mod photo_full;
+pub mod photo_path_history;
+pub mod scan_issues;
+pub mod scan_runs;
+pub mod scan_settings;
 pub mod tags;
+pub mod trash;
 pub mod wallpaper_sizes;
 
 // REFRESH `photo_order` MATERIALIZED VIEW *********************************************************
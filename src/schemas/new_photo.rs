@@ -1,12 +1,20 @@
 use std::fs::File;
-use std::io;
+use std::io::{self, Read};
 use std::path::Path;
+use std::slice;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
-use deadpool_postgres::Pool;
+use deadpool_postgres::{Client, Pool};
 use sha3::{Digest, Sha3_256};
 
+use crate::files::exif::ExifMetadata;
+use crate::files::heif::HeifInfo;
+use crate::files::phash;
+use crate::files::photos::FileInfo;
+use crate::files::validation::{self, FileFormat, FileIssue, IssueKind};
+use crate::files::video::VideoInfo;
+use crate::files::xmp::XmpMetadata;
 use crate::schemas::photo::Photo;
 use crate::types::DbSingleResult;
 
@@ -18,29 +26,86 @@ pub struct NewPhoto {
     pub date_created: NaiveDateTime,
     pub original_height: i32,
     pub original_width: i32,
+    pub file_size: i64,
+    pub file_modified: NaiveDateTime,
+    pub perceptual_hash: Option<i64>,
+    pub rotation: i32,
+    pub exif: ExifMetadata,
+    /// `image` or `video`
+    pub media_type: String,
+    /// Length of a clip in seconds, always `None` for images
+    pub duration: Option<f64>,
+    /// Codec of a clip, always `None` for images
+    pub codec: Option<String>,
+    /// Rating, tags and entities from the file's XMP sidecar or embedded XMP packet
+    pub xmp: XmpMetadata,
 }
 
+/// Longest names the `tags` and `entity` tables accept, longer XMP values are skipped
+const MAX_TAG_NAME_LENGTH: usize = 100;
+const MAX_ENTITY_NAME_LENGTH: usize = 250;
+
 impl NewPhoto {
-    pub fn new(path: &str, dt_created: SystemTime) -> Self {
-        let dt_created = system_time_to_date_time(dt_created).naive_utc();
-
-        let (width, height) = if get_file_name(&path).to_lowercase().ends_with(".heic") {
-            (0, 0)
-        } else {
-            let dim = image::image_dimensions(&path).unwrap_or((0, 0));
-            let width = dim.0 as i32;
-            let height = dim.1 as i32;
-            (width, height)
+    /// Validates the file and reads everything about it that is stored. Files that cannot be
+    /// read, are not in the format their extension claims or have a broken header are returned as
+    /// an issue instead.
+    pub fn new(file: &FileInfo) -> Result<Self, FileIssue> {
+        let path = file.file_path.as_str();
+        let dt_created = system_time_to_date_time(file.date_created).naive_utc();
+        let fingerprint = file.fingerprint();
+
+        // the `image` crate cannot read HEIF or clips, so their containers are parsed by hand
+        let format = validation::check_format(path)?;
+        let (width, height, container_rotation, video) = match format {
+            FileFormat::Heif => {
+                let heif = HeifInfo::read(path).ok_or_else(|| {
+                    FileIssue::new(path, IssueKind::Corrupt, "Unable to read the HEIF header")
+                })?;
+                (heif.width, heif.height, Some(heif.rotation), None)
+            }
+            FileFormat::Video(container) => {
+                let video = VideoInfo::read(path, container).ok_or_else(|| {
+                    FileIssue::new(path, IssueKind::Corrupt, "Unable to read the video track")
+                })?;
+                (video.width, video.height, Some(video.rotation), Some(video))
+            }
+            FileFormat::Image(format) => {
+                let (width, height) = validation::read_dimensions(path, format)?;
+                (width, height, None, None)
+            }
         };
 
-        NewPhoto {
-            file_name: get_file_name(&path),
-            file_hash: calculate_sha3_hash(&path),
+        let file_hash = calculate_sha3_hash(path)
+            .map_err(|err| FileIssue::new(path, IssueKind::Unreadable, err))?;
+        let exif = match video {
+            Some(_) => ExifMetadata::default(),
+            None => ExifMetadata::read(path),
+        };
+
+        // HEIF rotates with `irot` and clips with their track matrix, both take precedence over
+        // the EXIF orientation
+        let rotation = container_rotation.unwrap_or_else(|| exif.rotation());
+        let (duration, codec) = video
+            .map(|video| (video.duration, video.codec))
+            .unwrap_or_default();
+
+        Ok(NewPhoto {
+            file_name: get_file_name(path)?,
+            file_hash,
             file_path: path.to_string(),
             date_created: dt_created,
-            original_width: width,
-            original_height: height,
-        }
+            original_width: width as i32,
+            original_height: height as i32,
+            file_size: fingerprint.file_size,
+            file_modified: fingerprint.file_modified,
+            perceptual_hash: phash::perceptual_hash(path),
+            rotation,
+            exif,
+            media_type: format.media_type().to_string(),
+            duration,
+            codec,
+            xmp: XmpMetadata::read(path),
+        })
     }
 
     pub async fn insert(&self, pool: &Pool) -> DbSingleResult<Photo> {
@@ -56,8 +121,24 @@ impl NewPhoto {
                                                                           original_height,
                                                                           rotation,
                                                                           ineligible_for_wallpaper,
-                                                                          anonymous_entities)
-                                                      VALUES ($1, $2, $3, 0, $4, $4, $5, $6, 0, false, false) RETURNING id"#).await?;
+                                                                          anonymous_entities,
+                                                                          file_size,
+                                                                          file_modified,
+                                                                          perceptual_hash,
+                                                                          date_taken,
+                                                                          camera_make,
+                                                                          camera_model,
+                                                                          lens_model,
+                                                                          exposure_time,
+                                                                          f_number,
+                                                                          focal_length,
+                                                                          iso,
+                                                                          exif_extracted,
+                                                                          media_type,
+                                                                          duration,
+                                                                          codec)
+                                                      VALUES ($1, $2, $3, $22, $4, $4, $5, $6, $10, $19 = 'video', false, $7, $8, $9,
+                                                              $11, $12, $13, $14, $15, $16, $17, $18, true, $19, $20, $21) RETURNING id"#).await?;
 
         let result = client
             .query_one(
@@ -69,44 +150,200 @@ impl NewPhoto {
                     &self.date_created,
                     &self.original_width,
                     &self.original_height,
+                    &self.file_size,
+                    &self.file_modified,
+                    &self.perceptual_hash,
+                    &self.rotation,
+                    &self.exif.date_taken,
+                    &self.exif.camera_make,
+                    &self.exif.camera_model,
+                    &self.exif.lens_model,
+                    &self.exif.exposure_time,
+                    &self.exif.f_number,
+                    &self.exif.focal_length,
+                    &self.exif.iso,
+                    &self.media_type,
+                    &self.duration,
+                    &self.codec,
+                    &self.xmp.rating.unwrap_or(0),
                 ],
             )
             .await?;
+        NewPhoto::insert_labels(slice::from_ref(self), &client).await?;
         let result = Photo::get_by_id(result.get(0), pool).await?;
 
         Ok(result)
     }
 
+    /// Inserts all of `new_photos` with a single statement. Returns the number of rows inserted.
     pub async fn bulk_insert(new_photos: &[Self], pool: &Pool) -> DbSingleResult<u64> {
+        if new_photos.is_empty() {
+            return Ok(0);
+        }
+
+        let paths: Vec<&str> = new_photos.iter().map(|p| p.file_path.as_str()).collect();
+        let names: Vec<&str> = new_photos.iter().map(|p| p.file_name.as_str()).collect();
+        let hashes: Vec<&str> = new_photos.iter().map(|p| p.file_hash.as_str()).collect();
+        let dates_created: Vec<NaiveDateTime> = new_photos.iter().map(|p| p.date_created).collect();
+        let widths: Vec<i32> = new_photos.iter().map(|p| p.original_width).collect();
+        let heights: Vec<i32> = new_photos.iter().map(|p| p.original_height).collect();
+        let sizes: Vec<i64> = new_photos.iter().map(|p| p.file_size).collect();
+        let modified: Vec<NaiveDateTime> = new_photos.iter().map(|p| p.file_modified).collect();
+        let perceptual_hashes: Vec<Option<i64>> =
+            new_photos.iter().map(|p| p.perceptual_hash).collect();
+        let rotations: Vec<i32> = new_photos.iter().map(|p| p.rotation).collect();
+        let dates_taken: Vec<Option<NaiveDateTime>> =
+            new_photos.iter().map(|p| p.exif.date_taken).collect();
+        let camera_makes: Vec<Option<&str>> = new_photos
+            .iter()
+            .map(|p| p.exif.camera_make.as_deref())
+            .collect();
+        let camera_models: Vec<Option<&str>> = new_photos
+            .iter()
+            .map(|p| p.exif.camera_model.as_deref())
+            .collect();
+        let lens_models: Vec<Option<&str>> = new_photos
+            .iter()
+            .map(|p| p.exif.lens_model.as_deref())
+            .collect();
+        let exposure_times: Vec<Option<&str>> = new_photos
+            .iter()
+            .map(|p| p.exif.exposure_time.as_deref())
+            .collect();
+        let f_numbers: Vec<Option<f32>> = new_photos.iter().map(|p| p.exif.f_number).collect();
+        let focal_lengths: Vec<Option<f32>> =
+            new_photos.iter().map(|p| p.exif.focal_length).collect();
+        let isos: Vec<Option<i32>> = new_photos.iter().map(|p| p.exif.iso).collect();
+        let media_types: Vec<&str> = new_photos.iter().map(|p| p.media_type.as_str()).collect();
+        let durations: Vec<Option<f64>> = new_photos.iter().map(|p| p.duration).collect();
+        let codecs: Vec<Option<&str>> = new_photos.iter().map(|p| p.codec.as_deref()).collect();
+        let ratings: Vec<i32> = new_photos
+            .iter()
+            .map(|p| p.xmp.rating.unwrap_or(0))
+            .collect();
+
         let client = pool.get().await?;
+        let stmt = client.prepare(r#"INSERT INTO photos (file_path, file_name, file_hash, rating, date_created, date_updated, original_width, original_height, rotation, ineligible_for_wallpaper, anonymous_entities, file_size, file_modified, perceptual_hash,
+                                                      date_taken, camera_make, camera_model, lens_model, exposure_time, f_number, focal_length, iso, exif_extracted, media_type, duration, codec)
+                                      SELECT file_path, file_name, file_hash, rating, date_created, date_created, original_width, original_height, rotation, media_type = 'video', false, file_size, file_modified, perceptual_hash,
+                                             date_taken, camera_make, camera_model, lens_model, exposure_time, f_number, focal_length, iso, true, media_type, duration, codec
+                                      FROM unnest($1::text[], $2::text[], $3::text[], $4::timestamp[], $5::int[], $6::int[], $7::bigint[], $8::timestamp[], $9::bigint[], $10::int[],
+                                                  $11::timestamp[], $12::text[], $13::text[], $14::text[], $15::text[], $16::real[], $17::real[], $18::int[], $19::text[], $20::float8[], $21::text[], $22::int[])
+                                           AS u (file_path, file_name, file_hash, date_created, original_width, original_height, file_size, file_modified, perceptual_hash, rotation,
+                                                 date_taken, camera_make, camera_model, lens_model, exposure_time, f_number, focal_length, iso, media_type, duration, codec, rating)"#).await?;
+
+        let count = client
+            .execute(
+                &stmt,
+                &[
+                    &paths,
+                    &names,
+                    &hashes,
+                    &dates_created,
+                    &widths,
+                    &heights,
+                    &sizes,
+                    &modified,
+                    &perceptual_hashes,
+                    &rotations,
+                    &dates_taken,
+                    &camera_makes,
+                    &camera_models,
+                    &lens_models,
+                    &exposure_times,
+                    &f_numbers,
+                    &focal_lengths,
+                    &isos,
+                    &media_types,
+                    &durations,
+                    &codecs,
+                    &ratings,
+                ],
+            )
+            .await?;
+        NewPhoto::insert_labels(new_photos, &client).await?;
 
-        let stmt = "INSERT INTO photos (file_path, file_name, file_hash, rating, date_created, date_updated, original_width, original_height, rotation, ineligible_for_wallpaper, anonymous_entities) \
-                          VALUES ($1, $2, $3, 0, $4, $4, $5, $6, 0, false, false)";
-        let mut count = 0;
+        Ok(count)
+    }
+
+    /// Adds the tags and entities from the XMP of freshly inserted photos, creating the ones that
+    /// do not exist yet. Photos are matched by their path, since the insert does not return ids.
+    async fn insert_labels(new_photos: &[Self], client: &Client) -> DbSingleResult<()> {
+        let mut tag_paths = Vec::new();
+        let mut tag_names = Vec::new();
+        let mut entity_paths = Vec::new();
+        let mut entity_names = Vec::new();
 
         for photo in new_photos {
-            let result = client
-                .execute(
-                    stmt,
-                    &[
-                        &photo.file_path,
-                        &photo.file_name,
-                        &photo.file_hash,
-                        &photo.date_created,
-                        &photo.original_width,
-                        &photo.original_height,
-                    ],
+            for tag in &photo.xmp.tags {
+                if tag.chars().count() <= MAX_TAG_NAME_LENGTH {
+                    tag_paths.push(photo.file_path.as_str());
+                    tag_names.push(tag.as_str());
+                }
+            }
+            for entity in &photo.xmp.entities {
+                if entity.chars().count() <= MAX_ENTITY_NAME_LENGTH {
+                    entity_paths.push(photo.file_path.as_str());
+                    entity_names.push(entity.as_str());
+                }
+            }
+        }
+
+        if !tag_names.is_empty() {
+            let stmt = client
+                .prepare(
+                    "INSERT INTO tags (tag_name) \
+                     SELECT DISTINCT lower(tag_name) FROM unnest($1::text[]) AS u (tag_name) \
+                     ON CONFLICT (lower(tag_name)) DO NOTHING",
                 )
                 .await?;
+            client.execute(&stmt, &[&tag_names]).await?;
 
-            count += result;
+            let stmt = client
+                .prepare(
+                    "INSERT INTO photo_tag (photo_id, tag_id) \
+                     SELECT DISTINCT p.id, t.id \
+                     FROM unnest($1::text[], $2::text[]) AS u (file_path, tag_name) \
+                     JOIN photos p ON p.file_path = u.file_path AND p.date_trashed IS NULL \
+                     JOIN tags t ON lower(t.tag_name) = lower(u.tag_name) \
+                     ON CONFLICT DO NOTHING",
+                )
+                .await?;
+            client.execute(&stmt, &[&tag_paths, &tag_names]).await?;
         }
 
-        Ok(count)
+        if !entity_names.is_empty() {
+            // entities differing only in case are one and the same, the first spelling is kept
+            let stmt = client
+                .prepare(
+                    "INSERT INTO entity (entity_name) \
+                     SELECT DISTINCT ON (lower(entity_name)) entity_name \
+                     FROM unnest($1::text[]) AS u (entity_name) \
+                     ON CONFLICT (lower(entity_name)) DO NOTHING",
+                )
+                .await?;
+            client.execute(&stmt, &[&entity_names]).await?;
+
+            let stmt = client
+                .prepare(
+                    "INSERT INTO photo_entity (photo_id, entity_id) \
+                     SELECT DISTINCT p.id, e.id \
+                     FROM unnest($1::text[], $2::text[]) AS u (file_path, entity_name) \
+                     JOIN photos p ON p.file_path = u.file_path AND p.date_trashed IS NULL \
+                     JOIN entity e ON lower(e.entity_name) = lower(u.entity_name) \
+                     ON CONFLICT DO NOTHING",
+                )
+                .await?;
+            client
+                .execute(&stmt, &[&entity_paths, &entity_names])
+                .await?;
+        }
+
+        Ok(())
     }
 }
 
-fn system_time_to_date_time(t: SystemTime) -> DateTime<Utc> {
+pub fn system_time_to_date_time(t: SystemTime) -> DateTime<Utc> {
     let (sec, nsec) = match t.duration_since(UNIX_EPOCH) {
         Ok(dur) => (dur.as_secs() as i64, dur.subsec_nanos()),
         Err(e) => {
@@ -122,20 +359,25 @@ fn system_time_to_date_time(t: SystemTime) -> DateTime<Utc> {
     Utc.timestamp(sec, nsec)
 }
 
-fn calculate_sha3_hash(path: &str) -> String {
-    let mut file = File::open(path).unwrap();
+fn calculate_sha3_hash(path: &str) -> io::Result<String> {
+    let mut file = File::open(path)?;
+
+    sha3_hash(&mut file)
+}
+
+/// Hashes everything `reader` returns the same way `file_hash` is calculated
+pub fn sha3_hash<R: Read>(reader: &mut R) -> io::Result<String> {
     let mut hasher = Sha3_256::new();
-    let _n = io::copy(&mut file, &mut hasher).unwrap();
+    let _n = io::copy(reader, &mut hasher)?;
     let hash = format!("{:x}", hasher.result());
 
-    hash
+    Ok(hash)
 }
 
-fn get_file_name(path: &str) -> String {
+fn get_file_name(path: &str) -> Result<String, FileIssue> {
     Path::new(path)
         .file_name()
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string()
+        .and_then(|name| name.to_str())
+        .map(|name| name.to_string())
+        .ok_or_else(|| FileIssue::new(path, IssueKind::Unreadable, "File path has no file name"))
 }
@@ -1,11 +1,19 @@
-use std::fs;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 
-use chrono::{NaiveDateTime, Utc};
-use deadpool_postgres::Pool;
+use chrono::NaiveDateTime;
+use deadpool_postgres::{Pool, Transaction};
 use serde::{Deserialize, Serialize};
 use tokio_pg_mapper::FromTokioPostgresRow;
 use tokio_pg_mapper_derive::PostgresMapper;
+use tokio_postgres::types::ToSql;
 
+use crate::errors::ServiceError;
+use crate::files::exif::ExifMetadata;
+use crate::files::fingerprints::Fingerprint;
+use crate::files::heif::HeifInfo;
+use crate::files::wallpapers;
+use crate::requests::update_photo_request::UpdatePhotoRequest;
 use crate::schemas::entity::Entity;
 use crate::schemas::new_photo::NewPhoto;
 use crate::schemas::photo_full::PhotoFull;
@@ -30,9 +38,41 @@ pub struct Photo {
     pub rotation: i32,
     pub ineligible_for_wallpaper: bool,
     pub anonymous_entities: bool,
+    pub file_size: Option<i64>,
+    pub file_modified: Option<NaiveDateTime>,
+    pub perceptual_hash: Option<i64>,
+    pub date_taken: Option<NaiveDateTime>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub lens_model: Option<String>,
+    pub exposure_time: Option<String>,
+    pub f_number: Option<f32>,
+    pub focal_length: Option<f32>,
+    pub iso: Option<i32>,
+    pub exif_extracted: bool,
+    /// Set while the photo is in the trash, see `TrashedPhoto`
+    pub date_trashed: Option<NaiveDateTime>,
+    /// Path the file is restored to. `file_path` points into the trash while the photo is trashed.
+    pub trashed_from: Option<String>,
+    /// `image` or `video`
+    pub media_type: String,
+    /// Length of a clip in seconds
+    pub duration: Option<f64>,
+    pub codec: Option<String>,
+    /// Spot that stays in view when the photo is cropped, see `PhotoFraming`
+    pub focal_x: Option<f32>,
+    pub focal_y: Option<f32>,
 }
 
 impl Photo {
+    /// Returns the focal point of the photo, `None` while it is not set
+    pub fn focal_point(&self) -> Option<(f64, f64)> {
+        match (self.focal_x, self.focal_y) {
+            (Some(x), Some(y)) => Some((f64::from(x), f64::from(y))),
+            _ => None,
+        }
+    }
+
     pub async fn get_all(pool: &Pool) -> DbVecResult<Self> {
         let client = pool.get().await?;
         let stmt = client.prepare("SELECT * FROM photos").await?;
@@ -45,124 +85,715 @@ impl Photo {
         Ok(photos)
     }
 
+    /// Returns the photo whether or not it is in the trash, see `get_visible`
     pub async fn get_by_id(photo_id: i32, pool: &Pool) -> DbSingleResult<Self> {
         let client = pool.get().await?;
         let stmt = client.prepare("SELECT * FROM photos WHERE id = $1").await?;
-        let result = client.query_one(&stmt, &[&photo_id]).await?;
+        let result = client.query_opt(&stmt, &[&photo_id]).await?;
+
+        match result {
+            Some(row) => Ok(Photo::from_row(row).unwrap()),
+            None => Err(ServiceError::NotFound(format!(
+                "Photo {} does not exist",
+                photo_id
+            ))),
+        }
+    }
+
+    /// Returns the photo unless it is in the trash. Trashed photos are treated like photos that do
+    /// not exist, so that they are no longer served.
+    pub async fn get_visible(photo_id: i32, pool: &Pool) -> DbSingleResult<Self> {
+        let photo = Photo::get_by_id(photo_id, pool).await?;
+
+        if photo.date_trashed.is_some() {
+            return Err(ServiceError::NotFound(format!(
+                "Photo {} does not exist",
+                photo_id
+            )));
+        }
+
+        Ok(photo)
+    }
+
+    pub async fn get_by_path(file_path: &str, pool: &Pool) -> DbSingleResult<Self> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare("SELECT * FROM photos WHERE file_path = $1")
+            .await?;
+        let result = client.query_one(&stmt, &[&file_path]).await?;
 
         let photo = Photo::from_row(result).unwrap();
 
         Ok(photo)
     }
 
-    pub async fn update_photo(updated_photo: &Photo, pool: &Pool) -> DbSingleResult<PhotoFull> {
-        let mut updated = updated_photo.clone();
-        updated.date_updated = Utc::now().naive_utc();
+    /// Returns the hash of the photo at the path, `None` if the file is not a photo in the library
+    pub async fn get_file_hash_by_path(
+        file_path: &str,
+        pool: &Pool,
+    ) -> DbSingleResult<Option<String>> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare("select file_hash from photos where file_path = $1 and date_trashed is null")
+            .await?;
+        let row = client.query_opt(&stmt, &[&file_path]).await?;
 
+        Ok(row.map(|row| row.get(0)))
+    }
+
+    /// Returns the photos with the hash, leaving out trashed ones
+    pub async fn get_by_hash(file_hash: &str, pool: &Pool) -> DbVecResult<Self> {
         let client = pool.get().await?;
         let stmt = client
             .prepare(
-                "UPDATE photos
-                                    SET file_path = $1,
-                                        file_name = $2,
-                                        file_hash = $3,
-                                        rating = $4,
-                                        date_created = $5,
-                                        date_updated = $6,
-                                        original_width = $7,
-                                        original_height = $8,
-                                        rotation = $9,
-                                        ineligible_for_wallpaper = $10,
-                                        anonymous_entities = $11
-                                    WHERE id = $12",
+                "SELECT * FROM photos \
+                 WHERE file_hash = $1 AND date_trashed IS NULL \
+                 ORDER BY file_path",
             )
             .await?;
-        let _ = client
-            .execute(
-                &stmt,
-                &[
-                    &updated.file_path,
-                    &updated.file_name,
-                    &updated.file_hash,
-                    &updated.rating,
-                    &updated.date_created,
-                    &updated.date_updated,
-                    &updated.original_height,
-                    &updated.original_width,
-                    &updated.rotation,
-                    &updated.ineligible_for_wallpaper,
-                    &updated.anonymous_entities,
-                    &updated.id,
-                ],
+        let results = client.query(&stmt, &[&file_hash]).await?;
+        let photos: Vec<Photo> = results
+            .into_iter()
+            .map(|result| Photo::from_row(result).unwrap())
+            .collect();
+
+        Ok(photos)
+    }
+
+    /// Returns every photo that has one of `file_hashes`, grouped by hash. Trashed photos are left
+    /// out.
+    pub async fn get_by_hashes(
+        file_hashes: &[String],
+        pool: &Pool,
+    ) -> DbSingleResult<HashMap<String, Vec<Self>>> {
+        let mut photos: HashMap<String, Vec<Self>> = HashMap::new();
+
+        if file_hashes.is_empty() {
+            return Ok(photos);
+        }
+
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "SELECT * FROM photos \
+                 WHERE file_hash = ANY($1) AND date_trashed IS NULL \
+                 ORDER BY file_path",
             )
             .await?;
+        let results = client.query(&stmt, &[&file_hashes]).await?;
 
-        let result = PhotoFull::get_by_id(updated.id, pool).await?;
+        for result in results {
+            let photo = Photo::from_row(result).unwrap();
+            photos
+                .entry(photo.file_hash.to_owned())
+                .or_default()
+                .push(photo);
+        }
 
-        Ok(result)
+        Ok(photos)
     }
 
-    pub async fn get_photo_by_name(name: &str, hash: &str, pool: &Pool) -> DbSingleResult<Self> {
+    /// Returns the paths out of `file_paths` that already belong to a photo
+    pub async fn get_existing_paths(
+        file_paths: &[String],
+        pool: &Pool,
+    ) -> DbSingleResult<HashSet<String>> {
+        if file_paths.is_empty() {
+            return Ok(HashSet::new());
+        }
+
         let client = pool.get().await?;
         let stmt = client
-            .prepare("SELECT * FROM photos WHERE file_name = $1 AND file_path = $2")
+            .prepare("SELECT file_path FROM photos WHERE file_path = ANY($1)")
             .await?;
-        let result = client.query_one(&stmt, &[&name, &hash]).await?;
+        let results = client.query(&stmt, &[&file_paths]).await?;
 
-        let photo = Photo::from_row(result).unwrap();
+        Ok(results.into_iter().map(|row| row.get(0)).collect())
+    }
 
-        Ok(photo)
+    /// Returns the id and stored fingerprint of the photos at `file_paths`, keyed by path. Paths
+    /// without a photo are left out.
+    pub async fn get_fingerprints_by_path(
+        file_paths: &[String],
+        pool: &Pool,
+    ) -> DbSingleResult<HashMap<String, (i32, Option<Fingerprint>)>> {
+        if file_paths.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "SELECT id, file_path, file_size, file_modified FROM photos \
+                 WHERE file_path = ANY($1)",
+            )
+            .await?;
+        let results = client.query(&stmt, &[&file_paths]).await?;
+
+        let photos = results
+            .into_iter()
+            .map(|row| {
+                let file_size: Option<i64> = row.get("file_size");
+                let file_modified: Option<NaiveDateTime> = row.get("file_modified");
+                let fingerprint = match (file_size, file_modified) {
+                    (Some(file_size), Some(file_modified)) => Some(Fingerprint {
+                        file_size,
+                        file_modified,
+                    }),
+                    _ => None,
+                };
+
+                (row.get("file_path"), (row.get("id"), fingerprint))
+            })
+            .collect();
+
+        Ok(photos)
+    }
+
+    /// Changes only the fields present in `update`, so that a move, scan or trash of the photo in
+    /// the meantime is not undone. Trashed photos are not updated.
+    pub async fn update_fields(
+        photo_id: i32,
+        update: &UpdatePhotoRequest,
+        pool: &Pool,
+    ) -> DbSingleResult<PhotoFull> {
+        let fields: [(&str, Option<&(dyn ToSql + Sync)>); 6] = [
+            ("rating", update.rating.as_ref().map(to_sql)),
+            ("rotation", update.rotation.as_ref().map(to_sql)),
+            (
+                "ineligible_for_wallpaper",
+                update.ineligible_for_wallpaper.as_ref().map(to_sql),
+            ),
+            (
+                "anonymous_entities",
+                update.anonymous_entities.as_ref().map(to_sql),
+            ),
+            ("original_width", update.original_width.as_ref().map(to_sql)),
+            (
+                "original_height",
+                update.original_height.as_ref().map(to_sql),
+            ),
+        ];
+
+        let mut params: Vec<&(dyn ToSql + Sync)> = vec![&photo_id];
+        let mut assignments = vec!["date_updated = current_timestamp".to_string()];
+        for (column, value) in fields.iter() {
+            if let Some(value) = value {
+                params.push(*value);
+                assignments.push(format!("{} = ${}", column, params.len()));
+            }
+        }
+
+        let query = format!(
+            "UPDATE photos SET {} WHERE id = $1 AND date_trashed IS NULL",
+            assignments.join(", ")
+        );
+
+        let client = pool.get().await?;
+        let stmt = client.prepare(query.as_str()).await?;
+        let count = client.execute(&stmt, params.as_slice()).await?;
+
+        if count == 0 {
+            return Err(ServiceError::NotFound(format!(
+                "Photo {} does not exist",
+                photo_id
+            )));
+        }
+
+        PhotoFull::get_by_id(photo_id, pool).await
+    }
+
+    /// Removes every photo stored at one of `file_paths` along with its tags, entities and
+    /// wallpapers. Returns the number of photos deleted.
+    pub async fn delete_photos_by_path(file_paths: &[String], pool: &Pool) -> DbSingleResult<u64> {
+        if file_paths.is_empty() {
+            return Ok(0);
+        }
+
+        let mut client = pool.get().await?;
+        let tx = client.transaction().await?;
+
+        let stmt = tx
+            .prepare("SELECT id FROM photos WHERE file_path = ANY($1)")
+            .await?;
+        let ids: Vec<i32> = tx
+            .query(&stmt, &[&file_paths])
+            .await?
+            .into_iter()
+            .map(|row| row.get(0))
+            .collect();
+
+        Photo::delete_rows(&tx, &ids).await?;
+
+        tx.commit().await?;
+
+        Ok(ids.len() as u64)
     }
 
-    pub async fn delete_photo(photo_id: i32, pool: &Pool) -> DbMessageResult {
-        let photo = Photo::get_by_id(photo_id, &pool).await?;
+    /// Points the photo stored at `old_path` to `new_path`. Returns the number of rows updated.
+    pub async fn update_file_path(
+        old_path: &str,
+        new_path: &str,
+        pool: &Pool,
+    ) -> DbSingleResult<u64> {
+        let new_name = Path::new(new_path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "UPDATE photos \
+                 SET file_path = $2, file_name = $3, date_updated = current_timestamp \
+                 WHERE file_path = $1",
+            )
+            .await?;
+        let count = client
+            .execute(&stmt, &[&old_path, &new_path, &new_name])
+            .await?;
 
-        // attempt to delete photo
-        fs::remove_file(&photo.file_path).expect("Could not delete file");
+        Ok(count)
+    }
+
+    /// Rewrites the paths of every photo inside `old_dir` after the directory itself was moved.
+    /// Returns the number of rows updated.
+    pub async fn update_folder_path(
+        old_dir: &str,
+        new_dir: &str,
+        pool: &Pool,
+    ) -> DbSingleResult<u64> {
+        let old_dir = format!("{}/", old_dir.trim_end_matches('/'));
+        let new_dir = format!("{}/", new_dir.trim_end_matches('/'));
 
         let client = pool.get().await?;
-        let stmt = client.prepare("DELETE FROM photos WHERE id = $1").await?;
-        let _result = client.execute(&stmt, &[&photo_id]).await?;
+        let stmt = client
+            .prepare(
+                "UPDATE photos \
+                 SET file_path = $2 || substr(file_path, length($1) + 1), \
+                     date_updated = current_timestamp \
+                 WHERE left(file_path, length($1)) = $1",
+            )
+            .await?;
+        let count = client.execute(&stmt, &[&old_dir, &new_dir]).await?;
 
-        Ok("File deleted successfully!".to_string())
+        Ok(count)
     }
 
-    pub async fn delete_photo_by_path(file_path: &str, pool: &Pool) -> DbMessageResult {
+    /// Returns the paths of every photo inside `dir`, including those in its subdirectories
+    pub async fn get_paths_in_dir(dir: &str, pool: &Pool) -> DbSingleResult<Vec<String>> {
+        let dir = format!("{}/", dir.trim_end_matches('/'));
+
         let client = pool.get().await?;
         let stmt = client
-            .prepare("DELETE FROM photos WHERE file_path = $1")
+            .prepare("SELECT file_path FROM photos WHERE left(file_path, length($1)) = $1")
             .await?;
-        let _ = client.execute(&stmt, &[&file_path]).await?;
+        let rows = client.query(&stmt, &[&dir]).await?;
 
-        Ok("Photo removed from database successfully!".to_string())
+        Ok(rows.into_iter().map(|row| row.get(0)).collect())
     }
 
-    pub async fn update_last_viewed(photo_id: i32, pool: &Pool) -> DbSingleResult<Self> {
+    /// Stores fingerprints for photos that were imported before fingerprints existed
+    pub async fn update_fingerprints(
+        fingerprints: &[(i32, Fingerprint)],
+        pool: &Pool,
+    ) -> DbSingleResult<u64> {
+        let ids: Vec<i32> = fingerprints.iter().map(|(id, _)| *id).collect();
+        let sizes: Vec<i64> = fingerprints.iter().map(|(_, f)| f.file_size).collect();
+        let modified: Vec<NaiveDateTime> =
+            fingerprints.iter().map(|(_, f)| f.file_modified).collect();
+
         let client = pool.get().await?;
         let stmt = client
-            .prepare("UPDATE photos SET last_viewed = current_timestamp WHERE id = $1")
+            .prepare(
+                "UPDATE photos p \
+                 SET file_size = u.file_size, file_modified = u.file_modified \
+                 FROM unnest($1::int[], $2::bigint[], $3::timestamp[]) AS u (id, file_size, file_modified) \
+                 WHERE p.id = u.id",
+            )
             .await?;
-        let _ = client.execute(&stmt, &[&photo_id]).await?;
+        let count = client.execute(&stmt, &[&ids, &sizes, &modified]).await?;
 
-        let photo = Photo::get_by_id(photo_id, pool).await?;
+        Ok(count)
+    }
 
-        Ok(photo)
+    /// Points each photo id to its new file path
+    pub async fn update_file_paths(moves: &[(i32, String)], pool: &Pool) -> DbSingleResult<u64> {
+        let ids: Vec<i32> = moves.iter().map(|(id, _)| *id).collect();
+        let paths: Vec<&str> = moves.iter().map(|(_, path)| path.as_str()).collect();
+        let names: Vec<&str> = paths
+            .iter()
+            .map(|path| {
+                Path::new(path)
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .unwrap_or_default()
+            })
+            .collect();
+
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "UPDATE photos p \
+                 SET file_path = u.file_path, file_name = u.file_name, date_updated = current_timestamp \
+                 FROM unnest($1::int[], $2::text[], $3::text[]) AS u (id, file_path, file_name) \
+                 WHERE p.id = u.id",
+            )
+            .await?;
+        let count = client.execute(&stmt, &[&ids, &paths, &names]).await?;
+
+        Ok(count)
     }
 
-    pub async fn update_photo_path(new_photo: &NewPhoto, pool: &Pool) -> DbSingleResult<bool> {
-        let name = &new_photo.file_name;
-        let hash = &new_photo.file_hash;
+    /// Returns the id and path of photos in `dir` that have no perceptual hash yet
+    pub async fn get_without_perceptual_hash(
+        dir: &str,
+        pool: &Pool,
+    ) -> DbSingleResult<Vec<(i32, String)>> {
+        // only photos below `dir` itself, not those in a sibling folder that starts with its name
+        let dir = format!("{}/", dir.trim_end_matches('/'));
 
-        // Photo exists, so we can retrieve it without checking for null
-        let mut photo_to_update = Photo::get_photo_by_name(name, hash, pool).await?;
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "SELECT id, file_path FROM photos \
+                 WHERE perceptual_hash IS NULL AND date_trashed IS NULL \
+                   AND left(file_path, length($1)) = $1",
+            )
+            .await?;
+        let rows = client.query(&stmt, &[&dir]).await?;
 
-        // Update the file path of the existing photo with the file path of the "new" photo
-        photo_to_update.file_path = new_photo.to_owned().file_path;
+        let photos = rows
+            .into_iter()
+            .map(|row| (row.get("id"), row.get("file_path")))
+            .collect();
 
-        // Save the changes to the database
-        Photo::update_photo(&photo_to_update, pool).await?;
+        Ok(photos)
+    }
 
-        Ok(true)
+    /// Returns the id, file hash and perceptual hash of every photo that has a perceptual hash
+    pub async fn get_perceptual_hashes(pool: &Pool) -> DbSingleResult<Vec<(i32, String, i64)>> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "SELECT id, file_hash, perceptual_hash FROM photos \
+                 WHERE perceptual_hash IS NOT NULL AND date_trashed IS NULL \
+                 ORDER BY id",
+            )
+            .await?;
+        let rows = client.query(&stmt, &[]).await?;
+
+        let hashes = rows
+            .into_iter()
+            .map(|row| {
+                (
+                    row.get("id"),
+                    row.get("file_hash"),
+                    row.get("perceptual_hash"),
+                )
+            })
+            .collect();
+
+        Ok(hashes)
+    }
+
+    pub async fn update_perceptual_hashes(
+        hashes: &[(i32, i64)],
+        pool: &Pool,
+    ) -> DbSingleResult<u64> {
+        let ids: Vec<i32> = hashes.iter().map(|(id, _)| *id).collect();
+        let perceptual_hashes: Vec<i64> = hashes.iter().map(|(_, hash)| *hash).collect();
+
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "UPDATE photos p \
+                 SET perceptual_hash = u.perceptual_hash \
+                 FROM unnest($1::int[], $2::bigint[]) AS u (id, perceptual_hash) \
+                 WHERE p.id = u.id",
+            )
+            .await?;
+        let count = client.execute(&stmt, &[&ids, &perceptual_hashes]).await?;
+
+        Ok(count)
+    }
+
+    /// Returns the id and path of HEIF photos in `dir` that were imported without dimensions
+    pub async fn get_heif_without_dimensions(
+        dir: &str,
+        pool: &Pool,
+    ) -> DbSingleResult<Vec<(i32, String)>> {
+        let dir = format!("{}/", dir.trim_end_matches('/'));
+
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "SELECT id, file_path FROM photos \
+                 WHERE (original_width = 0 OR original_height = 0) \
+                   AND (lower(file_name) LIKE '%.heic' OR lower(file_name) LIKE '%.heif') \
+                   AND date_trashed IS NULL AND left(file_path, length($1)) = $1",
+            )
+            .await?;
+        let rows = client.query(&stmt, &[&dir]).await?;
+
+        let photos = rows
+            .into_iter()
+            .map(|row| (row.get("id"), row.get("file_path")))
+            .collect();
+
+        Ok(photos)
+    }
+
+    /// Stores the dimensions read from HEIF files. The HEIF rotation only replaces the rotation of
+    /// photos that have not been rotated yet.
+    pub async fn update_heif_dimensions(
+        dimensions: &[(i32, HeifInfo)],
+        pool: &Pool,
+    ) -> DbSingleResult<u64> {
+        let ids: Vec<i32> = dimensions.iter().map(|(id, _)| *id).collect();
+        let widths: Vec<i32> = dimensions.iter().map(|(_, i)| i.width as i32).collect();
+        let heights: Vec<i32> = dimensions.iter().map(|(_, i)| i.height as i32).collect();
+        let rotations: Vec<i32> = dimensions.iter().map(|(_, i)| i.rotation).collect();
+
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "UPDATE photos p \
+                 SET original_width = u.width, original_height = u.height, \
+                     rotation = CASE WHEN p.rotation = 0 THEN u.rotation ELSE p.rotation END \
+                 FROM unnest($1::int[], $2::int[], $3::int[], $4::int[]) AS u (id, width, height, rotation) \
+                 WHERE p.id = u.id",
+            )
+            .await?;
+        let count = client
+            .execute(&stmt, &[&ids, &widths, &heights, &rotations])
+            .await?;
+
+        Ok(count)
+    }
+
+    /// Returns the id and path of photos in `dir` whose EXIF metadata has not been read yet
+    pub async fn get_without_exif(dir: &str, pool: &Pool) -> DbSingleResult<Vec<(i32, String)>> {
+        let dir = format!("{}/", dir.trim_end_matches('/'));
+
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "SELECT id, file_path FROM photos \
+                 WHERE exif_extracted IS FALSE AND date_trashed IS NULL \
+                   AND left(file_path, length($1)) = $1",
+            )
+            .await?;
+        let rows = client.query(&stmt, &[&dir]).await?;
+
+        let photos = rows
+            .into_iter()
+            .map(|row| (row.get("id"), row.get("file_path")))
+            .collect();
+
+        Ok(photos)
+    }
+
+    /// Stores EXIF metadata for photos imported before it was extracted. The EXIF orientation only
+    /// replaces the rotation of photos that have not been rotated by hand.
+    pub async fn update_exif(metadata: &[(i32, ExifMetadata)], pool: &Pool) -> DbSingleResult<u64> {
+        if metadata.is_empty() {
+            return Ok(0);
+        }
+
+        let ids: Vec<i32> = metadata.iter().map(|(id, _)| *id).collect();
+        let rotations: Vec<i32> = metadata.iter().map(|(_, exif)| exif.rotation()).collect();
+        let dates_taken: Vec<Option<NaiveDateTime>> =
+            metadata.iter().map(|(_, exif)| exif.date_taken).collect();
+        let camera_makes: Vec<Option<&str>> = metadata
+            .iter()
+            .map(|(_, exif)| exif.camera_make.as_deref())
+            .collect();
+        let camera_models: Vec<Option<&str>> = metadata
+            .iter()
+            .map(|(_, exif)| exif.camera_model.as_deref())
+            .collect();
+        let lens_models: Vec<Option<&str>> = metadata
+            .iter()
+            .map(|(_, exif)| exif.lens_model.as_deref())
+            .collect();
+        let exposure_times: Vec<Option<&str>> = metadata
+            .iter()
+            .map(|(_, exif)| exif.exposure_time.as_deref())
+            .collect();
+        let f_numbers: Vec<Option<f32>> = metadata.iter().map(|(_, exif)| exif.f_number).collect();
+        let focal_lengths: Vec<Option<f32>> =
+            metadata.iter().map(|(_, exif)| exif.focal_length).collect();
+        let isos: Vec<Option<i32>> = metadata.iter().map(|(_, exif)| exif.iso).collect();
+
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "UPDATE photos p \
+                 SET rotation = CASE WHEN p.rotation = 0 THEN u.rotation ELSE p.rotation END, \
+                     date_taken = u.date_taken, camera_make = u.camera_make, \
+                     camera_model = u.camera_model, lens_model = u.lens_model, \
+                     exposure_time = u.exposure_time, f_number = u.f_number, \
+                     focal_length = u.focal_length, iso = u.iso, exif_extracted = true \
+                 FROM unnest($1::int[], $2::int[], $3::timestamp[], $4::text[], $5::text[], $6::text[], \
+                             $7::text[], $8::real[], $9::real[], $10::int[]) \
+                      AS u (id, rotation, date_taken, camera_make, camera_model, lens_model, \
+                            exposure_time, f_number, focal_length, iso) \
+                 WHERE p.id = u.id",
+            )
+            .await?;
+        let count = client
+            .execute(
+                &stmt,
+                &[
+                    &ids,
+                    &rotations,
+                    &dates_taken,
+                    &camera_makes,
+                    &camera_models,
+                    &lens_models,
+                    &exposure_times,
+                    &f_numbers,
+                    &focal_lengths,
+                    &isos,
+                ],
+            )
+            .await?;
+
+        Ok(count)
+    }
+
+    /// Replaces the hashes, fingerprint, dimensions and EXIF metadata of a photo whose file was
+    /// modified in place. A rotation that is already set is kept.
+    pub async fn update_file_contents(
+        photo_id: i32,
+        contents: &NewPhoto,
+        pool: &Pool,
+    ) -> DbSingleResult<u64> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "UPDATE photos \
+                 SET file_hash = $2, file_size = $3, file_modified = $4, \
+                     original_width = $5, original_height = $6, perceptual_hash = $7, \
+                     rotation = CASE WHEN rotation = 0 THEN $8 ELSE rotation END, \
+                     date_taken = $9, camera_make = $10, camera_model = $11, \
+                     lens_model = $12, exposure_time = $13, f_number = $14, focal_length = $15, \
+                     iso = $16, exif_extracted = true, media_type = $17, duration = $18, \
+                     codec = $19, date_updated = current_timestamp \
+                 WHERE id = $1",
+            )
+            .await?;
+        let count = client
+            .execute(
+                &stmt,
+                &[
+                    &photo_id,
+                    &contents.file_hash,
+                    &contents.file_size,
+                    &contents.file_modified,
+                    &contents.original_width,
+                    &contents.original_height,
+                    &contents.perceptual_hash,
+                    &contents.rotation,
+                    &contents.exif.date_taken,
+                    &contents.exif.camera_make,
+                    &contents.exif.camera_model,
+                    &contents.exif.lens_model,
+                    &contents.exif.exposure_time,
+                    &contents.exif.f_number,
+                    &contents.exif.focal_length,
+                    &contents.exif.iso,
+                    &contents.media_type,
+                    &contents.duration,
+                    &contents.codec,
+                ],
+            )
+            .await?;
+
+        Ok(count)
+    }
+
+    /// Deletes the photos along with their tags, entities and wallpapers. Files are left alone.
+    pub async fn delete_photos(photo_ids: &[i32], pool: &Pool) -> DbSingleResult<()> {
+        let mut client = pool.get().await?;
+        let tx = client.transaction().await?;
+
+        Photo::delete_rows(&tx, photo_ids).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Carries the tags, entities and wallpapers of the photos in `other_ids` over to `photo_id`,
+    /// which also keeps the best rating and latest view. Used to fold duplicates into the copy that
+    /// is kept.
+    pub async fn carry_over(
+        tx: &Transaction<'_>,
+        photo_id: i32,
+        other_ids: &[i32],
+    ) -> DbSingleResult<()> {
+        let carry_over = [
+            "INSERT INTO photo_tag (photo_id, tag_id) \
+             SELECT $1, tag_id FROM photo_tag WHERE photo_id = ANY($2) \
+             ON CONFLICT DO NOTHING",
+            "INSERT INTO photo_entity (photo_id, entity_id) \
+             SELECT $1, entity_id FROM photo_entity WHERE photo_id = ANY($2) \
+             ON CONFLICT DO NOTHING",
+            // only one wallpaper per size can move over, and only if the survivor has none yet
+            "UPDATE photo_wallpaper SET photo_id = $1 \
+             WHERE id IN ( \
+                 SELECT DISTINCT ON (wallpaper_size_id) id FROM photo_wallpaper \
+                 WHERE photo_id = ANY($2) \
+                   AND wallpaper_size_id NOT IN \
+                       (SELECT wallpaper_size_id FROM photo_wallpaper WHERE photo_id = $1) \
+                 ORDER BY wallpaper_size_id, id)",
+            "UPDATE photos p \
+             SET rating = greatest(p.rating, o.rating), \
+                 last_viewed = greatest(p.last_viewed, o.last_viewed), \
+                 date_updated = current_timestamp \
+             FROM (SELECT max(rating) AS rating, max(last_viewed) AS last_viewed \
+                   FROM photos WHERE id = ANY($2)) o \
+             WHERE p.id = $1",
+        ];
+
+        for statement in carry_over.iter() {
+            let stmt = tx.prepare(statement).await?;
+            let _ = tx.execute(&stmt, &[&photo_id, &other_ids]).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Same as `delete_photos`, within a transaction of the caller
+    pub async fn delete_rows(tx: &Transaction<'_>, photo_ids: &[i32]) -> DbSingleResult<()> {
+        // junction rows have no cascading deletes, so clear them before the photos themselves
+        let cleanup = [
+            "DELETE FROM photo_tag WHERE photo_id = ANY($1)",
+            "DELETE FROM photo_entity WHERE photo_id = ANY($1)",
+            "DELETE FROM photo_wallpaper WHERE photo_id = ANY($1)",
+            "DELETE FROM photos WHERE id = ANY($1)",
+        ];
+
+        for statement in cleanup.iter() {
+            let stmt = tx.prepare(statement).await?;
+            let _ = tx.execute(&stmt, &[&photo_ids]).await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn update_last_viewed(photo_id: i32, pool: &Pool) -> DbSingleResult<Self> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare("UPDATE photos SET last_viewed = current_timestamp WHERE id = $1")
+            .await?;
+        let _ = client.execute(&stmt, &[&photo_id]).await?;
+
+        let photo = Photo::get_by_id(photo_id, pool).await?;
+
+        Ok(photo)
     }
 
     // ENTITIES ************************************************************************************
@@ -246,7 +877,14 @@ impl Photo {
         pool: &Pool,
     ) -> DbMessageResult {
         let client = pool.get().await?;
-        let stmt = client.prepare("insert into photo_wallpaper (photo_id, wallpaper_size_id, file_path) values ($1, $2, $3)").await?;
+        // rendering a wallpaper again replaces the previous one
+        let stmt = client
+            .prepare(
+                "insert into photo_wallpaper (photo_id, wallpaper_size_id, file_path) \
+                 values ($1, $2, $3) \
+                 on conflict (photo_id, wallpaper_size_id) do update set file_path = $3",
+            )
+            .await?;
         let _ = client
             .execute(&stmt, &[&photo_id, &wallpaper_size_id, &file_path])
             .await?;
@@ -259,20 +897,37 @@ impl Photo {
         ))
     }
 
+    /// Removes the wallpaper of a photo in a size along with its rendered file
     pub async fn remove_wallpaper_from_photo(
         photo_id: i32,
         wallpaper_size_id: i32,
         pool: &Pool,
     ) -> DbMessageResult {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare("delete from photo_wallpaper where photo_id = $1 and wallpaper_size_id = $2")
-            .await?;
-        let _ = client
-            .execute(&stmt, &[&photo_id, &wallpaper_size_id])
+        let size = WallpaperSize::get_by_id(wallpaper_size_id, &pool).await?;
+
+        let mut client = pool.get().await?;
+        let tx = client.transaction().await?;
+        let stmt = tx
+            .prepare(
+                "delete from photo_wallpaper where photo_id = $1 and wallpaper_size_id = $2 \
+                 returning file_path",
+            )
             .await?;
+        let row = tx
+            .query_opt(&stmt, &[&photo_id, &wallpaper_size_id])
+            .await?
+            .ok_or_else(|| {
+                ServiceError::NotFound(format!(
+                    "Photo {} has no wallpaper in size `{}`",
+                    photo_id, size.name
+                ))
+            })?;
 
-        let size = WallpaperSize::get_by_id(wallpaper_size_id, &pool).await?;
+        // the row is only removed once its file is gone
+        let file_path: String = row.get(0);
+        wallpapers::remove(&file_path)?;
+
+        tx.commit().await?;
 
         Ok(format!(
             "Wallpaper size `{}` removed from photo successfully",
@@ -280,3 +935,105 @@ impl Photo {
         ))
     }
 }
+
+fn to_sql<T: ToSql + Sync>(value: &T) -> &(dyn ToSql + Sync) {
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+    use crate::schemas::tags::Tag;
+    use crate::utils::http_server;
+
+    /// Needs the database configured in `.env`, run with `cargo test -- --ignored`
+    #[actix_rt::test]
+    #[ignore]
+    async fn deletes_missing_photo_with_tags() {
+        dotenv::dotenv().ok();
+        let pool = http_server::create_pool();
+        let client = pool.get().await.unwrap();
+
+        let file_path = format!("/missing-{}/photo.jpg", process::id());
+        let photo_id: i32 = client
+            .query_one(
+                "INSERT INTO photos (file_path, file_name) VALUES ($1, 'photo.jpg') RETURNING id",
+                &[&file_path],
+            )
+            .await
+            .unwrap()
+            .get(0);
+        let tag = Tag::create(&format!("missing-{}", process::id()), &pool)
+            .await
+            .unwrap();
+        Photo::add_tag_to_photo(photo_id, tag.id, &pool)
+            .await
+            .unwrap();
+
+        let deleted = Photo::delete_photos_by_path(&[file_path], &pool).await;
+
+        let remaining: i64 = client
+            .query_one(
+                "SELECT count(*) FROM photo_tag WHERE photo_id = $1",
+                &[&photo_id],
+            )
+            .await
+            .unwrap()
+            .get(0);
+        let _ = client
+            .execute("DELETE FROM tags WHERE id = $1", &[&tag.id])
+            .await;
+
+        assert_eq!(deleted.unwrap(), 1);
+        assert_eq!(remaining, 0);
+        assert!(Photo::get_by_id(photo_id, &pool).await.is_err());
+    }
+
+    /// Needs the database configured in `.env`, run with `cargo test -- --ignored`
+    #[actix_rt::test]
+    #[ignore]
+    async fn updates_only_requested_fields() {
+        dotenv::dotenv().ok();
+        let pool = http_server::create_pool();
+        let client = pool.get().await.unwrap();
+
+        let file_path = format!("/update-{}/photo.jpg", process::id());
+        let photo_id: i32 = client
+            .query_one(
+                "INSERT INTO photos (file_path, file_name) VALUES ($1, 'photo.jpg') RETURNING id",
+                &[&file_path],
+            )
+            .await
+            .unwrap()
+            .get(0);
+        let update = UpdatePhotoRequest {
+            rating: Some(4),
+            ..Default::default()
+        };
+
+        // the file is moved after the photo was read for validation
+        let moved_path = format!("/update-{}/moved.jpg", process::id());
+        Photo::update_file_path(&file_path, &moved_path, &pool)
+            .await
+            .unwrap();
+        let updated = Photo::update_fields(photo_id, &update, &pool).await;
+
+        client
+            .execute(
+                "UPDATE photos SET date_trashed = current_timestamp WHERE id = $1",
+                &[&photo_id],
+            )
+            .await
+            .unwrap();
+        let trashed = Photo::update_fields(photo_id, &update, &pool).await;
+
+        let _ = Photo::delete_photos(&[photo_id], &pool).await;
+
+        let updated = updated.unwrap();
+        assert_eq!(updated.rating, 4);
+        assert_eq!(updated.file_path, moved_path);
+        assert!(matches!(trashed, Err(ServiceError::NotFound(_))));
+    }
+}
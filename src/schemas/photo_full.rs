@@ -1,13 +1,15 @@
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 
 use chrono::NaiveDateTime;
-use deadpool_postgres::{Client, Pool};
+use deadpool_postgres::{Client, Pool, Transaction};
 use percent_encoding::{percent_encode, AsciiSet, CONTROLS};
 use serde::{Deserialize, Serialize};
 use tokio_postgres::types::ToSql;
 use tokio_postgres::Row;
 
 use crate::errors::ServiceError;
+use crate::files::thumbnails;
 use crate::pagination::links::Links;
 use crate::pagination::page::Page;
 use crate::pagination::page_metadata::PageMetadata;
@@ -42,20 +44,52 @@ pub struct PhotoFull {
     pub entities: Option<Vec<String>>,
     pub tags: Option<Vec<String>>,
     pub wallpapers: Option<Vec<String>>,
+    pub date_taken: Option<NaiveDateTime>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub lens_model: Option<String>,
+    pub exposure_time: Option<String>,
+    pub f_number: Option<f32>,
+    pub focal_length: Option<f32>,
+    pub iso: Option<i32>,
+    pub library_id: Option<i32>,
+    /// `image` or `video`
+    pub media_type: String,
+    /// Length of a clip in seconds
+    pub duration: Option<f64>,
+    pub codec: Option<String>,
 
     pub media_url: String,
+    /// Url of the thumbnail in every configured size, keyed by the name of the size. Empty for
+    /// photos that no thumbnails can be generated for.
+    pub thumbnail_urls: BTreeMap<String, String>,
+}
+
+/// A photo that looks like another one, along with the number of bits by which their perceptual
+/// hashes differ
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SimilarPhoto {
+    pub distance: i32,
+    #[serde(flatten)]
+    pub photo: PhotoFull,
 }
 
 impl PhotoFull {
     pub fn from_row(row: &Row) -> Self {
         let file_path: String = row.get("file_path");
+        let library_root: Option<String> = row.get("library_root");
+        let media_prefix: Option<String> = row.get("media_prefix");
+        let id: i32 = row.get("id");
+        let file_hash: String = row.get("file_hash");
+        let rotation: i32 = row.get("rotation");
 
         PhotoFull {
-            id: row.get("id"),
+            id,
             file_path: file_path.clone(),
             folder: row.get("folder"),
             file_name: row.get("file_name"),
-            file_hash: row.get("file_hash"),
+            file_hash: file_hash.clone(),
             rating: row.get("rating"),
             date_created: row.get("date_created"),
             date_updated: row.get("date_updated"),
@@ -64,7 +98,7 @@ impl PhotoFull {
             original_height: row.get("original_height"),
             aspect_ratio: row.get("aspect_ratio"),
             orientation: row.get("orientation"),
-            rotation: row.get("rotation"),
+            rotation,
             ineligible_for_wallpaper: row.get("ineligible_for_wallpaper"),
             anonymous_entities: row.get("anonymous_entities"),
             suggested_entity_name: row.get("suggested_entity_name"),
@@ -72,8 +106,21 @@ impl PhotoFull {
             entities: row.get("entities"),
             tags: row.get("tags"),
             wallpapers: row.get("wallpapers"),
-
-            media_url: PhotoFull::build_photo_url(file_path),
+            date_taken: row.get("date_taken"),
+            camera_make: row.get("camera_make"),
+            camera_model: row.get("camera_model"),
+            lens_model: row.get("lens_model"),
+            exposure_time: row.get("exposure_time"),
+            f_number: row.get("f_number"),
+            focal_length: row.get("focal_length"),
+            iso: row.get("iso"),
+            library_id: row.get("library_id"),
+            media_type: row.get("media_type"),
+            duration: row.get("duration"),
+            codec: row.get("codec"),
+
+            thumbnail_urls: PhotoFull::build_thumbnail_urls(id, &file_path, &file_hash, rotation),
+            media_url: PhotoFull::build_photo_url(file_path, library_root, media_prefix),
         }
     }
 
@@ -97,16 +144,108 @@ impl PhotoFull {
         Ok(photos)
     }
 
+    /// Returns the photo unless it is in the trash, see `Photo::get_visible`
     pub async fn get_by_id(id: i32, pool: &Pool) -> DbSingleResult<Self> {
         let client = pool.get().await?;
         let stmt = client
             .prepare("select * from photos_all where id = $1")
             .await?;
-        let result = client.query_one(&stmt, &[&id]).await?;
+        let result = client.query_opt(&stmt, &[&id]).await?;
+
+        match result {
+            Some(row) => Ok(PhotoFull::from_row(&row)),
+            None => Err(ServiceError::NotFound(format!(
+                "Photo {} does not exist",
+                id
+            ))),
+        }
+    }
+
+    pub async fn get_by_hash(file_hash: &str, pool: &Pool) -> DbVecResult<Self> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare("select * from photos_all where file_hash = $1 order by file_path")
+            .await?;
+        let rows = client.query(&stmt, &[&file_hash]).await?;
+
+        let photos = rows
+            .into_iter()
+            .map(|row| PhotoFull::from_row(&row))
+            .collect::<Vec<PhotoFull>>();
+
+        Ok(photos)
+    }
+
+    /// Returns the photos of every hash in one query, keyed by hash
+    pub async fn get_by_hashes(
+        file_hashes: &[&str],
+        pool: &Pool,
+    ) -> DbSingleResult<HashMap<String, Vec<Self>>> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare("select * from photos_all where file_hash = any($1) order by file_path")
+            .await?;
+        let rows = client.query(&stmt, &[&file_hashes]).await?;
+
+        let mut photos: HashMap<String, Vec<PhotoFull>> = HashMap::new();
+        for row in rows {
+            let photo = PhotoFull::from_row(&row);
+            photos
+                .entry(photo.file_hash.to_owned())
+                .or_default()
+                .push(photo);
+        }
+
+        Ok(photos)
+    }
+
+    pub async fn get_by_ids(ids: &[i32], pool: &Pool) -> DbVecResult<Self> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare("select * from photos_all where id = any($1) order by file_path")
+            .await?;
+        let rows = client.query(&stmt, &[&ids]).await?;
 
-        let photo = PhotoFull::from_row(&result);
+        let photos = rows
+            .into_iter()
+            .map(|row| PhotoFull::from_row(&row))
+            .collect::<Vec<PhotoFull>>();
 
-        Ok(photo)
+        Ok(photos)
+    }
+
+    /// Returns photos whose perceptual hash is within `max_distance` bits of the given photo,
+    /// closest first
+    pub async fn get_similar(
+        photo_id: i32,
+        max_distance: i32,
+        pool: &Pool,
+    ) -> DbVecResult<SimilarPhoto> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "select pa.*, s.distance
+                 from (select p.id, hamming_distance(p.perceptual_hash, o.perceptual_hash) as distance
+                       from photos p
+                                inner join photos o on o.id = $1
+                       where p.id <> o.id
+                         and p.perceptual_hash is not null) s
+                          inner join photos_all pa on pa.id = s.id
+                 where s.distance <= $2
+                 order by s.distance, pa.file_path",
+            )
+            .await?;
+        let rows = client.query(&stmt, &[&photo_id, &max_distance]).await?;
+
+        let photos = rows
+            .into_iter()
+            .map(|row| SimilarPhoto {
+                distance: row.get("distance"),
+                photo: PhotoFull::from_row(&row),
+            })
+            .collect();
+
+        Ok(photos)
     }
 
     pub async fn get_page(
@@ -114,7 +253,10 @@ impl PhotoFull {
         pool: &Pool,
     ) -> Result<PaginatedPhotos, ServiceError> {
         let client = pool.get().await?;
-        let mut params: Vec<&(dyn ToSql + Sync)> = vec![];
+
+        let page_size = req.get_page_size();
+        let offset = (req.get_page() - 1) * req.get_page_size();
+        let mut params: Vec<&(dyn ToSql + Sync)> = vec![&offset, &page_size];
 
         // pre-emptive TODO: cleanup and optimize this procedurally built query
         let mut query = "select id,
@@ -138,6 +280,20 @@ impl PhotoFull {
                                entities,
                                tags,
                                wallpapers,
+                               date_taken,
+                               camera_make,
+                               camera_model,
+                               lens_model,
+                               exposure_time,
+                               f_number,
+                               focal_length,
+                               iso,
+                               library_id,
+                               library_root,
+                               media_prefix,
+                               media_type,
+                               duration,
+                               codec,
                                count(*) over ()
                         from (
                                  select row_number() over () as position, photos.*
@@ -148,64 +304,34 @@ impl PhotoFull {
                                                               on pa.id = po.photo_id "
             .to_string();
 
-        if req.has_collection_or_filters() {
-            query += " \nWHERE ";
-
-            // collections override custom filters since a collection should already have necessary filtering logic
-            if req.collection_id.is_some() {
-                let collection_id = &req.collection_id.unwrap();
-
-                let collection = Collection::get(collection_id.to_owned(), pool).await?;
-
-                query += format!(" ({}) ", collection.query).as_str();
-            } else {
-                // folder
-                let folder = req.get_folder();
-                query += format!("(folder like '{}' || '%')", folder).as_str();
-
-                // ratings
-                if req.get_exclude_ratings().is_some() {
-                    let excluded = req.get_exclude_ratings().unwrap().join(", ");
-
-                    query += format!(" AND (rating NOT IN ({}))", excluded).as_str();
-                }
-            }
-        }
+        query += &PhotoFull::build_filters(&req, &mut params, pool).await?;
 
         query += " order by po.position \n
               ) photos \n
      ) random";
 
-        query += " WHERE random.position > $1 ";
-
         // sorting
-        query += " ORDER BY ";
-
         if req.get_sort_by().is_some() {
             let sortings = PhotoFull::determine_sorting(req.clone().get_sort_by().unwrap());
 
-            let length = sortings.len();
-            for (index, (category, direction)) in sortings.into_iter().enumerate() {
-                query += format!("{} {}", category, direction).as_str();
-
-                if index >= length {
-                    query += ", "
-                }
+            // positions follow the random order, so pages of any other order are counted off the
+            // sorted rows instead. The id breaks ties, otherwise rows with equal values could
+            // move between pages.
+            query += " ORDER BY ";
+            for (category, direction) in sortings.into_iter() {
+                // photos without EXIF have no value for most camera columns, keep them at the end
+                query += format!("{} {} NULLS LAST, ", category, direction).as_str();
             }
+            query += " id OFFSET $1 ";
         } else {
             // aka random sorting
-            query += " random.position "
+            query += " WHERE random.position > $1 ORDER BY random.position ";
         }
 
         query += " LIMIT $2";
 
         println!("\n{}\n", &query);
 
-        let page_size = &req.get_page_size();
-        let page = (req.get_page() - 1) * req.get_page_size();
-        params.push(&page);
-        params.push(page_size);
-
         let stmt = client.prepare(query.as_str()).await?;
         let rows = client.query(&stmt, params.as_slice()).await?;
 
@@ -223,28 +349,162 @@ impl PhotoFull {
         Ok(page)
     }
 
-    fn build_photo_url(image_path: String) -> String {
-        let divider = "photos";
+    /// Returns the ids of every photo matching the collection or filters of a request, in id
+    /// order. Pagination and sorting are ignored. The ids are read within `tx`, so that they can be
+    /// acted on in the same transaction.
+    pub async fn get_ids(
+        req: &GetPhotosRequest,
+        tx: &Transaction<'_>,
+        pool: &Pool,
+    ) -> DbVecResult<i32> {
+        let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+        let mut query = "select pa.id from photos_all pa ".to_string();
+        query += &PhotoFull::build_filters(req, &mut params, pool).await?;
+        query += " order by pa.id";
+
+        let stmt = tx.prepare(query.as_str()).await?;
+        let rows = tx.query(&stmt, params.as_slice()).await?;
+
+        Ok(rows.into_iter().map(|row| row.get(0)).collect())
+    }
+
+    /// Builds the `WHERE` clause for the collection or filters of a request and pushes the values
+    /// it references onto `params`. Returns an empty string if the request has neither.
+    async fn build_filters<'a>(
+        req: &'a GetPhotosRequest,
+        params: &mut Vec<&'a (dyn ToSql + Sync)>,
+        pool: &Pool,
+    ) -> Result<String, ServiceError> {
+        let mut query = String::new();
+
+        if !req.has_collection_or_filters() {
+            return Ok(query);
+        }
+
+        query += " \nWHERE ";
+
+        // collections override custom filters since a collection should already have necessary filtering logic
+        if let Some(collection_id) = req.collection_id {
+            let collection = Collection::get(collection_id, pool).await?;
+
+            query += format!(" ({}) ", collection.query).as_str();
+
+            return Ok(query);
+        }
+
+        let mut conditions: Vec<String> = Vec::new();
+
+        // folder, compared as a prefix so that `%` and `_` in the name are not wildcards
+        if let Some(folder) = req.get_folder_filter() {
+            params.push(folder);
+            conditions.push(format!(
+                "(left(folder, char_length(${0})) = ${0})",
+                params.len()
+            ));
+        }
+
+        // ratings, only ever digits between 0 and 5
+        if let Some(excluded) = req.get_exclude_ratings() {
+            conditions.push(format!("(rating NOT IN ({}))", excluded.join(", ")));
+        }
+
+        // camera metadata
+        if let Some(camera_make) = req.get_camera_make() {
+            params.push(camera_make);
+            conditions.push(format!("(lower(camera_make) = lower(${}))", params.len()));
+        }
+
+        if let Some(camera_model) = req.get_camera_model() {
+            params.push(camera_model);
+            conditions.push(format!("(lower(camera_model) = lower(${}))", params.len()));
+        }
+
+        if let Some(lens_model) = req.get_lens_model() {
+            params.push(lens_model);
+            conditions.push(format!("(lower(lens_model) = lower(${}))", params.len()));
+        }
+
+        // images or clips only
+        if let Some(media_type) = req.get_media_type() {
+            params.push(media_type);
+            conditions.push(format!("(media_type = ${})", params.len()));
+        }
+
+        // date taken, both ends inclusive
+        if let Some(taken_after) = req.get_taken_after() {
+            params.push(taken_after);
+            conditions.push(format!("(date_taken >= ${}::date)", params.len()));
+        }
+
+        if let Some(taken_before) = req.get_taken_before() {
+            params.push(taken_before);
+            conditions.push(format!("(date_taken < ${}::date + 1)", params.len()));
+        }
+
+        if conditions.is_empty() {
+            return Ok(String::new());
+        }
+
+        query += &conditions.join(" AND ");
+
+        Ok(query)
+    }
+
+    /// Builds the url of the photo relative to the root of its library. Photos outside of every
+    /// library have no url to be served from, so an empty string is returned for them.
+    fn build_photo_url(
+        image_path: String,
+        library_root: Option<String>,
+        media_prefix: Option<String>,
+    ) -> String {
+        let (library_root, media_prefix) = match (library_root, media_prefix) {
+            (Some(library_root), Some(media_prefix)) => (library_root, media_prefix),
+            _ => return String::new(),
+        };
 
         let hostname = env::var("SCARLETT_HOSTNAME")
             .expect("SCARLETT_HOSTNAME environment variable not found.");
 
-        let divider_offset = &image_path
-            .find(&divider)
-            .unwrap_or_else(|| image_path.len());
-        let divider_length = &divider.len();
-        let index = divider_offset + divider_length + 1;
-
-        let mut path = image_path;
-        path.replace_range(..index, "");
+        let path = image_path
+            .strip_prefix(library_root.as_str())
+            .unwrap_or(&image_path)
+            .trim_start_matches('/');
 
-        let url = format!("https://{}/media/{}", hostname, path);
+        let url = format!("https://{}/files/{}/{}", hostname, media_prefix, path);
 
         const FRAGMENT: &AsciiSet = &CONTROLS.add(b' ').add(b'\'');
         let encoded = percent_encode(url.as_ref(), FRAGMENT);
         encoded.to_string()
     }
 
+    /// Builds the url of every thumbnail size. The urls carry the start of the file hash and the
+    /// rotation, so that a thumbnail cached by a client is fetched again once either changes.
+    fn build_thumbnail_urls(
+        id: i32,
+        file_path: &str,
+        file_hash: &str,
+        rotation: i32,
+    ) -> BTreeMap<String, String> {
+        if !thumbnails::can_generate(file_path) {
+            return BTreeMap::new();
+        }
+
+        let hostname = env::var("SCARLETT_HOSTNAME")
+            .expect("SCARLETT_HOSTNAME environment variable not found.");
+        let version = format!("{}-{}", file_hash.get(..16).unwrap_or(file_hash), rotation);
+
+        thumbnails::thumbnail_sizes()
+            .into_iter()
+            .map(|size| {
+                let url = format!(
+                    "https://{}/thumbnails/{}/{}?v={}",
+                    hostname, id, size.name, version
+                );
+                (size.name, url)
+            })
+            .collect()
+    }
+
     fn determine_sorting(sorting: Vec<String>) -> Vec<(String, String)> {
         sorting
             .into_iter()
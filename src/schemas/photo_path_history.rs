@@ -0,0 +1,44 @@
+use chrono::NaiveDateTime;
+use deadpool_postgres::Pool;
+use serde::{Deserialize, Serialize};
+use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_pg_mapper_derive::PostgresMapper;
+
+use crate::types::DbVecResult;
+
+// PHOTO PATH HISTORY ******************************************************************************
+
+/// A path a photo has been stored at. Rows are written by a trigger on `photos`, never by hand.
+#[derive(Serialize, Deserialize, Debug, Clone, PostgresMapper)]
+#[serde(rename_all = "camelCase")]
+#[pg_mapper(table = "photo_path_history")]
+pub struct PhotoPathHistory {
+    pub id: i32,
+    pub photo_id: i32,
+    /// `None` for the path the photo was first found at
+    pub old_path: Option<String>,
+    pub new_path: String,
+    pub date_changed: NaiveDateTime,
+}
+
+impl PhotoPathHistory {
+    /// Returns every path the photo has been stored at, oldest first
+    pub async fn get_for_photo(photo_id: i32, pool: &Pool) -> DbVecResult<Self> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "select * from photo_path_history \
+                 where photo_id = $1 \
+                 order by date_changed, id",
+            )
+            .await?;
+        let results = client.query(&stmt, &[&photo_id]).await?;
+
+        let history = results
+            .into_iter()
+            .map(|result| PhotoPathHistory::from_row(result).unwrap())
+            .collect();
+
+        Ok(history)
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::slice;
+use std::sync::Arc;
+
+use chrono::NaiveDateTime;
+use deadpool_postgres::Pool;
+use serde::{Deserialize, Serialize};
+use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_pg_mapper_derive::PostgresMapper;
+
+use crate::errors::ServiceError;
+use crate::files::photos::{self, FileInfo};
+use crate::files::validation::FileIssue;
+use crate::jobs::scan::ScanProgress;
+use crate::schemas;
+use crate::schemas::new_photo::NewPhoto;
+use crate::schemas::photo::Photo;
+use crate::schemas::photo_full::PhotoFull;
+use crate::types::{DbMessageResult, DbSingleResult, DbVecResult};
+
+pub const SCAN_ISSUE_STATUSES: [&str; 2] = ["pending", "ignored"];
+
+// SCAN ISSUE **************************************************************************************
+
+/// A file that a scan quarantined because it failed validation, see `FileIssue`
+#[derive(Serialize, Deserialize, Debug, Clone, PostgresMapper)]
+#[serde(rename_all = "camelCase")]
+#[pg_mapper(table = "scan_issues")]
+pub struct ScanIssue {
+    pub id: i32,
+    pub file_path: String,
+    pub kind: String,
+    pub error: String,
+    /// `pending` until the issue is ignored. Scans skip files with an ignored issue.
+    pub status: String,
+    pub date_detected: NaiveDateTime,
+    pub date_updated: NaiveDateTime,
+}
+
+impl ScanIssue {
+    pub async fn get_all(status: Option<&str>, pool: &Pool) -> DbVecResult<Self> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "select * \
+                 from scan_issues \
+                 where $1::text is null or status = $1 \
+                 order by file_path",
+            )
+            .await?;
+        let results = client.query(&stmt, &[&status]).await?;
+
+        let issues = results
+            .into_iter()
+            .map(|result| ScanIssue::from_row(result).unwrap())
+            .collect();
+
+        Ok(issues)
+    }
+
+    pub async fn get(id: i32, pool: &Pool) -> DbSingleResult<Self> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare("select * from scan_issues where id = $1")
+            .await?;
+        let result = client.query_opt(&stmt, &[&id]).await?;
+
+        match result {
+            Some(row) => Ok(ScanIssue::from_row(row).unwrap()),
+            None => Err(ServiceError::NotFound(format!(
+                "Scan issue {} not found",
+                id
+            ))),
+        }
+    }
+
+    /// Returns the paths of files whose issue was ignored
+    pub async fn get_ignored_paths(pool: &Pool) -> DbSingleResult<HashSet<String>> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare("select file_path from scan_issues where status = 'ignored'")
+            .await?;
+        let results = client.query(&stmt, &[]).await?;
+
+        Ok(results.into_iter().map(|row| row.get(0)).collect())
+    }
+
+    /// Records the issues found by a scan. Files that already have an issue get the new reason,
+    /// but keep their status so that ignored issues stay ignored.
+    pub async fn record(issues: &[FileIssue], pool: &Pool) -> DbSingleResult<u64> {
+        if issues.is_empty() {
+            return Ok(0);
+        }
+
+        let paths: Vec<&str> = issues.iter().map(|i| i.file_path.as_str()).collect();
+        let kinds: Vec<&str> = issues.iter().map(|i| i.kind.as_str()).collect();
+        let errors: Vec<&str> = issues.iter().map(|i| i.error.as_str()).collect();
+
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "insert into scan_issues (file_path, kind, error) \
+                 select * from unnest($1::text[], $2::text[], $3::text[]) \
+                 on conflict (file_path) do update \
+                 set kind = excluded.kind, error = excluded.error, \
+                     date_updated = current_timestamp",
+            )
+            .await?;
+        let count = client.execute(&stmt, &[&paths, &kinds, &errors]).await?;
+
+        Ok(count)
+    }
+
+    /// Removes the issues of files that have since passed validation
+    pub async fn clear(file_paths: &[String], pool: &Pool) -> DbSingleResult<u64> {
+        if file_paths.is_empty() {
+            return Ok(0);
+        }
+
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare("delete from scan_issues where file_path = any($1)")
+            .await?;
+        let count = client.execute(&stmt, &[&file_paths]).await?;
+
+        Ok(count)
+    }
+
+    /// Removes the issues of files in `dir` that no longer exist
+    pub async fn clear_missing(dir: &str, pool: &Pool) -> DbSingleResult<u64> {
+        let dir = dir.trim_end_matches('/');
+
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "select file_path from scan_issues \
+                 where file_path = $1 or left(file_path, length($1) + 1) = $1 || '/'",
+            )
+            .await?;
+        let results = client.query(&stmt, &[&dir]).await?;
+
+        let missing: Vec<String> = results
+            .into_iter()
+            .map(|row| row.get::<_, String>(0))
+            .filter(|path| !Path::new(path).exists())
+            .collect();
+
+        ScanIssue::clear(&missing, pool).await
+    }
+
+    pub async fn ignore(id: i32, pool: &Pool) -> DbSingleResult<Self> {
+        ScanIssue::set_status(id, "ignored", pool).await?;
+
+        ScanIssue::get(id, pool).await
+    }
+
+    pub async fn delete(id: i32, pool: &Pool) -> DbMessageResult {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare("delete from scan_issues where id = $1")
+            .await?;
+        let _ = client.execute(&stmt, &[&id]).await?;
+
+        Ok("Scan issue removed successfully!".to_string())
+    }
+
+    /// Validates the file again and imports it if it passes, the same way a scan would. A retried
+    /// issue is no longer ignored, so a file that still fails shows up as pending again.
+    pub async fn retry(id: i32, pool: &Pool) -> DbSingleResult<PhotoFull> {
+        let issue = ScanIssue::get(id, pool).await?;
+
+        if !Path::new(&issue.file_path).is_file() {
+            ScanIssue::delete(id, pool).await?;
+
+            return Err(ServiceError::NotFound(format!(
+                "{} no longer exists, the issue was removed",
+                issue.file_path
+            )));
+        }
+
+        ScanIssue::set_status(id, "pending", pool).await?;
+
+        // the file may have been picked up since the issue was recorded
+        let existing = Photo::get_existing_paths(&[issue.file_path.to_owned()], pool).await?;
+        if existing.is_empty() {
+            let file = FileInfo::new_from_path(Path::new(&issue.file_path));
+            let file = match file {
+                Ok(file) => file,
+                Err(file_issue) => {
+                    ScanIssue::record(slice::from_ref(&file_issue), pool).await?;
+                    return Err(ScanIssue::retry_failed(&file_issue));
+                }
+            };
+
+            let progress = Arc::new(ScanProgress::default());
+            let result = photos::process_new_files(vec![file], pool, &progress, false).await?;
+
+            if let Some(file_issue) = result.issues.first() {
+                return Err(ScanIssue::retry_failed(file_issue));
+            }
+
+            NewPhoto::bulk_insert(&result.new_photos, pool).await?;
+            schemas::reset_seed(pool).await?;
+        }
+
+        ScanIssue::clear(&[issue.file_path.to_owned()], pool).await?;
+
+        let photo = Photo::get_by_path(&issue.file_path, pool).await?;
+
+        PhotoFull::get_by_id(photo.id, pool).await
+    }
+
+    fn retry_failed(file_issue: &FileIssue) -> ServiceError {
+        ServiceError::BadRequest(format!(
+            "{} still cannot be imported: {}",
+            file_issue.file_path, file_issue.error
+        ))
+    }
+
+    async fn set_status(id: i32, status: &str, pool: &Pool) -> DbSingleResult<()> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "update scan_issues \
+                 set status = $2, date_updated = current_timestamp \
+                 where id = $1",
+            )
+            .await?;
+        let count = client.execute(&stmt, &[&id, &status]).await?;
+
+        if count == 0 {
+            return Err(ServiceError::NotFound(format!(
+                "Scan issue {} not found",
+                id
+            )));
+        }
+
+        Ok(())
+    }
+}
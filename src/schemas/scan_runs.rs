@@ -0,0 +1,192 @@
+use chrono::NaiveDateTime;
+use deadpool_postgres::Pool;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JSON;
+use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_pg_mapper_derive::PostgresMapper;
+use tokio_postgres::Row;
+
+use crate::errors::ServiceError;
+use crate::files::photos::{FileScanResult, PlannedMove};
+use crate::files::scan_rules::ExcludedFiles;
+use crate::types::{DbSingleResult, DbVecResult};
+
+// SCAN RUN ****************************************************************************************
+
+/// A scan that was started, along with the counts of what it changed
+#[derive(Serialize, Deserialize, Debug, Clone, PostgresMapper)]
+#[serde(rename_all = "camelCase")]
+#[pg_mapper(table = "scan_runs")]
+pub struct ScanRun {
+    pub id: i32,
+    /// `None` when every library was scanned
+    pub folder: Option<String>,
+    pub dry_run: bool,
+    pub status: String,
+    pub date_started: NaiveDateTime,
+    pub date_finished: Option<NaiveDateTime>,
+    pub new_photos: i32,
+    pub existing_photos: i32,
+    pub updated_photos: i32,
+    pub deleted_photos: i32,
+    pub hashed_photos: i32,
+    pub duplicate_groups: i32,
+    /// Files that failed validation, see `ScanIssue`
+    pub quarantined_files: i32,
+    pub error: Option<String>,
+}
+
+/// A scan run along with the files it changed. For dry runs these are the planned changes.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanRunDetails {
+    #[serde(flatten)]
+    pub run: ScanRun,
+    pub inserted_files: Vec<String>,
+    pub moved_files: Vec<PlannedMove>,
+    pub updated_files: Vec<String>,
+    pub deleted_files: Vec<String>,
+    pub excluded_files: ExcludedFiles,
+}
+
+impl ScanRunDetails {
+    fn from_row(row: &Row) -> Self {
+        let moved_files: JSON = row.get("moved_files");
+        let excluded_files: JSON = row.get("excluded_files");
+
+        ScanRunDetails {
+            run: ScanRun::from_row_ref(row).unwrap(),
+            inserted_files: row.get("inserted_files"),
+            moved_files: serde_json::from_value(moved_files).unwrap_or_default(),
+            updated_files: row.get("updated_files"),
+            deleted_files: row.get("deleted_files"),
+            excluded_files: serde_json::from_value(excluded_files).unwrap_or_default(),
+        }
+    }
+}
+
+impl ScanRun {
+    /// Returns every scan run, newest first. The file lists are left out, see `get_details`.
+    pub async fn get_all(pool: &Pool) -> DbVecResult<Self> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "select id, folder, dry_run, status, date_started, date_finished, new_photos, \
+                        existing_photos, updated_photos, deleted_photos, hashed_photos, \
+                        duplicate_groups, quarantined_files, error \
+                 from scan_runs \
+                 order by date_started desc, id desc",
+            )
+            .await?;
+        let results = client.query(&stmt, &[]).await?;
+
+        let runs = results
+            .into_iter()
+            .map(|result| ScanRun::from_row(result).unwrap())
+            .collect();
+
+        Ok(runs)
+    }
+
+    pub async fn get_details(id: i32, pool: &Pool) -> DbSingleResult<ScanRunDetails> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare("select * from scan_runs where id = $1")
+            .await?;
+        let result = client.query_opt(&stmt, &[&id]).await?;
+
+        match result {
+            Some(row) => Ok(ScanRunDetails::from_row(&row)),
+            None => Err(ServiceError::NotFound(format!("Scan run {} not found", id))),
+        }
+    }
+
+    /// Records the start of a scan and returns the id of the run
+    pub async fn start(folder: Option<&str>, dry_run: bool, pool: &Pool) -> DbSingleResult<i32> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare("insert into scan_runs (folder, dry_run) values ($1, $2) returning id")
+            .await?;
+        let result = client.query_one(&stmt, &[&folder, &dry_run]).await?;
+
+        Ok(result.get(0))
+    }
+
+    pub async fn complete(id: i32, result: &FileScanResult, pool: &Pool) -> DbSingleResult<()> {
+        let moved_files = serde_json::to_value(&result.plan.would_move).unwrap_or_default();
+        let excluded_files = serde_json::to_value(&result.excluded).unwrap_or_default();
+
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "update scan_runs \
+                 set status = 'completed', date_finished = current_timestamp, \
+                     new_photos = $2, existing_photos = $3, updated_photos = $4, \
+                     deleted_photos = $5, hashed_photos = $6, duplicate_groups = $7, \
+                     inserted_files = $8, moved_files = $9, updated_files = $10, \
+                     deleted_files = $11, excluded_files = $12, quarantined_files = $13 \
+                 where id = $1",
+            )
+            .await?;
+        let _ = client
+            .execute(
+                &stmt,
+                &[
+                    &id,
+                    &result.new_photos_count,
+                    &result.existing_photos_count,
+                    &result.updated_photos_count,
+                    &result.deleted_photos_count,
+                    &result.hashed_photos_count,
+                    &result.duplicate_groups_count,
+                    &result.plan.would_insert,
+                    &moved_files,
+                    &result.plan.would_update,
+                    &result.plan.would_delete,
+                    &excluded_files,
+                    &(result.issues.len() as i32),
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Marks the run as failed, or as cancelled if `error` is `None`
+    pub async fn abort(id: i32, error: Option<&str>, pool: &Pool) -> DbSingleResult<()> {
+        let status = if error.is_some() {
+            "failed"
+        } else {
+            "cancelled"
+        };
+
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "update scan_runs \
+                 set status = $2, error = $3, date_finished = current_timestamp \
+                 where id = $1",
+            )
+            .await?;
+        let _ = client.execute(&stmt, &[&id, &status, &error]).await?;
+
+        Ok(())
+    }
+
+    /// Scans only live in memory while they run, so runs that were still going when the server
+    /// stopped can never finish. Marks them as failed.
+    pub async fn fail_interrupted(pool: &Pool) -> DbSingleResult<u64> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "update scan_runs \
+                 set status = 'failed', error = 'Interrupted by a server restart', \
+                     date_finished = current_timestamp \
+                 where status = 'running'",
+            )
+            .await?;
+        let count = client.execute(&stmt, &[]).await?;
+
+        Ok(count)
+    }
+}
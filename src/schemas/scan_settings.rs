@@ -0,0 +1,97 @@
+use deadpool_postgres::Pool;
+use serde::{Deserialize, Serialize};
+use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_pg_mapper_derive::PostgresMapper;
+
+use crate::errors::ServiceError;
+use crate::files::scan_rules::ScanRules;
+use crate::types::DbSingleResult;
+
+// SCAN SETTINGS ***********************************************************************************
+
+/// Decides which files a scan picks up, see `ScanRules`
+#[derive(Serialize, Deserialize, Debug, Clone, PostgresMapper)]
+#[serde(rename_all = "camelCase")]
+#[pg_mapper(table = "scan_settings")]
+pub struct ScanSettings {
+    /// File extensions to scan, without the leading dot
+    pub extensions: Vec<String>,
+    /// Glob patterns a file has to match to be scanned. An empty list includes every file.
+    pub include_patterns: Vec<String>,
+    /// Glob patterns of files to leave out
+    pub exclude_patterns: Vec<String>,
+}
+
+impl ScanSettings {
+    pub async fn get(pool: &Pool) -> DbSingleResult<Self> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare("select * from scan_settings where id = 1")
+            .await?;
+        let result = client.query_one(&stmt, &[]).await?;
+
+        let settings = ScanSettings::from_row(result).unwrap();
+
+        Ok(settings)
+    }
+
+    pub async fn update(settings: ScanSettings, pool: &Pool) -> DbSingleResult<Self> {
+        let settings = settings.normalize()?;
+
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "update scan_settings \
+                 set extensions = $1, include_patterns = $2, exclude_patterns = $3 \
+                 where id = 1",
+            )
+            .await?;
+        let _ = client
+            .execute(
+                &stmt,
+                &[
+                    &settings.extensions,
+                    &settings.include_patterns,
+                    &settings.exclude_patterns,
+                ],
+            )
+            .await?;
+
+        let result = ScanSettings::get(pool).await?;
+
+        Ok(result)
+    }
+
+    /// Lowercases extensions, drops blank entries and makes sure every pattern is a valid glob
+    fn normalize(self) -> Result<Self, ServiceError> {
+        fn clean(values: Vec<String>) -> Vec<String> {
+            values
+                .into_iter()
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+                .collect()
+        }
+
+        let extensions: Vec<String> = clean(self.extensions)
+            .into_iter()
+            .map(|extension| extension.trim_start_matches('.').to_lowercase())
+            .collect();
+
+        if extensions.is_empty() {
+            return Err(ServiceError::BadRequest(
+                "At least one file extension has to be scanned".to_string(),
+            ));
+        }
+
+        let settings = ScanSettings {
+            extensions,
+            include_patterns: clean(self.include_patterns),
+            exclude_patterns: clean(self.exclude_patterns),
+        };
+
+        // building the rules compiles every pattern
+        ScanRules::new(&settings)?;
+
+        Ok(settings)
+    }
+}
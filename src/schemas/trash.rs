@@ -0,0 +1,241 @@
+use chrono::NaiveDateTime;
+use deadpool_postgres::{Pool, Transaction};
+use serde::{Deserialize, Serialize};
+use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_pg_mapper_derive::PostgresMapper;
+
+use crate::errors::ServiceError;
+use crate::files::trash;
+use crate::schemas::libraries::Library;
+use crate::schemas::photo::Photo;
+use crate::schemas::photo_full::PhotoFull;
+use crate::types::{DbSingleResult, DbVecResult};
+
+// TRASHED PHOTO ***********************************************************************************
+
+/// A deleted photo whose file waits in the trash directory until it is restored or purged. The row
+/// keeps its tags, entities and wallpapers the whole time.
+#[derive(Serialize, Deserialize, Debug, Clone, PostgresMapper)]
+#[serde(rename_all = "camelCase")]
+#[pg_mapper(table = "photos")]
+pub struct TrashedPhoto {
+    pub id: i32,
+    /// Location of the file inside the trash directory
+    pub file_path: String,
+    /// Path the file is restored to
+    pub trashed_from: String,
+    pub file_name: String,
+    pub file_hash: String,
+    pub rating: i32,
+    pub date_trashed: NaiveDateTime,
+    /// When the photo is purged for good, `None` if the trash is only ever emptied by hand
+    pub date_purged: Option<NaiveDateTime>,
+}
+
+impl TrashedPhoto {
+    /// Returns every photo in the trash, most recently trashed first
+    pub async fn get_all(pool: &Pool) -> DbVecResult<Self> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "select id, file_path, trashed_from, file_name, file_hash, rating, date_trashed, \
+                        date_trashed + make_interval(days => $1) as date_purged \
+                 from photos \
+                 where date_trashed is not null \
+                 order by date_trashed desc, id desc",
+            )
+            .await?;
+        let results = client.query(&stmt, &[&trash::retention_days()]).await?;
+
+        let photos = results
+            .into_iter()
+            .map(|result| TrashedPhoto::from_row(result).unwrap())
+            .collect();
+
+        Ok(photos)
+    }
+
+    pub async fn get(id: i32, pool: &Pool) -> DbSingleResult<Self> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "select id, file_path, trashed_from, file_name, file_hash, rating, date_trashed, \
+                        date_trashed + make_interval(days => $2) as date_purged \
+                 from photos \
+                 where id = $1 and date_trashed is not null",
+            )
+            .await?;
+        let result = client
+            .query_opt(&stmt, &[&id, &trash::retention_days()])
+            .await?;
+
+        match result {
+            Some(row) => Ok(TrashedPhoto::from_row(row).unwrap()),
+            None => Err(ServiceError::NotFound(format!(
+                "Photo {} is not in the trash",
+                id
+            ))),
+        }
+    }
+
+    /// Moves the photo's file into the trash directory and marks the photo as trashed
+    pub async fn trash(photo_id: i32, pool: &Pool) -> DbSingleResult<Self> {
+        let photo = Photo::get_by_id(photo_id, pool).await?;
+
+        if photo.date_trashed.is_some() {
+            return Err(ServiceError::BadRequest(format!(
+                "Photo {} is already in the trash",
+                photo_id
+            )));
+        }
+
+        let library_root = Library::get_root_of(&photo.file_path, pool).await?;
+
+        // everything that can fail on the database side is done before the file is moved
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "update photos \
+                 set file_path = $2, trashed_from = file_path, date_trashed = current_timestamp \
+                 where id = $1",
+            )
+            .await?;
+
+        let trash_path = trash::move_to_trash(&photo.file_path, &library_root)?;
+        let trash_path = trash_path.to_string_lossy().to_string();
+
+        // put the file back rather than leave the row pointing at a path that no longer exists
+        if let Err(err) = client.execute(&stmt, &[&photo_id, &trash_path]).await {
+            trash::restore_from_trash(&trash_path, &photo.file_path)?;
+            return Err(err.into());
+        }
+
+        TrashedPhoto::get(photo_id, pool).await
+    }
+
+    /// Moves the files of the photos into the trash, returning the id, original path and trash path
+    /// of each. If a file cannot be moved, the files moved so far are put back first.
+    pub async fn move_files(
+        photos: &[&Photo],
+        pool: &Pool,
+    ) -> Result<Vec<(i32, String, String)>, ServiceError> {
+        let libraries = Library::get_all(pool).await?;
+
+        let mut moved = Vec::new();
+        for photo in photos {
+            let library_root = Library::root_of(&libraries, &photo.file_path);
+
+            match trash::move_to_trash(&photo.file_path, &library_root) {
+                Ok(trash_path) => moved.push((
+                    photo.id,
+                    photo.file_path.to_owned(),
+                    trash_path.to_string_lossy().to_string(),
+                )),
+                Err(err) => {
+                    TrashedPhoto::put_back(&moved);
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(moved)
+    }
+
+    /// Moves files moved by `move_files` back to where they came from, when the photos cannot be
+    /// marked as trashed
+    pub fn put_back(moved: &[(i32, String, String)]) {
+        for (_, original_path, trash_path) in moved {
+            if let Err(err) = trash::restore_from_trash(trash_path, original_path) {
+                println!(
+                    "Unable to move {} back to {}: {}",
+                    trash_path, original_path, err
+                );
+            }
+        }
+    }
+
+    /// Marks the photos whose files were moved by `move_files` as trashed
+    pub async fn mark_trashed(
+        tx: &Transaction<'_>,
+        moved: &[(i32, String, String)],
+    ) -> DbSingleResult<()> {
+        let ids: Vec<i32> = moved.iter().map(|(id, _, _)| *id).collect();
+        let trash_paths: Vec<&str> = moved.iter().map(|(_, _, path)| path.as_str()).collect();
+
+        let stmt = tx
+            .prepare(
+                "update photos p \
+                 set file_path = m.trash_path, trashed_from = p.file_path, \
+                     date_trashed = current_timestamp \
+                 from unnest($1::int[], $2::text[]) as m (id, trash_path) \
+                 where p.id = m.id",
+            )
+            .await?;
+        let _ = tx.execute(&stmt, &[&ids, &trash_paths]).await?;
+
+        Ok(())
+    }
+
+    /// Moves the file back to where it was trashed from and makes the photo visible again
+    pub async fn restore(id: i32, pool: &Pool) -> DbSingleResult<PhotoFull> {
+        let trashed = TrashedPhoto::get(id, pool).await?;
+
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "update photos \
+                 set file_path = trashed_from, trashed_from = null, date_trashed = null \
+                 where id = $1",
+            )
+            .await?;
+
+        trash::restore_from_trash(&trashed.file_path, &trashed.trashed_from)?;
+
+        // move the file back into the trash, where the row still points
+        if let Err(err) = client.execute(&stmt, &[&id]).await {
+            trash::return_to_trash(&trashed.trashed_from, &trashed.file_path)?;
+            return Err(err.into());
+        }
+
+        PhotoFull::get_by_id(id, pool).await
+    }
+
+    /// Permanently deletes every photo in the trash. Returns the number of photos deleted.
+    pub async fn empty(pool: &Pool) -> DbSingleResult<usize> {
+        TrashedPhoto::purge(None, pool).await
+    }
+
+    /// Permanently deletes photos that have been in the trash for longer than `days`. Returns the
+    /// number of photos deleted.
+    pub async fn purge_expired(days: i32, pool: &Pool) -> DbSingleResult<usize> {
+        TrashedPhoto::purge(Some(days), pool).await
+    }
+
+    async fn purge(older_than_days: Option<i32>, pool: &Pool) -> DbSingleResult<usize> {
+        let client = pool.get().await?;
+        let stmt = client
+            .prepare(
+                "select id, file_path from photos \
+                 where date_trashed is not null \
+                   and ($1::int is null or date_trashed < now() - make_interval(days => $1))",
+            )
+            .await?;
+        let results = client.query(&stmt, &[&older_than_days]).await?;
+
+        // only rows whose file is gone are deleted, so a file that cannot be removed is purged
+        // again next time
+        let mut purged: Vec<i32> = Vec::new();
+        for row in results {
+            let file_path: String = row.get("file_path");
+
+            match trash::remove_from_trash(&file_path) {
+                Ok(_) => purged.push(row.get("id")),
+                Err(err) => println!("Unable to purge {}: {}", file_path, err),
+            }
+        }
+
+        Photo::delete_photos(&purged, pool).await?;
+
+        Ok(purged.len())
+    }
+}
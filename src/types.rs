@@ -23,7 +23,7 @@ pub type PaginatedPhotoResponse = ApiResponse<PaginatedPhotos>;
 
 // FILES *******************************************************************************************
 
-pub type FileCollectionResult = Result<(Vec<FileInfo>, i32), ServiceError>;
+pub type FileCollectionResult = Result<Vec<FileInfo>, ServiceError>;
 
 // PAGINATION **************************************************************************************
 